@@ -0,0 +1,295 @@
+use std::f32::consts::FRAC_PI_2;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::source::{SeekError, UniformSourceIterator};
+use rodio::Source;
+
+pub type BoxedSource = Box<dyn Source<Item = f32> + Send>;
+
+// Where the player puts the opening of the next track once it knows what that
+// is, for the crossfade to pick up when the current track reaches its end.
+pub type NextSlot = Arc<Mutex<Option<BoxedSource>>>;
+
+// How often, in samples, an empty slot is checked once the fade is due.
+const SLOT_CHECK_INTERVAL: u64 = 1024;
+
+// Plays a track, mixing the opening of the next one over its last `fade`
+// seconds with equal-power gain curves. The crossfade ends together with the
+// opening it was given, so the rest of the next track plays as its own source
+// straight after. If the track ends before the fade is due, the opening is
+// played on its own instead so none of the next track is lost.
+pub struct Crossfade<S> {
+    current: S,
+    next: NextSlot,
+    fading: Option<UniformSourceIterator<BoxedSource, f32>>,
+    // Where the source starts in the track, for sources that skip an opening
+    // that was already played by the previous crossfade.
+    start: Duration,
+    samples_per_second: u64,
+    // The sample the fade starts at, counted from `start`.
+    fade_start: u64,
+    fade_len: u64,
+    position: u64,
+    faded: u64,
+}
+
+impl<S> Crossfade<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(
+        current: S,
+        start: Duration,
+        duration: Duration,
+        fade: Duration,
+        next: NextSlot,
+    ) -> Self {
+        let samples_per_second = current.sample_rate() as u64 * current.channels() as u64;
+        let to_samples =
+            |duration: Duration| (duration.as_secs_f64() * samples_per_second as f64) as u64;
+
+        // The length the decoder gives is preferred over the stored one, which
+        // is only in whole seconds.
+        let remaining = current
+            .total_duration()
+            .unwrap_or_else(|| duration.saturating_sub(start));
+
+        let fade_len = to_samples(fade);
+        let fade_start = if fade.is_zero() {
+            u64::MAX
+        } else {
+            to_samples(remaining).saturating_sub(fade_len)
+        };
+
+        Self {
+            current,
+            next,
+            fading: None,
+            start,
+            samples_per_second,
+            fade_start,
+            fade_len: fade_len.max(1),
+            position: 0,
+            faded: 0,
+        }
+    }
+
+    fn take_next(&mut self) {
+        let Ok(mut next) = self.next.try_lock() else {
+            return;
+        };
+
+        if let Some(next) = next.take() {
+            self.fading = Some(UniformSourceIterator::new(
+                next,
+                self.current.channels(),
+                self.current.sample_rate(),
+            ));
+        }
+    }
+}
+
+impl<S> Iterator for Crossfade<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.fading.is_none()
+            && self.position >= self.fade_start
+            && (self.position - self.fade_start) % SLOT_CHECK_INTERVAL == 0
+        {
+            self.take_next();
+        }
+
+        if self.fading.is_none() {
+            if let Some(sample) = self.current.next() {
+                self.position += 1;
+
+                return Some(sample);
+            }
+
+            // The track ended before the fade began, so the opening is played
+            // at full volume.
+            self.take_next();
+            self.faded = self.fade_len;
+        }
+
+        // Once the opening of the next track runs out the fade is complete,
+        // and whatever is left of the current track is dropped.
+        let incoming = self.fading.as_mut()?.next()?;
+        let outgoing = self.current.next().unwrap_or(0.0);
+
+        let (outgoing_gain, incoming_gain) = gains(self.faded as f32 / self.fade_len as f32);
+
+        self.position += 1;
+        self.faded += 1;
+
+        Some(outgoing * outgoing_gain + incoming * incoming_gain)
+    }
+}
+
+// The gains of the outgoing and incoming tracks `progress` of the way through
+// a fade, whose squares always add up to one so the loudness holds steady.
+fn gains(progress: f32) -> (f32, f32) {
+    let angle = progress.clamp(0.0, 1.0) * FRAC_PI_2;
+
+    (angle.cos(), angle.sin())
+}
+
+impl<S> Source for Crossfade<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        match self.fading {
+            Some(_) => None,
+            None => self.current.current_frame_len(),
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.current.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.current.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    // Seeking abandons a fade in progress; the player queues the next track
+    // again afterwards.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.current.try_seek(pos)?;
+
+        self.fading = None;
+        self.faded = 0;
+        self.position =
+            (pos.saturating_sub(self.start).as_secs_f64() * self.samples_per_second as f64) as u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 1000;
+
+    // A source that doesn't know its length, like some decoders.
+    struct Unsized(SamplesBuffer<f32>);
+
+    impl Iterator for Unsized {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            self.0.next()
+        }
+    }
+
+    impl Source for Unsized {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            RATE
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    fn constant(value: f32, samples: usize) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(1, RATE, vec![value; samples])
+    }
+
+    fn slot_with(opening: SamplesBuffer<f32>) -> NextSlot {
+        Arc::new(Mutex::new(Some(Box::new(opening) as BoxedSource)))
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn gains_follow_equal_power_curves() {
+        let (outgoing, incoming) = gains(0.0);
+        assert_close(outgoing, 1.0);
+        assert_close(incoming, 0.0);
+
+        let (outgoing, incoming) = gains(0.5);
+        assert_close(outgoing, 0.5f32.sqrt());
+        assert_close(incoming, 0.5f32.sqrt());
+
+        let (outgoing, incoming) = gains(1.0);
+        assert_close(outgoing, 0.0);
+        assert_close(incoming, 1.0);
+    }
+
+    #[test]
+    fn gains_keep_summed_power_constant() {
+        for step in 0..=100 {
+            let (outgoing, incoming) = gains(step as f32 / 100.0);
+
+            assert_close(outgoing.powi(2) + incoming.powi(2), 1.0);
+        }
+    }
+
+    #[test]
+    fn fades_over_the_end_of_the_decoded_track() {
+        // The stored duration is a second longer than the audio, which is what
+        // rounding to whole seconds can do.
+        let samples: Vec<f32> = Crossfade::new(
+            constant(1.0, 1000),
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_millis(200),
+            slot_with(constant(0.5, 200)),
+        )
+        .collect();
+
+        assert_eq!(samples.len(), 1000);
+        assert_close(samples[799], 1.0);
+        assert_close(samples[800], 1.0);
+        assert_close(samples[900], 0.5f32.sqrt() + 0.5 * 0.5f32.sqrt());
+        assert!((samples[999] - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn plays_the_opening_if_the_track_ends_before_the_fade() {
+        let samples: Vec<f32> = Crossfade::new(
+            Unsized(constant(1.0, 1000)),
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_millis(200),
+            slot_with(constant(0.5, 200)),
+        )
+        .collect();
+
+        assert_eq!(samples.len(), 1200);
+        assert!(samples[..1000].iter().all(|&sample| sample == 1.0));
+
+        for &sample in &samples[1000..] {
+            assert_close(sample, 0.5);
+        }
+    }
+}
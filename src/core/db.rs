@@ -1,206 +1,370 @@
-use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use super::envelope;
+use super::migrations;
+use super::tags::{TagEdit, TrackTags};
 
 use log;
-use rusqlite::{Connection, Error as RusqliteError};
+use rusqlite::{Connection, Error as RusqliteError, OptionalExtension, Row};
+use tokio::sync::oneshot;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DatabaseError {
-    SqliteError,
+    SqliteError(String),
+    MalformedRow(String),
+    NotFound,
+    IoError(String),
+    UnsupportedVersion(u32),
+    Disconnected,
 }
 
 impl From<RusqliteError> for DatabaseError {
-    fn from(_: RusqliteError) -> Self {
-        DatabaseError::SqliteError
+    fn from(error: RusqliteError) -> Self {
+        match error {
+            RusqliteError::QueryReturnedNoRows => DatabaseError::NotFound,
+            RusqliteError::InvalidColumnType(..)
+            | RusqliteError::IntegralValueOutOfRange(..)
+            | RusqliteError::FromSqlConversionFailure(..) => {
+                DatabaseError::MalformedRow(error.to_string())
+            }
+            _ => DatabaseError::SqliteError(error.to_string()),
+        }
     }
 }
 
-#[derive(Debug)]
-struct Music {
-    music_id: i32,
-    video_id: String,
-    extension: String,
-    duration: i32,
-    display_name: String,
+impl From<std::io::Error> for DatabaseError {
+    fn from(error: std::io::Error) -> Self {
+        DatabaseError::IoError(error.to_string())
+    }
 }
 
-#[derive(Debug)]
-struct Playlist {
-    playlist_id: i32,
-    name: String,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MusicId(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlaylistId(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BookmarkId(pub i32);
+
+// A single audio track stored in the `music` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    pub music_id: MusicId,
+    pub video_id: String,
+    pub extension: String,
+    pub duration: Duration,
+    pub display_name: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    // Integrated loudness in LUFS and true peak as a linear amplitude, filled
+    // in by the loudness analyser. The album values are shared by every track
+    // with the same album and album artist.
+    pub loudness: Option<f64>,
+    pub true_peak: Option<f64>,
+    pub album_loudness: Option<f64>,
+    pub album_peak: Option<f64>,
+    // The speed the track was last played at, if it was changed.
+    pub speed: Option<f32>,
 }
 
-#[derive(Debug)]
-struct MusicPlaylist {
-    music_playlist_id: i32,
-    music_id: i32,
-    playlist_id: i32,
-}
-
-// Pretty self-explanatory. Checks if the database file exists.
-pub fn check_database_exists() -> bool {
-    Path::new("./data/data.db").exists()
-}
-
-// Creates the database tables. Called on startup if the database doesn't already exist.
-pub fn create_database_tables() -> Result<(), DatabaseError> {
-    log::info!("Creating database tables.");
-    let conn = Connection::open("./data/data.db")?;
-
-    conn.execute(
-        "CREATE TABLE music (
-            music_id INTEGER PRIMARY KEY NOT NULL,
-            video_id TEXT NOT NULL,
-            extension TEXT NOT NULL,
-            duration INTEGER,
-            display_name TEXT
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE TABLE playlists (
-            playlist_id INTEGER PRIMARY KEY NOT NULL,
-            name TEXT NOT NULL
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE TABLE music_playlists (
-            music_playlists INTEGER PRIMARY KEY NOT NULL,
-            music_id INTEGER REFERENCES music(music_id),
-            playlist_id INTEGER REFERENCES playlists(playlist_id)
-        )",
-        [],
-    )?;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist {
+    pub playlist_id: PlaylistId,
+    pub name: String,
+}
 
-    Ok(())
+// A named position in a track, stored in the `bookmarks` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub bookmark_id: BookmarkId,
+    pub music_id: MusicId,
+    pub name: String,
+    pub position: Duration,
 }
 
-// Adds a track to the `music` table in the databsae. This is called when
-// downloading/importing new audio tracks.
-pub fn add_music(video_data: HashMap<String, String>) -> Result<(), DatabaseError> {
-    log::info!("Adding music to database.");
-    let conn = Connection::open("./data/data.db")?;
+// A row of the `music_playlists` table, linking a track to a playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistEntry {
+    pub entry_id: i32,
+    pub music_id: MusicId,
+    pub playlist_id: PlaylistId,
+}
 
-    let video_id = video_data.get("video_id").unwrap();
-    let extension = video_data.get("format_type").unwrap();
-    let display_name = video_data.get("display_name").unwrap();
-    let duration = video_data.get("duration").unwrap();
+const TRACK_COLUMNS: &str = "music_id, video_id, extension, duration, display_name, artist, \
+    album, album_artist, track_number, disc_number, year, genre, loudness, true_peak, \
+    album_loudness, album_peak, speed";
+
+impl Track {
+    // Expects the columns in the order of `TRACK_COLUMNS`. A NULL or negative
+    // duration is reported as an error rather than silently defaulted.
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let duration: u64 = row.get(3)?;
+
+        Ok(Self {
+            music_id: MusicId(row.get(0)?),
+            video_id: row.get(1)?,
+            extension: row.get(2)?,
+            duration: Duration::from_secs(duration),
+            display_name: row.get(4)?,
+            artist: row.get(5)?,
+            album: row.get(6)?,
+            album_artist: row.get(7)?,
+            track_number: row.get(8)?,
+            disc_number: row.get(9)?,
+            year: row.get(10)?,
+            genre: row.get(11)?,
+            loudness: row.get(12)?,
+            true_peak: row.get(13)?,
+            album_loudness: row.get(14)?,
+            album_peak: row.get(15)?,
+            speed: row.get(16)?,
+        })
+    }
 
-    conn.execute(
-        "INSERT INTO music (video_id, extension, display_name, duration)
-        VALUES (?1, ?2, ?3, ?4)",
-        [&video_id, &extension, &display_name, &duration],
-    )?;
+    // Where the track's audio file is stored.
+    pub fn audio_path(&self) -> PathBuf {
+        PathBuf::from(format!("./data/audio/{}.{}", self.video_id, self.extension))
+    }
+}
 
-    Ok(())
+impl Playlist {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            playlist_id: PlaylistId(row.get(0)?),
+            name: row.get(1)?,
+        })
+    }
 }
 
-pub fn get_music_from_id(id: i32) -> Result<HashMap<String, String>, DatabaseError> {
-    log::info!("Requesting music data from ID.");
+impl PlaylistEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            entry_id: row.get(0)?,
+            music_id: MusicId(row.get(1)?),
+            playlist_id: PlaylistId(row.get(2)?),
+        })
+    }
+}
 
-    let conn = Connection::open("./data/data.db")?;
+impl Bookmark {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let position: u64 = row.get(3)?;
 
-    let mut statement = conn
-        .prepare("SELECT * FROM music WHERE music_id = ?1")
-        .unwrap();
-    let music = statement
-        .query_row([id], |row| {
-            Ok(Music {
-                music_id: row.get(0)?,
-                video_id: row.get(1)?,
-                extension: row.get(2)?,
-                duration: row.get(3)?,
-                display_name: row.get(4)?,
-            })
+        Ok(Self {
+            bookmark_id: BookmarkId(row.get(0)?),
+            music_id: MusicId(row.get(1)?),
+            name: row.get(2)?,
+            position: Duration::from_secs(position),
         })
-        .unwrap();
+    }
+}
 
-    let mut music_map = HashMap::new();
-    music_map.insert("music_id".to_string(), music.music_id.to_string());
-    music_map.insert("video_id".to_string(), music.video_id);
-    music_map.insert("extension".to_string(), music.extension);
-    music_map.insert("duration".to_string(), music.duration.to_string());
-    music_map.insert("display_name".to_string(), music.display_name);
+type Job = Box<dyn FnOnce(&mut Connection) + Send>;
 
-    log::info!("Music data received.");
+const DATABASE_PATH: &str = "./data/data.db";
 
-    Ok(music_map)
-}
+static SENDER: OnceLock<mpsc::Sender<Job>> = OnceLock::new();
 
-pub fn add_playlist(name: String) -> Result<(), DatabaseError> {
-    log::info!("Adding playlist to database.");
-    let conn = Connection::open("./data/data.db")?;
+// Opens the database and hands the connection to a background thread that
+// owns it for the lifetime of the app. Pending schema migrations are applied
+// first, and an integrity check is queued so that it runs before any request
+// made by the UI. Called once on startup before anything else touches the
+// database.
+pub fn start_receiver() -> Result<(), DatabaseError> {
+    log::info!("Checking database schema.");
+    migrations::run(Path::new(DATABASE_PATH))?;
+
+    let mut conn = Connection::open(DATABASE_PATH)?;
 
-    conn.execute("INSERT INTO playlists (name) VALUES (?1)", [name])?;
+    let journal_mode: String =
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    log::info!("Database journal mode: {}", journal_mode);
+
+    conn.set_prepared_statement_cache_capacity(32);
+
+    let (sender, receiver) = mpsc::channel::<Job>();
+
+    sender
+        .send(Box::new(|conn| {
+            if let Err(e) = verify_data_integrity(conn) {
+                log::error!("Failed to verify database integrity: {:?}", e);
+            }
+        }))
+        .map_err(|_| DatabaseError::Disconnected)?;
+
+    SENDER
+        .set(sender)
+        .map_err(|_| DatabaseError::SqliteError("Database already started".to_string()))?;
+
+    thread::spawn(move || {
+        for job in receiver {
+            job(&mut conn);
+        }
+    });
 
     Ok(())
 }
 
-pub fn get_all_playlists() -> Vec<HashMap<String, String>> {
-    log::info!("Requesting all playlists.");
+// Runs `query` on the database thread and resolves once it has completed.
+async fn call<T, F>(query: F) -> Result<T, DatabaseError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Connection) -> Result<T, DatabaseError> + Send + 'static,
+{
+    let (response_sender, response) = oneshot::channel();
+
+    SENDER
+        .get()
+        .ok_or(DatabaseError::Disconnected)?
+        .send(Box::new(move |conn| {
+            let _ = response_sender.send(query(conn));
+        }))
+        .map_err(|_| DatabaseError::Disconnected)?;
+
+    response.await.map_err(|_| DatabaseError::Disconnected)?
+}
 
-    let conn = Connection::open("./data/data.db").unwrap();
+// Adds a track to the `music` table in the databsae. This is called when
+// downloading/importing new audio tracks. A track already in the library keeps
+// its row, and its id is returned.
+pub async fn add_music(
+    video_id: String,
+    extension: String,
+    display_name: String,
+    duration: Duration,
+    tags: TrackTags,
+) -> Result<MusicId, DatabaseError> {
+    log::info!("Adding music to database.");
 
-    let mut statement = conn.prepare("SELECT * FROM playlists").unwrap();
+    call(move |conn| {
+        let existing: Option<i32> = conn
+            .prepare_cached("SELECT music_id FROM music WHERE video_id = ?1")?
+            .query_row([&video_id], |row| row.get(0))
+            .optional()?;
 
-    let playlist_iter = statement
-        .query_map([], |row| {
-            Ok(Playlist {
-                playlist_id: row.get(0)?,
-                name: row.get(1)?,
-            })
-        })
-        .unwrap();
+        if let Some(music_id) = existing {
+            log::info!("{} is already in the library.", video_id);
 
-    let mut playlist_data = Vec::new();
+            return Ok(MusicId(music_id));
+        }
 
-    for playlist_item in playlist_iter {
-        let playlist_item = playlist_item.unwrap();
-        let mut playlist_map = HashMap::new();
-        playlist_map.insert("id".to_string(), playlist_item.playlist_id.to_string());
-        playlist_map.insert("name".to_string(), playlist_item.name);
-        playlist_data.push(playlist_map);
-    }
+        conn.prepare_cached(
+            "INSERT INTO music (video_id, extension, display_name, duration, artist, album,
+                album_artist, track_number, disc_number, year, genre)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?
+        .execute((
+            video_id,
+            extension,
+            display_name,
+            duration.as_secs(),
+            tags.artist,
+            tags.album,
+            tags.album_artist,
+            tags.track_number,
+            tags.disc_number,
+            tags.year,
+            tags.genre,
+        ))?;
+
+        Ok(MusicId(conn.last_insert_rowid() as i32))
+    })
+    .await
+}
+
+// Whether a track with `video_id` is already in the library.
+pub async fn has_music(video_id: String) -> Result<bool, DatabaseError> {
+    call(move |conn| {
+        Ok(conn
+            .prepare_cached("SELECT 1 FROM music WHERE video_id = ?1")?
+            .exists([video_id])?)
+    })
+    .await
+}
+
+pub async fn get_music_from_id(id: MusicId) -> Result<Track, DatabaseError> {
+    log::info!("Requesting music data from ID.");
+
+    call(move |conn| {
+        let music = conn
+            .prepare_cached(&format!(
+                "SELECT {} FROM music WHERE music_id = ?1",
+                TRACK_COLUMNS
+            ))?
+            .query_row([id.0], Track::from_row)?;
+
+        log::info!("Music data received.");
+
+        Ok(music)
+    })
+    .await
+}
+
+pub async fn add_playlist(name: String) -> Result<PlaylistId, DatabaseError> {
+    log::info!("Adding playlist to database.");
+
+    call(move |conn| {
+        conn.prepare_cached("INSERT INTO playlists (name) VALUES (?1)")?
+            .execute([name])?;
+
+        Ok(PlaylistId(conn.last_insert_rowid() as i32))
+    })
+    .await
+}
+
+pub async fn get_all_playlists() -> Result<Vec<Playlist>, DatabaseError> {
+    log::info!("Requesting all playlists.");
+
+    call(|conn| {
+        let playlists = conn
+            .prepare_cached("SELECT playlist_id, name FROM playlists")?
+            .query_map([], Playlist::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
 
-    log::info!("Playlists received.");
+        log::info!("Playlists received.");
 
-    playlist_data
+        Ok(playlists)
+    })
+    .await
 }
 
 // Verifies the integrity of the audio tracks in the database by comparing all
 // tracks in the database to the audio files.
 // If the audio track is in the database but the corresponding audio track does NOT
 // exist, it will be deleted from the database.
-// This is called on app startup and is not checked again.
-pub fn verify_data_integrity() -> Result<(), DatabaseError> {
+// This is queued when the database is opened and is not checked again.
+fn verify_data_integrity(conn: &mut Connection) -> Result<(), DatabaseError> {
     log::info!("Verifying database integrity.");
-    let conn = Connection::open("./data/data.db")?;
 
-    let mut statement = conn.prepare("SELECT * FROM music").unwrap();
-    let music_iter = statement
+    let mut statement = conn.prepare("SELECT music_id, video_id, extension FROM music")?;
+    let music = statement
         .query_map([], |row| {
-            Ok(Music {
-                music_id: row.get(0)?,
-                video_id: row.get(1)?,
-                extension: row.get(2)?,
-                duration: row.get(3)?,
-                display_name: row.get(4)?,
-            })
-        })
-        .unwrap();
-
-    for music in music_iter {
-        let music = music.unwrap();
-        let path_str = format!("./data/audio/{}.{}", music.video_id, music.extension);
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (music_id, video_id, extension) in music {
+        let path_str = format!("./data/audio/{}.{}", video_id, extension);
         let path = Path::new(&path_str);
 
         if !path.exists() {
             log::info!("Found entry which doesn't exist. Deleting from database.");
 
-            conn.execute("DELETE FROM music WHERE music_id = ?1", [music.music_id])?;
+            conn.execute("DELETE FROM bookmarks WHERE music_id = ?1", [music_id])?;
+            conn.execute("DELETE FROM music WHERE music_id = ?1", [music_id])?;
         }
     }
 
@@ -211,129 +375,361 @@ pub fn verify_data_integrity() -> Result<(), DatabaseError> {
 
 // Gets all audio tracks from the database. This is called to be displayed on
 // the `track_list` for displaying all songs.
-pub fn get_all_music() -> Vec<HashMap<String, String>> {
+pub async fn get_all_music() -> Result<Vec<Track>, DatabaseError> {
     log::info!("Requesting all music data.");
 
-    let conn = Connection::open("./data/data.db").unwrap();
+    call(|conn| {
+        let music = conn
+            .prepare_cached(&format!("SELECT {} FROM music", TRACK_COLUMNS))?
+            .query_map([], Track::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
 
-    let mut statement = conn.prepare("SELECT * FROM music").unwrap();
-    let music_iter = statement
-        .query_map([], |row| {
-            Ok(Music {
-                music_id: row.get(0)?,
-                video_id: row.get(1)?,
-                extension: row.get(2)?,
-                duration: row.get(3)?,
-                display_name: row.get(4)?,
-            })
-        })
-        .unwrap();
+        log::info!("Music data received.");
 
-    let mut music_data = Vec::new();
-
-    for music_item in music_iter {
-        let music_item = music_item.unwrap();
-        let mut music_map = HashMap::new();
-        music_map.insert("music_id".to_string(), music_item.music_id.to_string());
-        music_map.insert("video_id".to_string(), music_item.video_id);
-        music_map.insert("extension".to_string(), music_item.extension);
-        music_map.insert("duration".to_string(), music_item.duration.to_string());
-        music_map.insert("display_name".to_string(), music_item.display_name);
-        music_data.push(music_map);
-    }
+        Ok(music)
+    })
+    .await
+}
+
+pub async fn delete_music(video_id: String) -> Result<(), DatabaseError> {
+    log::info!("Deleting track from database.");
+
+    call(move |conn| {
+        let extension: String = conn
+            .prepare_cached("SELECT extension FROM music WHERE video_id = ?1")?
+            .query_row([&video_id], |row| row.get(0))?;
 
-    log::info!("Music data received.");
+        conn.prepare_cached(
+            "DELETE FROM bookmarks WHERE music_id IN
+            (SELECT music_id FROM music WHERE video_id = ?1)",
+        )?
+        .execute([&video_id])?;
 
-    music_data
+        conn.prepare_cached("DELETE FROM music WHERE video_id = ?1")?
+            .execute([&video_id])?;
+
+        let path_str = format!("./data/audio/{}.{}", video_id, extension);
+        let path = Path::new(&path_str);
+        std::fs::remove_file(path)?;
+
+        envelope::remove(&video_id);
+
+        Ok(())
+    })
+    .await
 }
 
-pub fn delete_music(video_id: String) -> Result<(), DatabaseError> {
-    log::info!("Deleting track from database.");
+// Swaps an edited audio file into place for `update_metadata`. `replace` runs
+// inside the transaction, and `restore` puts the original file back if the
+// transaction then fails to commit.
+pub struct FileReplacement {
+    pub replace: Box<dyn FnOnce() -> std::io::Result<()> + Send>,
+    pub restore: Box<dyn FnOnce() -> std::io::Result<()> + Send>,
+}
 
-    let conn = Connection::open("./data/data.db")?;
+// Stores an edit made in the track list. The file is replaced after the row is
+// updated; if that fails the update is rolled back, and if the commit fails the
+// file is restored.
+pub async fn update_metadata(
+    music_id: MusicId,
+    edit: TagEdit,
+    file: FileReplacement,
+) -> Result<(), DatabaseError> {
+    log::info!("Editing metadata for track {}.", music_id.0);
+
+    call(move |conn| {
+        let transaction = conn.transaction()?;
+
+        transaction
+            .prepare_cached(
+                "UPDATE music SET display_name = ?1, artist = ?2, album = ?3, year = ?4, genre = ?5
+                WHERE music_id = ?6",
+            )?
+            .execute((
+                edit.title,
+                edit.artist,
+                edit.album,
+                edit.year,
+                edit.genre,
+                music_id.0,
+            ))?;
+
+        (file.replace)()?;
+
+        if let Err(e) = transaction.commit() {
+            if let Err(restore_error) = (file.restore)() {
+                log::error!("Failed to restore the original file: {}", restore_error);
+            }
+
+            return Err(e.into());
+        }
 
-    conn.execute("DELETE FROM music WHERE video_id = ?1", [&video_id])?;
+        Ok(())
+    })
+    .await
+}
 
-    let path_str = format!("./data/audio/{}.{}", video_id, "mp3");
-    let path = Path::new(&path_str);
-    std::fs::remove_file(path).unwrap();
+// Replaces the stored tags of a track. The display name is left alone since
+// the user may have changed it.
+pub async fn update_tags(music_id: MusicId, tags: TrackTags) -> Result<(), DatabaseError> {
+    log::info!("Updating tags for track {}.", music_id.0);
+
+    call(move |conn| {
+        conn.prepare_cached(
+            "UPDATE music SET artist = ?1, album = ?2, album_artist = ?3, track_number = ?4,
+                disc_number = ?5, year = ?6, genre = ?7
+            WHERE music_id = ?8",
+        )?
+        .execute((
+            tags.artist,
+            tags.album,
+            tags.album_artist,
+            tags.track_number,
+            tags.disc_number,
+            tags.year,
+            tags.genre,
+            music_id.0,
+        ))?;
+
+        Ok(())
+    })
+    .await
+}
 
-    Ok(())
+// Gets the tracks the loudness analyser hasn't been through yet.
+pub async fn get_unanalysed_music() -> Result<Vec<Track>, DatabaseError> {
+    log::info!("Requesting unanalysed music data.");
+
+    call(|conn| {
+        let music = conn
+            .prepare_cached(&format!(
+                "SELECT {} FROM music WHERE analysed = 0",
+                TRACK_COLUMNS
+            ))?
+            .query_map([], Track::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(music)
+    })
+    .await
 }
 
-pub fn edit_display_name(video_id: String, new_display_name: String) -> Result<(), DatabaseError> {
-    log::info!(
-        "Editing display name for {} to: {}",
-        video_id,
-        new_display_name
-    );
-    let conn = Connection::open("./data/data.db")?;
+pub async fn set_loudness(
+    music_id: MusicId,
+    loudness: f64,
+    true_peak: f64,
+) -> Result<(), DatabaseError> {
+    log::info!("Storing loudness for track {}.", music_id.0);
+
+    call(move |conn| {
+        conn.prepare_cached(
+            "UPDATE music SET loudness = ?1, true_peak = ?2, analysed = 1 WHERE music_id = ?3",
+        )?
+        .execute((loudness, true_peak, music_id.0))?;
+
+        Ok(())
+    })
+    .await
+}
 
-    conn.execute(
-        "UPDATE music SET display_name = ?1 WHERE video_id = ?2",
-        [&new_display_name, &video_id],
-    )?;
+// Marks a track the analyser couldn't measure, e.g. because it is silent or
+// can't be decoded, so it isn't tried again every run. It plays at its own
+// volume.
+pub async fn set_unmeasurable(music_id: MusicId) -> Result<(), DatabaseError> {
+    log::info!("Marking track {} as unmeasurable.", music_id.0);
 
-    Ok(())
+    call(move |conn| {
+        conn.prepare_cached("UPDATE music SET analysed = 1 WHERE music_id = ?1")?
+            .execute([music_id.0])?;
+
+        Ok(())
+    })
+    .await
 }
 
-pub fn get_playlist_tracks(playlist_id: i32) -> Vec<HashMap<String, String>> {
-    log::info!("Requesting playlist tracks.");
+// Stores the loudness of an album on every track in it. Tracks without an
+// album artist are grouped together.
+pub async fn set_album_loudness(
+    album: String,
+    album_artist: Option<String>,
+    loudness: f64,
+    peak: f64,
+) -> Result<(), DatabaseError> {
+    log::info!("Storing loudness for album {}.", album);
+
+    call(move |conn| {
+        conn.prepare_cached(
+            "UPDATE music SET album_loudness = ?1, album_peak = ?2
+            WHERE album = ?3 AND album_artist IS ?4",
+        )?
+        .execute((loudness, peak, album, album_artist))?;
+
+        Ok(())
+    })
+    .await
+}
 
-    let conn = Connection::open("./data/data.db").unwrap();
+// Remembers the speed a track is played at for the next time it is played.
+pub async fn set_speed(video_id: String, speed: f32) -> Result<(), DatabaseError> {
+    log::info!("Storing playback speed for {}.", video_id);
 
-    let mut statement = conn
-        .prepare("SELECT * FROM music_playlists WHERE playlist_id = ?1")
-        .unwrap();
-    let music_playlist_iter = statement
-        .query_map([playlist_id], |row| {
-            Ok(MusicPlaylist {
-                music_playlist_id: row.get(0)?,
-                music_id: row.get(1)?,
-                playlist_id: row.get(2)?,
-            })
-        })
-        .unwrap();
+    call(move |conn| {
+        conn.prepare_cached("UPDATE music SET speed = ?1 WHERE video_id = ?2")?
+            .execute((speed, video_id))?;
 
-    let mut music_data = Vec::new();
-
-    for music_playlist_item in music_playlist_iter {
-        let music_playlist_item = music_playlist_item.unwrap();
-        let mut music_map = HashMap::new();
-        music_map.insert(
-            "music_playlist_id".to_string(),
-            music_playlist_item.music_playlist_id.to_string(),
-        );
-        music_map.insert(
-            "music_id".to_string(),
-            music_playlist_item.music_id.to_string(),
-        );
-        music_map.insert(
-            "playlist_id".to_string(),
-            music_playlist_item.playlist_id.to_string(),
-        );
-        music_data.push(music_map);
-    }
+        Ok(())
+    })
+    .await
+}
+
+// The bookmarks of a track, in the order they come in the track.
+pub async fn get_bookmarks(music_id: MusicId) -> Result<Vec<Bookmark>, DatabaseError> {
+    log::info!("Requesting bookmarks.");
+
+    call(move |conn| {
+        let bookmarks = conn
+            .prepare_cached(
+                "SELECT bookmark_id, music_id, name, position FROM bookmarks
+                WHERE music_id = ?1 ORDER BY position",
+            )?
+            .query_map([music_id.0], Bookmark::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(bookmarks)
+    })
+    .await
+}
+
+pub async fn add_bookmark(
+    music_id: MusicId,
+    name: String,
+    position: Duration,
+) -> Result<(), DatabaseError> {
+    log::info!("Adding bookmark.");
+
+    call(move |conn| {
+        conn.prepare_cached(
+            "INSERT INTO bookmarks (music_id, name, position) VALUES (?1, ?2, ?3)",
+        )?
+        .execute((music_id.0, name, position.as_secs()))?;
+
+        Ok(())
+    })
+    .await
+}
 
-    log::info!("Playlist tracks received.");
+pub async fn delete_bookmark(bookmark_id: BookmarkId) -> Result<(), DatabaseError> {
+    log::info!("Deleting bookmark.");
 
-    music_data
+    call(move |conn| {
+        conn.prepare_cached("DELETE FROM bookmarks WHERE bookmark_id = ?1")?
+            .execute([bookmark_id.0])?;
+
+        Ok(())
+    })
+    .await
 }
 
-pub fn add_music_playlist(video_id: String, playlist_id: i32) -> Result<(), DatabaseError> {
+// Gets the tracks of a playlist in the order they were added to it.
+pub async fn get_playlist_tracks(
+    playlist_id: PlaylistId,
+) -> Result<Vec<PlaylistEntry>, DatabaseError> {
+    log::info!("Requesting playlist tracks.");
+
+    call(move |conn| {
+        let entries = playlist_tracks(conn, playlist_id)?;
+
+        log::info!("Playlist tracks received.");
+
+        Ok(entries)
+    })
+    .await
+}
+
+fn playlist_tracks(
+    conn: &Connection,
+    playlist_id: PlaylistId,
+) -> Result<Vec<PlaylistEntry>, DatabaseError> {
+    Ok(conn
+        .prepare_cached(
+            "SELECT music_playlists, music_id, playlist_id FROM music_playlists
+            WHERE playlist_id = ?1
+            ORDER BY music_playlists",
+        )?
+        .query_map([playlist_id.0], PlaylistEntry::from_row)?
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+pub async fn add_music_playlist(
+    video_id: String,
+    playlist_id: PlaylistId,
+) -> Result<(), DatabaseError> {
     log::info!("Adding track to playlist.");
 
-    let conn = Connection::open("./data/data.db")?;
+    call(move |conn| {
+        let music_id: i32 = conn
+            .prepare_cached("SELECT music_id FROM music WHERE video_id = ?1")?
+            .query_row([video_id], |row| row.get(0))?;
 
-    let mut statement = conn
-        .prepare("SELECT music_id FROM music WHERE video_id = ?1")
+        conn.prepare_cached("INSERT INTO music_playlists (music_id, playlist_id) VALUES (?1, ?2)")?
+            .execute([music_id, playlist_id.0])?;
+
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A track with nothing but its id filled in, for tests that need one.
+    pub(crate) fn track(id: i32) -> Track {
+        Track {
+            music_id: MusicId(id),
+            video_id: id.to_string(),
+            extension: "mp3".to_string(),
+            duration: Duration::from_secs(60),
+            display_name: id.to_string(),
+            artist: None,
+            album: None,
+            album_artist: None,
+            track_number: None,
+            disc_number: None,
+            year: None,
+            genre: None,
+            loudness: None,
+            true_peak: None,
+            album_loudness: None,
+            album_peak: None,
+            speed: None,
+        }
+    }
+
+    #[test]
+    fn lists_playlist_tracks_in_the_order_they_were_added() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("data.db");
+
+        migrations::run(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+
+        conn.execute_batch(
+            "INSERT INTO music (music_id, video_id, extension, duration)
+                VALUES (1, 'a', 'mp3', 1), (2, 'b', 'mp3', 1), (3, 'c', 'mp3', 1);
+            INSERT INTO playlists (playlist_id, name) VALUES (1, 'one'), (2, 'two');
+            INSERT INTO music_playlists (music_id, playlist_id)
+                VALUES (3, 1), (2, 2), (1, 1), (2, 1);",
+        )
         .unwrap();
-    let music_id = statement.query_row([video_id], |row| row.get(0)).unwrap();
 
-    conn.execute(
-        "INSERT INTO music_playlists (music_id, playlist_id) VALUES (?1, ?2)",
-        [music_id, playlist_id],
-    )?;
+        let music_ids: Vec<MusicId> = playlist_tracks(&conn, PlaylistId(1))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.music_id)
+            .collect();
 
-    Ok(())
+        assert_eq!(music_ids, [MusicId(3), MusicId(1), MusicId(2)]);
+    }
 }
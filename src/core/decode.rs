@@ -0,0 +1,334 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::Source;
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleError {
+    ReadError(String),
+    DecodeError(String),
+}
+
+// Decodes the file at `path` for the analysers, handing `f` the interleaved
+// samples of each packet along with their sample rate and channel count.
+// Corrupt packets are skipped rather than failing the whole track, and an
+// error from `f` stops decoding.
+pub fn decode_samples<E>(
+    path: &Path,
+    mut f: impl FnMut(&[f32], u32, usize) -> Result<(), E>,
+) -> Result<(), E>
+where
+    E: From<SampleError>,
+{
+    let (mut format, mut decoder, track_id) = open(path)?;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(e) => return Err(SampleError::DecodeError(e.to_string()).into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(SampleError::DecodeError(e.to_string()).into()),
+        };
+
+        let spec = *decoded.spec();
+
+        let buffer =
+            buffer.get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, spec));
+
+        if buffer.capacity() < decoded.capacity() * spec.channels.count() {
+            *buffer = SampleBuffer::new(decoded.capacity() as u64, spec);
+        }
+
+        buffer.copy_interleaved_ref(decoded);
+
+        f(buffer.samples(), spec.rate, spec.channels.count())?;
+    }
+
+    Ok(())
+}
+
+// A file's reader, a decoder for its default track and that track's id.
+type OpenedFile = (Box<dyn FormatReader>, Box<dyn Decoder>, u32);
+
+fn open(path: &Path) -> Result<OpenedFile, SampleError> {
+    let file = File::open(path).map_err(|e| SampleError::ReadError(e.to_string()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+
+    if let Some(extension) = path.extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| SampleError::DecodeError(e.to_string()))?;
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| SampleError::DecodeError("No audio track found".to_string()))?;
+
+    let track_id = track.id;
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| SampleError::DecodeError(e.to_string()))?;
+
+    Ok((probed.format, decoder, track_id))
+}
+
+// Plays a file straight from symphonia, for the MP4 containers rodio's decoder
+// can't open: it doesn't tell symphonia how long the file is, and the MP4
+// reader won't start without knowing.
+pub struct FileSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    spec: SignalSpec,
+    buffer: Option<SampleBuffer<f32>>,
+    // The next sample of `buffer` to play.
+    offset: usize,
+    total_duration: Option<Duration>,
+}
+
+impl FileSource {
+    pub fn open(path: &Path) -> Result<Self, SampleError> {
+        let (format, decoder, track_id) = open(path)?;
+
+        let params = &decoder.codec_params();
+        let total_duration = params
+            .time_base
+            .zip(params.n_frames)
+            .map(|(base, frames)| base.calc_time(frames))
+            .map(|time| Duration::from_secs_f64(time.seconds as f64 + time.frac));
+        let spec = SignalSpec::new(
+            params.sample_rate.unwrap_or(44100),
+            params.channels.unwrap_or_default(),
+        );
+
+        let mut source = Self {
+            format,
+            decoder,
+            track_id,
+            spec,
+            buffer: None,
+            offset: 0,
+            total_duration,
+        };
+
+        source.decode_next();
+
+        Ok(source)
+    }
+
+    fn remaining(&self) -> usize {
+        self.buffer
+            .as_ref()
+            .map_or(0, |buffer| buffer.samples().len() - self.offset)
+    }
+
+    // Decodes packets until one has samples, leaving the buffer empty at the
+    // end of the track. Corrupt packets are skipped as they are for analysis.
+    fn decode_next(&mut self) {
+        self.offset = 0;
+
+        loop {
+            let Ok(packet) = self.format.next_packet() else {
+                self.buffer = None;
+
+                return;
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(_) => {
+                    self.buffer = None;
+
+                    return;
+                }
+            };
+
+            if decoded.frames() == 0 {
+                continue;
+            }
+
+            self.spec = *decoded.spec();
+
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, self.spec));
+
+            if buffer.capacity() < decoded.capacity() * self.spec.channels.count() {
+                *buffer = SampleBuffer::new(decoded.capacity() as u64, self.spec);
+            }
+
+            buffer.copy_interleaved_ref(decoded);
+
+            return;
+        }
+    }
+}
+
+impl Iterator for FileSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.buffer.as_ref()?.samples()[self.offset];
+
+        self.offset += 1;
+
+        // Decoding ahead keeps the frame length from reading zero until the
+        // track has ended.
+        if self.remaining() == 0 {
+            self.decode_next();
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for FileSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.remaining())
+    }
+
+    fn channels(&self) -> u16 {
+        self.spec.channels.count() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.spec.rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let channels = self.spec.channels.count();
+        // Whoever is reading may be part way through a frame, and must still
+        // get the channel it expects next.
+        let channel = self.offset % channels;
+
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: pos.as_secs_f64().into(),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|e| SeekError::Other(Box::new(e)))?;
+
+        self.decoder.reset();
+
+        // The reader lands on the packet holding `pos`, so the frames before
+        // it are dropped.
+        let mut skip = (seeked.required_ts - seeked.actual_ts) as usize * channels + channel;
+
+        loop {
+            self.decode_next();
+
+            let remaining = self.remaining();
+
+            if remaining == 0 || skip < remaining {
+                self.offset = skip.min(remaining);
+
+                return Ok(());
+            }
+
+            skip -= remaining;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_fixture_samples() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/short.wav");
+        let mut samples = 0;
+
+        decode_samples::<SampleError>(&path, |decoded, rate, channels| {
+            assert_eq!((rate, channels), (44100, 1));
+
+            samples += decoded.len();
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(samples, 11025);
+    }
+
+    #[test]
+    fn stops_at_callback_error() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/short.wav");
+        let mut calls = 0;
+
+        let result = decode_samples(&path, |_, _, _| {
+            calls += 1;
+
+            Err(SampleError::DecodeError("stop".to_string()))
+        });
+
+        assert_eq!(result, Err(SampleError::DecodeError("stop".to_string())));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn plays_and_seeks_mp4_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/short.m4a");
+
+        let source = FileSource::open(&path).unwrap();
+
+        assert_eq!((source.sample_rate(), source.channels()), (44100, 1));
+        assert_eq!(source.count(), 10240);
+
+        let mut source = FileSource::open(&path).unwrap();
+        source.try_seek(Duration::from_millis(100)).unwrap();
+
+        assert_eq!(source.count(), 10240 - 4410);
+    }
+
+    #[test]
+    fn reports_missing_file() {
+        let result = decode_samples::<SampleError>(Path::new("missing.wav"), |_, _, _| Ok(()));
+
+        assert!(matches!(result, Err(SampleError::ReadError(_))));
+    }
+}
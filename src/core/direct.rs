@@ -0,0 +1,258 @@
+use std::path::Path;
+
+use super::playback;
+use super::source::{AudioStream, MusicSource, SearchResult, StatusError, TrackInfo};
+
+use iced::futures::future::BoxFuture;
+use iced::futures::{stream, FutureExt, StreamExt};
+use reqwest::Url;
+
+// Downloads audio files linked to directly, such as
+// `https://example.com/music/track.mp3`.
+pub struct DirectSource;
+
+// The file a link points at, if it is an http(s) link to a format the player
+// supports.
+fn file_of(url: &str) -> Option<(Url, String)> {
+    let url = Url::parse(url).ok()?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let extension = Path::new(url.path())
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+
+    playback::is_supported(&extension).then_some((url, extension))
+}
+
+// Links don't come with an id, so one is made from the link itself. This keeps
+// it the same each time the same link is downloaded.
+fn id_of(url: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in url.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    format!("http-{:016x}", hash)
+}
+
+impl MusicSource for DirectSource {
+    fn name(&self) -> &'static str {
+        "direct link"
+    }
+
+    fn resolve(&self, url: &str) -> Option<String> {
+        file_of(url).map(|_| id_of(url))
+    }
+
+    fn search(&self, _query: String) -> BoxFuture<'static, Result<Vec<SearchResult>, StatusError>> {
+        async { Err(StatusError::Unsupported) }.boxed()
+    }
+
+    fn metadata(&self, url: String) -> BoxFuture<'static, Result<TrackInfo, StatusError>> {
+        async move {
+            let (parsed, extension) = file_of(&url).ok_or(StatusError::Unsupported)?;
+
+            // Without tags the file name is the best title there is, and tags
+            // read once it has downloaded take its place.
+            let title = Path::new(parsed.path())
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            Ok(TrackInfo {
+                id: id_of(&url),
+                url,
+                title,
+                duration: None,
+                extension,
+                thumbnail: None,
+            })
+        }
+        .boxed()
+    }
+
+    fn download(&self, info: TrackInfo) -> BoxFuture<'static, Result<AudioStream, StatusError>> {
+        async move {
+            let response = reqwest::get(&info.url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|error| {
+                    if error.status() == Some(reqwest::StatusCode::NOT_FOUND) {
+                        StatusError::VideoNotFound
+                    } else {
+                        StatusError::NetworkError
+                    }
+                })?;
+
+            let length = response.content_length();
+
+            let chunks = stream::unfold(Some(response), |response| async move {
+                let mut response = response?;
+
+                match response.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), Some(response))),
+                    Ok(None) => None,
+                    Err(_) => Some((Err(StatusError::DownloadError), None)),
+                }
+            });
+
+            Ok(AudioStream {
+                length,
+                chunks: chunks.boxed(),
+            })
+        }
+        .boxed()
+    }
+
+    // Any cover art is read from the file's tags instead.
+    fn thumbnail(
+        &self,
+        _info: TrackInfo,
+    ) -> BoxFuture<'static, Result<Option<Vec<u8>>, StatusError>> {
+        async { Ok(None) }.boxed()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    // A fixture to serve, read from `tests/fixtures`.
+    pub(crate) fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    // Serves `respond`'s answer to every request on a local port, writing it
+    // in the pieces given with a pause between each so it arrives in several
+    // chunks. Returns the address to request from.
+    pub(crate) fn serve(respond: fn(&str) -> Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                let mut request = Vec::new();
+                let mut byte = [0; 1];
+
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    request.push(byte[0]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split(' ').nth(1).unwrap_or("/");
+
+                for piece in respond(path) {
+                    let _ = stream.write_all(&piece);
+                    let _ = stream.flush();
+
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+        });
+
+        address
+    }
+
+    // Answers requests for `/short.mp3` with the fixture in four pieces, and
+    // anything else with a 404.
+    pub(crate) fn audio_server(path: &str) -> Vec<Vec<u8>> {
+        if path != "/short.mp3" {
+            return vec![
+                b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            ];
+        }
+
+        let body = fixture("short.mp3");
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+
+        std::iter::once(header.into_bytes())
+            .chain(body.chunks(body.len().div_ceil(4)).map(<[u8]>::to_vec))
+            .collect()
+    }
+
+    pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn resolves_links_to_supported_files() {
+        assert_eq!(
+            DirectSource.resolve("http://127.0.0.1/music/short.MP3"),
+            Some(id_of("http://127.0.0.1/music/short.MP3"))
+        );
+        assert!(DirectSource.resolve("https://example.com/a.flac").is_some());
+
+        assert_eq!(DirectSource.resolve("https://example.com/a.opus"), None);
+        assert_eq!(DirectSource.resolve("https://example.com/music/"), None);
+        assert_eq!(DirectSource.resolve("ftp://example.com/a.mp3"), None);
+    }
+
+    #[test]
+    fn reads_metadata_from_the_link() {
+        let url = format!("{}/short.mp3", serve(audio_server));
+        let info = block_on(DirectSource.metadata(url.clone())).unwrap();
+
+        assert_eq!(info.id, id_of(&url));
+        assert_eq!(info.title, "short");
+        assert_eq!(info.extension, "mp3");
+        assert_eq!(info.duration, None);
+    }
+
+    #[test]
+    fn downloads_the_file() {
+        let url = format!("{}/short.mp3", serve(audio_server));
+
+        let downloaded = block_on(async {
+            let info = DirectSource.metadata(url).await.unwrap();
+            let stream = DirectSource.download(info).await.unwrap();
+
+            let expected = fixture("short.mp3").len() as u64;
+            assert_eq!(stream.length, Some(expected));
+
+            stream.chunks.map(Result::unwrap).concat().await
+        });
+
+        assert_eq!(downloaded, fixture("short.mp3"));
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        let url = format!("{}/missing.mp3", serve(audio_server));
+
+        let result = block_on(async {
+            let info = DirectSource.metadata(url).await.unwrap();
+
+            DirectSource.download(info).await.map(|_| ())
+        });
+
+        assert_eq!(result, Err(StatusError::VideoNotFound));
+    }
+}
@@ -0,0 +1,549 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+use super::db::{self, PlaylistId};
+use super::source::{self, Progress, StatusError};
+use super::youtube::{self, PlaylistVideo};
+
+use iced::futures::SinkExt;
+use iced::{stream, Subscription};
+use log;
+use tokio::sync::mpsc::{self as async_mpsc, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+// How many downloads run at once. The rest wait their turn, so starting many
+// never runs more than this many ffmpeg processes.
+const MAX_CONCURRENT: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DownloadId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadState {
+    Queued,
+    FetchingInfo,
+    // How much of the audio has been downloaded, from 0 to 1.
+    Downloading(f32),
+    Converting,
+    Thumbnail,
+    Done,
+    Failed(StatusError),
+    Cancelled,
+}
+
+impl DownloadState {
+    // Whether the download has stopped, for good or until it is retried.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done | Self::Failed(_) | Self::Cancelled)
+    }
+
+    // A download can be cancelled until its track is being added to the
+    // library.
+    pub fn is_cancellable(&self) -> bool {
+        matches!(
+            self,
+            Self::Queued | Self::FetchingInfo | Self::Downloading(_) | Self::Converting
+        )
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Failed(_) | Self::Cancelled)
+    }
+}
+
+impl fmt::Display for DownloadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Queued => write!(f, "Queued"),
+            Self::FetchingInfo => write!(f, "Fetching info"),
+            Self::Downloading(fraction) => write!(f, "Downloading {:.0}%", fraction * 100.0),
+            Self::Converting => write!(f, "Converting"),
+            Self::Thumbnail => write!(f, "Saving thumbnail"),
+            Self::Done => write!(f, "Done"),
+            Self::Failed(error) => write!(f, "Failed: {:?}", error),
+            Self::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Download {
+    pub id: DownloadId,
+    pub url: String,
+    // The video's title, once its info has been fetched.
+    pub title: Option<String>,
+    pub state: DownloadState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaylistError {
+    FetchError(StatusError),
+    DatabaseError(db::DatabaseError),
+}
+
+impl From<db::DatabaseError> for PlaylistError {
+    fn from(error: db::DatabaseError) -> Self {
+        PlaylistError::DatabaseError(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    Add(String),
+    AddPlaylist(String),
+    Cancel(DownloadId),
+    Retry(DownloadId),
+    ClearFinished,
+    Subscribe(UnboundedSender<DownloadStatus>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadStatus {
+    // Every download the manager knows of, whenever any of them changes.
+    Changed(Vec<Download>),
+    // A download that has just finished or failed.
+    Finished(Download),
+    // A playlist that has been filled with its tracks, and how many of them
+    // it was given.
+    PlaylistFilled(String, usize),
+    // A playlist that couldn't be listed, created or filled.
+    PlaylistFailed(String, PlaylistError),
+}
+
+// What the manager receives: events from the UI, and reports from the jobs it
+// started. Jobs tag their reports with the attempt they belong to, so a late
+// report from a cancelled job can't touch its retry.
+enum Message {
+    Event(DownloadEvent),
+    Progress(DownloadId, usize, Progress),
+    Finished(DownloadId, usize, Result<(), StatusError>),
+    PlaylistFound(FoundPlaylist),
+    PlaylistFailed(String, PlaylistError),
+}
+
+// A YouTube playlist that has been listed and given a wavey playlist, along
+// with the tracks the library already has.
+struct FoundPlaylist {
+    name: String,
+    playlist_id: PlaylistId,
+    videos: Vec<PlaylistVideo>,
+    library: HashSet<String>,
+}
+
+// A playlist waiting for its downloads. It is only filled once all of them
+// have finished, so its tracks go in the order they are listed rather than
+// the order they finish in. A download retried after that isn't added to it.
+struct PlaylistImport {
+    name: String,
+    playlist_id: PlaylistId,
+    video_ids: Vec<String>,
+    downloads: Vec<DownloadId>,
+}
+
+struct Item {
+    download: Download,
+    attempt: usize,
+    job: Option<JoinHandle<()>>,
+}
+
+struct Manager {
+    items: Vec<Item>,
+    imports: Vec<PlaylistImport>,
+    next_id: usize,
+    semaphore: Arc<Semaphore>,
+    messages: UnboundedSender<Message>,
+    status: Option<UnboundedSender<DownloadStatus>>,
+}
+
+// Runs the download manager on its own thread until every sender of `events`
+// is dropped.
+pub fn start_manager(mut events: UnboundedReceiver<DownloadEvent>) {
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::error!("Failed to start the download manager: {}", e);
+
+                return;
+            }
+        };
+
+        runtime.block_on(async move {
+            let (messages, mut receiver) = async_mpsc::unbounded_channel();
+
+            let forward = messages.clone();
+
+            tokio::spawn(async move {
+                while let Some(event) = events.recv().await {
+                    if forward.send(Message::Event(event)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let mut manager = Manager {
+                items: Vec::new(),
+                imports: Vec::new(),
+                next_id: 0,
+                semaphore: Arc::new(Semaphore::new(MAX_CONCURRENT)),
+                messages,
+                status: None,
+            };
+
+            while let Some(message) = receiver.recv().await {
+                match message {
+                    Message::Event(event) => manager.handle(event),
+                    Message::Progress(id, attempt, progress) => {
+                        manager.progress(id, attempt, progress)
+                    }
+                    Message::Finished(id, attempt, result) => manager.finish(id, attempt, result),
+                    Message::PlaylistFound(found) => manager.import_playlist(found),
+                    Message::PlaylistFailed(name, error) => {
+                        log::error!("Failed to download playlist {}: {:?}", name, error);
+
+                        manager.publish(DownloadStatus::PlaylistFailed(name, error));
+                    }
+                }
+            }
+        });
+    });
+}
+
+impl Manager {
+    fn handle(&mut self, event: DownloadEvent) {
+        match event {
+            DownloadEvent::Add(url) => {
+                self.add(url, None);
+            }
+            DownloadEvent::AddPlaylist(url) => {
+                let messages = self.messages.clone();
+
+                tokio::spawn(async move {
+                    let message = match find_playlist(url.clone()).await {
+                        Ok(found) => Message::PlaylistFound(found),
+                        Err(error) => Message::PlaylistFailed(url, error),
+                    };
+
+                    let _ = messages.send(message);
+                });
+            }
+            DownloadEvent::Cancel(id) => {
+                let Some(item) = self.item(id) else {
+                    return;
+                };
+
+                if !item.download.state.is_cancellable() {
+                    return;
+                }
+
+                if let Some(job) = item.job.take() {
+                    job.abort();
+                }
+
+                item.download.state = DownloadState::Cancelled;
+
+                log::info!("Cancelled download of {}.", item.download.url);
+            }
+            DownloadEvent::Retry(id) => {
+                let Some(item) = self.item(id) else {
+                    return;
+                };
+
+                if !item.download.state.is_retryable() {
+                    return;
+                }
+
+                self.start(id);
+            }
+            DownloadEvent::ClearFinished => {
+                self.items.retain(|item| !item.download.state.is_finished());
+            }
+            DownloadEvent::Subscribe(sender) => {
+                self.status = Some(sender);
+            }
+        }
+
+        self.fill_finished_playlists();
+        self.publish_changed();
+    }
+
+    fn add(&mut self, url: String, title: Option<String>) -> DownloadId {
+        let id = DownloadId(self.next_id);
+
+        self.next_id += 1;
+        self.items.push(Item {
+            download: Download {
+                id,
+                url,
+                title,
+                state: DownloadState::Queued,
+            },
+            attempt: 0,
+            job: None,
+        });
+
+        self.start(id);
+
+        id
+    }
+
+    // Queues a download for each track of the playlist the library doesn't
+    // have yet.
+    fn import_playlist(&mut self, found: FoundPlaylist) {
+        let mut queued = HashSet::new();
+        let mut downloads = Vec::new();
+
+        for video in &found.videos {
+            if found.library.contains(&video.video_id) || !queued.insert(&video.video_id) {
+                continue;
+            }
+
+            downloads.push(self.add(
+                youtube::video_url(&video.video_id),
+                Some(video.title.clone()),
+            ));
+        }
+
+        log::info!(
+            "Downloading {} of the {} tracks in playlist {}.",
+            downloads.len(),
+            found.videos.len(),
+            found.name
+        );
+
+        self.imports.push(PlaylistImport {
+            name: found.name,
+            playlist_id: found.playlist_id,
+            video_ids: found
+                .videos
+                .into_iter()
+                .map(|video| video.video_id)
+                .collect(),
+            downloads,
+        });
+
+        self.fill_finished_playlists();
+        self.publish_changed();
+    }
+
+    // Fills every playlist whose downloads have all finished, whether or not
+    // they succeeded. Tracks that failed are left out.
+    fn fill_finished_playlists(&mut self) {
+        let items = &self.items;
+
+        let (finished, waiting) = std::mem::take(&mut self.imports)
+            .into_iter()
+            .partition::<Vec<_>, _>(|import| {
+                import.downloads.iter().all(|id| {
+                    items
+                        .iter()
+                        .find(|item| item.download.id == *id)
+                        .map_or(true, |item| item.download.state.is_finished())
+                })
+            });
+
+        self.imports = waiting;
+
+        for import in finished {
+            let messages = self.status.clone();
+
+            tokio::spawn(async move {
+                let status = match fill_playlist(import.playlist_id, import.video_ids).await {
+                    Ok(added) => DownloadStatus::PlaylistFilled(import.name, added),
+                    Err(error) => {
+                        log::error!("Failed to fill playlist {}: {:?}", import.name, error);
+
+                        DownloadStatus::PlaylistFailed(import.name, error.into())
+                    }
+                };
+
+                if let Some(sender) = messages {
+                    let _ = sender.send(status);
+                }
+            });
+        }
+    }
+
+    // Queues a job for the download. It waits for a free slot before doing
+    // anything, and reports back to the manager as it goes.
+    fn start(&mut self, id: DownloadId) {
+        let semaphore = self.semaphore.clone();
+        let messages = self.messages.clone();
+
+        let Some(item) = self.item(id) else {
+            return;
+        };
+
+        item.attempt += 1;
+        item.download.state = DownloadState::Queued;
+
+        let attempt = item.attempt;
+        let url = item.download.url.clone();
+
+        item.job = Some(tokio::spawn(async move {
+            let Ok(_permit) = semaphore.acquire_owned().await else {
+                return;
+            };
+
+            let progress = messages.clone();
+
+            let result = source::download_from_url(url, move |stage| {
+                let _ = progress.send(Message::Progress(id, attempt, stage));
+            })
+            .await;
+
+            let _ = messages.send(Message::Finished(id, attempt, result));
+        }));
+    }
+
+    fn progress(&mut self, id: DownloadId, attempt: usize, progress: Progress) {
+        let Some(item) = self.item(id) else {
+            return;
+        };
+
+        if item.attempt != attempt || item.download.state.is_finished() {
+            return;
+        }
+
+        let state = match progress {
+            Progress::FetchingInfo => DownloadState::FetchingInfo,
+            Progress::Title(title) => {
+                item.download.title = Some(title);
+
+                item.download.state.clone()
+            }
+            Progress::Downloading(fraction) => {
+                // Only whole percentages are shown, so the rest aren't sent.
+                if let DownloadState::Downloading(current) = item.download.state {
+                    if (current * 100.0) as u32 == (fraction * 100.0) as u32 {
+                        return;
+                    }
+                }
+
+                DownloadState::Downloading(fraction)
+            }
+            Progress::Converting => DownloadState::Converting,
+            Progress::Thumbnail => DownloadState::Thumbnail,
+        };
+
+        item.download.state = state;
+
+        self.publish_changed();
+    }
+
+    fn finish(&mut self, id: DownloadId, attempt: usize, result: Result<(), StatusError>) {
+        let Some(item) = self.item(id) else {
+            return;
+        };
+
+        if item.attempt != attempt || item.download.state.is_finished() {
+            return;
+        }
+
+        item.job = None;
+        item.download.state = match result {
+            Ok(()) => DownloadState::Done,
+            Err(error) => {
+                log::error!("Failed to download {}: {:?}", item.download.url, error);
+
+                DownloadState::Failed(error)
+            }
+        };
+
+        let download = item.download.clone();
+
+        self.publish(DownloadStatus::Finished(download));
+        self.fill_finished_playlists();
+        self.publish_changed();
+    }
+
+    fn item(&mut self, id: DownloadId) -> Option<&mut Item> {
+        self.items.iter_mut().find(|item| item.download.id == id)
+    }
+
+    fn publish(&self, status: DownloadStatus) {
+        if let Some(sender) = &self.status {
+            let _ = sender.send(status);
+        }
+    }
+
+    fn publish_changed(&self) {
+        self.publish(DownloadStatus::Changed(
+            self.items
+                .iter()
+                .map(|item| item.download.clone())
+                .collect(),
+        ));
+    }
+}
+
+// Lists the playlist at `url` and creates a wavey playlist of the same name
+// for it.
+async fn find_playlist(url: String) -> Result<FoundPlaylist, PlaylistError> {
+    let (name, videos) = youtube::get_playlist(url)
+        .await
+        .map_err(PlaylistError::FetchError)?;
+
+    let library = db::get_all_music()
+        .await?
+        .into_iter()
+        .map(|track| track.video_id)
+        .collect();
+
+    let playlist_id = db::add_playlist(name.clone()).await?;
+
+    Ok(FoundPlaylist {
+        name,
+        playlist_id,
+        videos,
+        library,
+    })
+}
+
+// Adds the tracks to the playlist in order, skipping any that aren't in the
+// library, and returns how many were added.
+async fn fill_playlist(
+    playlist_id: PlaylistId,
+    video_ids: Vec<String>,
+) -> Result<usize, db::DatabaseError> {
+    let mut added = 0;
+
+    for video_id in video_ids {
+        match db::add_music_playlist(video_id, playlist_id).await {
+            Ok(()) => added += 1,
+            Err(db::DatabaseError::NotFound) => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(added)
+}
+
+// Streams the status of the download manager to the UI.
+pub fn status_subscription(sender: UnboundedSender<DownloadEvent>) -> Subscription<DownloadStatus> {
+    Subscription::run_with_id(
+        "download-status",
+        stream::channel(100, move |mut output| async move {
+            let (status_sender, mut status_receiver) = async_mpsc::unbounded_channel();
+
+            if sender
+                .send(DownloadEvent::Subscribe(status_sender))
+                .is_err()
+            {
+                log::error!("Failed to subscribe to download status.");
+            }
+
+            while let Some(status) = status_receiver.recv().await {
+                if output.send(status).await.is_err() {
+                    break;
+                }
+            }
+        }),
+    )
+}
@@ -0,0 +1,226 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::db;
+use super::decode::{self, SampleError};
+
+use log;
+use serde::{Deserialize, Serialize};
+
+// Envelopes are kept in their own folder rather than beside the thumbnails,
+// since thumbnails are found by any file name containing the video id.
+const ENVELOPE_DIRECTORY: &str = "./data/envelopes";
+
+// How many points an envelope is reduced to, whatever the track's length.
+pub const ENVELOPE_POINTS: usize = 300;
+
+// The track is first measured in windows this many times a second, which are
+// then merged down to the envelope's points.
+const WINDOWS_PER_SECOND: u32 = 20;
+
+static GENERATING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvelopeError {
+    ReadError(String),
+    DecodeError(String),
+    WriteError(String),
+}
+
+impl From<SampleError> for EnvelopeError {
+    fn from(error: SampleError) -> Self {
+        match error {
+            SampleError::ReadError(e) => EnvelopeError::ReadError(e),
+            SampleError::DecodeError(e) => EnvelopeError::DecodeError(e),
+        }
+    }
+}
+
+// The shape of a track's loudness over its length: the peak and RMS level of
+// each stretch of it, scaled so the loudest peak is 1.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Envelope {
+    pub peaks: Vec<f32>,
+    pub rms: Vec<f32>,
+}
+
+// How many envelopes a run generated and how many tracks couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenerationSummary {
+    pub generated: usize,
+    pub failed: usize,
+}
+
+fn envelope_path(video_id: &str) -> PathBuf {
+    PathBuf::from(format!("{}/{}.json", ENVELOPE_DIRECTORY, video_id))
+}
+
+// The envelope of `track`, generating it first if it hasn't been yet.
+pub async fn load(track: db::Track) -> Option<Envelope> {
+    if let Ok(contents) = tokio::fs::read_to_string(envelope_path(&track.video_id)).await {
+        match serde_json::from_str(&contents) {
+            Ok(envelope) => return Some(envelope),
+            Err(e) => log::error!("Error parsing envelope of {}: {}", track.video_id, e),
+        }
+    }
+
+    match generate(&track).await {
+        Ok(envelope) => Some(envelope),
+        Err(e) => {
+            log::error!("Failed to generate envelope of {}: {:?}", track.video_id, e);
+
+            None
+        }
+    }
+}
+
+// Generates the envelope of every track that doesn't have one yet. Only one
+// run happens at a time; starting another while one is going returns straight
+// away.
+pub async fn generate_library() -> Result<GenerationSummary, db::DatabaseError> {
+    let mut summary = GenerationSummary::default();
+
+    if GENERATING.swap(true, Ordering::SeqCst) {
+        return Ok(summary);
+    }
+
+    let result = generate_missing(&mut summary).await;
+
+    GENERATING.store(false, Ordering::SeqCst);
+
+    result?;
+
+    if summary.generated > 0 || summary.failed > 0 {
+        log::info!(
+            "Generated envelopes: {} tracks measured, {} failed.",
+            summary.generated,
+            summary.failed
+        );
+    }
+
+    Ok(summary)
+}
+
+async fn generate_missing(summary: &mut GenerationSummary) -> Result<(), db::DatabaseError> {
+    for track in db::get_all_music().await? {
+        if tokio::fs::try_exists(envelope_path(&track.video_id))
+            .await
+            .unwrap_or(false)
+        {
+            continue;
+        }
+
+        match generate(&track).await {
+            Ok(_) => summary.generated += 1,
+            Err(e) => {
+                log::error!("Failed to generate envelope of {}: {:?}", track.video_id, e);
+
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Measures `track` and saves its envelope. It is written to a temporary file
+// and moved into place, so a track being measured twice at once is harmless.
+async fn generate(track: &db::Track) -> Result<Envelope, EnvelopeError> {
+    let path = track.audio_path();
+
+    let envelope = tokio::task::spawn_blocking(move || measure(&path))
+        .await
+        .map_err(|e| EnvelopeError::DecodeError(e.to_string()))??;
+
+    let data =
+        serde_json::to_string(&envelope).map_err(|e| EnvelopeError::WriteError(e.to_string()))?;
+
+    let destination = envelope_path(&track.video_id);
+    let temporary = destination.with_extension("json.tmp");
+
+    tokio::fs::create_dir_all(ENVELOPE_DIRECTORY)
+        .await
+        .map_err(|e| EnvelopeError::WriteError(e.to_string()))?;
+
+    tokio::fs::write(&temporary, data)
+        .await
+        .map_err(|e| EnvelopeError::WriteError(e.to_string()))?;
+
+    tokio::fs::rename(&temporary, &destination)
+        .await
+        .map_err(|e| EnvelopeError::WriteError(e.to_string()))?;
+
+    Ok(envelope)
+}
+
+// Removes the envelope of a deleted track.
+pub fn remove(video_id: &str) {
+    let _ = std::fs::remove_file(envelope_path(video_id));
+}
+
+// Decodes the file at `path` and measures the peak and mean square of each
+// window of it, across all channels.
+fn measure(path: &Path) -> Result<Envelope, EnvelopeError> {
+    let mut windows: Vec<(f32, f32)> = Vec::new();
+
+    // The peak, sum of squares and number of samples of the window being read.
+    let mut window = (0.0f32, 0.0f32, 0usize);
+
+    decode::decode_samples::<EnvelopeError>(path, |samples, rate, channels| {
+        let window_samples = (rate / WINDOWS_PER_SECOND).max(1) as usize * channels;
+
+        for &sample in samples {
+            window.0 = window.0.max(sample.abs());
+            window.1 += sample * sample;
+            window.2 += 1;
+
+            if window.2 >= window_samples {
+                windows.push((window.0, window.1 / window.2 as f32));
+                window = (0.0, 0.0, 0);
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if window.2 > 0 {
+        windows.push((window.0, window.1 / window.2 as f32));
+    }
+
+    if windows.is_empty() {
+        return Err(EnvelopeError::DecodeError("No audio decoded".to_string()));
+    }
+
+    Ok(reduce(&windows))
+}
+
+// Merges the measured windows down to at most `ENVELOPE_POINTS`, keeping the
+// highest peak and the mean power of the windows each point covers.
+fn reduce(windows: &[(f32, f32)]) -> Envelope {
+    let points = windows.len().min(ENVELOPE_POINTS);
+
+    let mut peaks = Vec::with_capacity(points);
+    let mut rms = Vec::with_capacity(points);
+
+    for point in 0..points {
+        let first = point * windows.len() / points;
+        let last = ((point + 1) * windows.len() / points).max(first + 1);
+        let covered = &windows[first..last];
+
+        peaks.push(covered.iter().map(|window| window.0).fold(0.0, f32::max));
+        rms.push(
+            (covered.iter().map(|window| window.1).sum::<f32>() / covered.len() as f32).sqrt(),
+        );
+    }
+
+    let loudest = peaks.iter().copied().fold(0.0, f32::max);
+
+    // Silent tracks are left flat rather than divided by zero.
+    if loudest > 0.0 {
+        for level in peaks.iter_mut().chain(rms.iter_mut()) {
+            *level = (*level / loudest).min(1.0);
+        }
+    }
+
+    Envelope { peaks, rms }
+}
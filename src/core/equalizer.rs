@@ -0,0 +1,315 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+
+pub const BAND_COUNT: usize = 10;
+
+// Centre frequencies of the bands in Hz, an octave apart.
+pub const BANDS: [f32; BAND_COUNT] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+// How far each band can be raised or lowered, in dB.
+pub const MAX_GAIN: f32 = 12.0;
+
+// The bandwidth of each band, about one octave.
+const Q: f32 = 1.41;
+
+// How often, in samples, the running filter checks for new gains.
+const UPDATE_INTERVAL: u64 = 1024;
+
+// The gains the playback thread writes and every playing source reads, so
+// changes apply without reopening the track.
+pub type EqGains = Arc<Mutex<[f32; BAND_COUNT]>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    BassBoost,
+    Vocal,
+    Custom,
+}
+
+impl EqPreset {
+    pub const ALL: &'static [Self] = &[Self::Flat, Self::BassBoost, Self::Vocal, Self::Custom];
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Flat => "Flat",
+            Self::BassBoost => "Bass Boost",
+            Self::Vocal => "Vocal",
+            Self::Custom => "Custom",
+        }
+        .fmt(f)
+    }
+}
+
+// The selected preset and the gains of the custom one, which are kept when
+// switching to another preset and back.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct EqualizerSettings {
+    pub preset: EqPreset,
+    pub custom: [f32; BAND_COUNT],
+}
+
+impl EqualizerSettings {
+    // The gain of each band in dB.
+    pub fn gains(&self) -> [f32; BAND_COUNT] {
+        match self.preset {
+            EqPreset::Flat => [0.0; BAND_COUNT],
+            EqPreset::BassBoost => [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            EqPreset::Vocal => [-2.0, -2.0, -1.0, 0.0, 2.0, 4.0, 4.0, 2.0, 0.0, -1.0],
+            EqPreset::Custom => self.custom,
+        }
+    }
+}
+
+// The coefficients of a peaking filter from the Audio EQ Cookbook, normalised
+// so that a0 is 1.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn peaking(frequency: f32, gain: f32, sample_rate: u32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * Q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha / a;
+
+        Self {
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * cos) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * cos) / a0,
+            a2: (1.0 - alpha / a) / a0,
+        }
+    }
+}
+
+// The last two inputs and outputs of one filter on one channel.
+#[derive(Clone, Copy, Default)]
+struct History {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+// Runs a source through a 10-band graphic equalizer. Bands left at 0 dB, and
+// bands above the Nyquist frequency, are skipped. When any band is raised the
+// whole signal is lowered by the largest boost so it doesn't clip.
+pub struct Equalizer<S> {
+    input: S,
+    shared: EqGains,
+    gains: [f32; BAND_COUNT],
+    filters: [Option<Biquad>; BAND_COUNT],
+    // One entry per band per channel, indexed `band * channels + channel`.
+    history: Vec<History>,
+    preamp: f32,
+    channels: usize,
+    channel: usize,
+    position: u64,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, shared: EqGains) -> Self {
+        let gains = *shared.lock().unwrap();
+        let channels = input.channels().max(1) as usize;
+
+        let mut equalizer = Self {
+            input,
+            shared,
+            gains,
+            filters: [None; BAND_COUNT],
+            history: vec![History::default(); BAND_COUNT * channels],
+            preamp: 1.0,
+            channels,
+            channel: 0,
+            position: 0,
+        };
+
+        equalizer.update_filters();
+
+        equalizer
+    }
+
+    // Rebuilds the filters from `gains`. Bands that were already running keep
+    // their history so the change doesn't click.
+    fn update_filters(&mut self) {
+        let sample_rate = self.input.sample_rate();
+        let nyquist = sample_rate as f32 / 2.0;
+
+        for (band, (&frequency, gain)) in BANDS.iter().zip(self.gains).enumerate() {
+            let filter = (gain != 0.0 && frequency < nyquist)
+                .then(|| Biquad::peaking(frequency, gain, sample_rate));
+
+            if self.filters[band].is_none() {
+                self.history[band * self.channels..(band + 1) * self.channels]
+                    .fill(History::default());
+            }
+
+            self.filters[band] = filter;
+        }
+
+        let boost = self.gains.iter().copied().fold(0.0, f32::max);
+        self.preamp = 10f32.powf(-boost / 20.0);
+    }
+
+    fn check_gains(&mut self) {
+        let Ok(shared) = self.shared.try_lock() else {
+            return;
+        };
+
+        if *shared != self.gains {
+            self.gains = *shared;
+
+            drop(shared);
+
+            self.update_filters();
+        }
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position % UPDATE_INTERVAL == 0 {
+            self.check_gains();
+        }
+
+        let mut sample = self.input.next()? * self.preamp;
+
+        for (band, filter) in self.filters.iter().enumerate() {
+            let Some(filter) = filter else {
+                continue;
+            };
+
+            let history = &mut self.history[band * self.channels + self.channel];
+
+            let output = filter.b0 * sample + filter.b1 * history.x1 + filter.b2 * history.x2
+                - filter.a1 * history.y1
+                - filter.a2 * history.y2;
+
+            history.x2 = history.x1;
+            history.x1 = sample;
+            history.y2 = history.y1;
+            history.y1 = output;
+
+            sample = output;
+        }
+
+        self.position += 1;
+        self.channel = (self.channel + 1) % self.channels;
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    // The filter history belongs to the audio before the seek, so it is
+    // cleared rather than carried over.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+
+        self.history.fill(History::default());
+        self.channel = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 44100;
+
+    // Half a second of a full-scale sine at `frequency`.
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..RATE / 2)
+            .map(|i| (2.0 * PI * frequency * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    fn equalize(samples: &[f32], gains: [f32; BAND_COUNT]) -> Vec<f32> {
+        let source = SamplesBuffer::new(1, RATE, samples.to_vec());
+
+        Equalizer::new(source, Arc::new(Mutex::new(gains))).collect()
+    }
+
+    // The RMS after the filters have settled.
+    fn rms(samples: &[f32]) -> f32 {
+        let settled = &samples[samples.len() / 2..];
+
+        (settled.iter().map(|sample| sample * sample).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn flat_preset_passes_audio_through() {
+        let input = sine(440.0);
+        let gains = EqualizerSettings::default().gains();
+
+        assert_eq!(equalize(&input, gains), input);
+    }
+
+    #[test]
+    fn raised_band_boosts_its_centre_frequency() {
+        let mut gains = [0.0; BAND_COUNT];
+        gains[5] = 6.0;
+
+        let centre = sine(BANDS[5]);
+        let away = sine(BANDS[9]);
+
+        // Everything is lowered by the 6 dB boost to leave headroom, so the
+        // centre comes out as loud as it went in and the rest 6 dB quieter.
+        let boosted = rms(&equalize(&centre, gains)) / rms(&centre);
+        let lowered = rms(&equalize(&away, gains)) / rms(&away);
+
+        assert!((boosted - 1.0).abs() < 0.02, "{}", boosted);
+        assert!((lowered - 0.5).abs() < 0.02, "{}", lowered);
+        assert!(20.0 * (boosted / lowered).log10() > 5.8);
+    }
+}
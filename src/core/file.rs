@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use super::playback;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FileError {
     DialogClosed,
@@ -14,3 +16,38 @@ pub async fn pick_file() -> Result<PathBuf, FileError> {
 
     Ok(handle.path().to_owned())
 }
+
+pub async fn pick_audio_files() -> Result<Vec<PathBuf>, FileError> {
+    let handles = rfd::AsyncFileDialog::new()
+        .set_title("Select audio files")
+        .add_filter("Audio", playback::SUPPORTED_EXTENSIONS)
+        .pick_files()
+        .await
+        .ok_or(FileError::DialogClosed)?;
+
+    Ok(handles
+        .iter()
+        .map(|handle| handle.path().to_owned())
+        .collect())
+}
+
+pub async fn pick_image() -> Result<PathBuf, FileError> {
+    let handle = rfd::AsyncFileDialog::new()
+        .set_title("Select cover art")
+        .add_filter("Image", &["jpg", "jpeg", "png"])
+        .pick_file()
+        .await
+        .ok_or(FileError::DialogClosed)?;
+
+    Ok(handle.path().to_owned())
+}
+
+pub async fn pick_folder() -> Result<PathBuf, FileError> {
+    let handle = rfd::AsyncFileDialog::new()
+        .set_title("Select folder")
+        .pick_folder()
+        .await
+        .ok_or(FileError::DialogClosed)?;
+
+    Ok(handle.path().to_owned())
+}
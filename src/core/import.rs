@@ -0,0 +1,362 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::db;
+use super::playback;
+use super::tags::TrackTags;
+
+use log;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision};
+use symphonia::core::probe::Hint;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    UnsupportedFormat,
+    ReadError(String),
+    DecodeError(String),
+    WriteError(String),
+    DatabaseError(db::DatabaseError),
+}
+
+// The outcome of an import: the display names of the tracks that were added,
+// and the path and reason of every file that wasn't.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub failed: Vec<(PathBuf, ImportError)>,
+}
+
+// Everything read from an audio file before it is copied into the library.
+pub struct ProbedFile {
+    pub duration: Duration,
+    pub cover_art: Option<(Vec<u8>, &'static str)>,
+    pub tags: TrackTags,
+}
+
+// Copies each file into `data/audio` under a generated id and adds it to the
+// library. Files that can't be imported are recorded in the summary and don't
+// stop the rest from being imported.
+pub async fn import_files(paths: Vec<PathBuf>) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+
+    for path in paths {
+        match import_file(path.clone()).await {
+            Ok(display_name) => summary.imported.push(display_name),
+            Err(e) => {
+                log::error!("Failed to import {}: {:?}", path.display(), e);
+
+                summary.failed.push((path, e));
+            }
+        }
+    }
+
+    log::info!(
+        "Imported {} files, {} failed.",
+        summary.imported.len(),
+        summary.failed.len()
+    );
+
+    summary
+}
+
+// Imports every supported audio file in `folder` and its subfolders. Folders
+// that can't be read are recorded in the summary along with the files.
+pub async fn import_folder(folder: PathBuf) -> ImportSummary {
+    let scanned = folder.clone();
+
+    let (paths, unreadable) =
+        match tokio::task::spawn_blocking(move || find_audio_files(&scanned)).await {
+            Ok(found) => found,
+            Err(e) => {
+                log::error!("Folder scan panicked: {}", e);
+
+                return ImportSummary {
+                    failed: vec![(folder, ImportError::ReadError(e.to_string()))],
+                    ..Default::default()
+                };
+            }
+        };
+
+    let mut summary = import_files(paths).await;
+    summary.failed.splice(0..0, unreadable);
+
+    summary
+}
+
+// Finds the supported audio files in `folder` and its subfolders, and the
+// folders and entries that couldn't be read. Symlinked folders aren't
+// followed, so a link back up the tree can't make the scan loop forever.
+fn find_audio_files(folder: &Path) -> (Vec<PathBuf>, Vec<(PathBuf, ImportError)>) {
+    let mut paths = Vec::new();
+    let mut unreadable = Vec::new();
+
+    scan_folder(folder, &mut paths, &mut unreadable);
+
+    paths.sort();
+
+    (paths, unreadable)
+}
+
+fn scan_folder(
+    folder: &Path,
+    paths: &mut Vec<PathBuf>,
+    unreadable: &mut Vec<(PathBuf, ImportError)>,
+) {
+    let mut unreadable_entry = |path: &Path, e: std::io::Error| {
+        log::error!("Failed to read {}: {}", path.display(), e);
+
+        unreadable.push((path.to_path_buf(), ImportError::ReadError(e.to_string())));
+    };
+
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) => return unreadable_entry(folder, e),
+    };
+
+    let mut folders = Vec::new();
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                unreadable_entry(folder, e);
+
+                continue;
+            }
+        };
+
+        let path = entry.path();
+
+        // The entry's own type, which for a symlink is the link rather than
+        // what it points at.
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => folders.push(path),
+            Ok(file_type) if file_type.is_symlink() && path.is_dir() => {
+                log::info!("Skipping symlinked folder {}.", path.display());
+            }
+            Ok(_) => {
+                if extension_of(&path).is_some_and(|ext| playback::is_supported(&ext)) {
+                    paths.push(path);
+                }
+            }
+            Err(e) => unreadable_entry(&path, e),
+        }
+    }
+
+    for folder in folders {
+        scan_folder(&folder, paths, unreadable);
+    }
+}
+
+async fn import_file(path: PathBuf) -> Result<String, ImportError> {
+    let extension = extension_of(&path).ok_or(ImportError::UnsupportedFormat)?;
+
+    if !playback::is_supported(&extension) {
+        return Err(ImportError::UnsupportedFormat);
+    }
+
+    let id = Uuid::new_v4().simple().to_string();
+    let destination = PathBuf::from(format!("./data/audio/{}.{}", id, extension));
+
+    let probed = {
+        let path = path.clone();
+        let extension = extension.clone();
+
+        tokio::task::spawn_blocking(move || probe_file(&path, &extension))
+            .await
+            .map_err(|e| ImportError::DecodeError(e.to_string()))??
+    };
+
+    // Prefer the title tag, falling back to the file name.
+    let display_name = probed.tags.title.clone().unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+
+    tokio::fs::copy(&path, &destination)
+        .await
+        .map_err(|e| ImportError::WriteError(e.to_string()))?;
+
+    if let Err(e) = db::add_music(
+        id.clone(),
+        extension,
+        display_name.clone(),
+        probed.duration,
+        probed.tags,
+    )
+    .await
+    {
+        let _ = tokio::fs::remove_file(&destination).await;
+
+        return Err(ImportError::DatabaseError(e));
+    }
+
+    // Written once the track is in the library, so a failed import doesn't
+    // leave a cover behind.
+    if let Some((data, image_extension)) = probed.cover_art {
+        let thumbnail_path = format!("./data/thumbnails/{}.{}", id, image_extension);
+
+        if let Err(e) = tokio::fs::write(thumbnail_path, data).await {
+            log::error!("Failed to write cover art: {}", e);
+        }
+    }
+
+    Ok(display_name)
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+// Reads the duration, tags and embedded cover art of an audio file. The duration
+// comes from the container's frame count when it records one, otherwise the
+// packets are walked and their durations summed.
+pub fn probe_file(path: &Path, extension: &str) -> Result<ProbedFile, ImportError> {
+    let file = File::open(path).map_err(|e| ImportError::ReadError(e.to_string()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    hint.with_extension(extension);
+
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| ImportError::DecodeError(e.to_string()))?;
+
+    let mut tags = TrackTags::default();
+    let mut cover_art = None;
+
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.merge_revision(revision);
+            cover_art = cover_art_of(revision);
+        }
+    }
+
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.merge_revision(revision);
+        cover_art = cover_art.or_else(|| cover_art_of(revision));
+    }
+
+    let track = probed
+        .format
+        .default_track()
+        .ok_or_else(|| ImportError::DecodeError("No audio track found".to_string()))?;
+
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base.or_else(|| {
+        track
+            .codec_params
+            .sample_rate
+            .map(|rate| symphonia::core::units::TimeBase::new(1, rate))
+    });
+    let time_base =
+        time_base.ok_or_else(|| ImportError::DecodeError("Unknown sample rate".to_string()))?;
+
+    let frames = match track.codec_params.n_frames {
+        Some(frames) => frames,
+        None => {
+            let mut frames = 0;
+
+            while let Ok(packet) = probed.format.next_packet() {
+                if packet.track_id() == track_id {
+                    frames += packet.dur;
+                }
+            }
+
+            frames
+        }
+    };
+
+    let time = time_base.calc_time(frames);
+
+    Ok(ProbedFile {
+        duration: Duration::from_secs(time.seconds),
+        cover_art,
+        tags,
+    })
+}
+
+fn cover_art_of(revision: &MetadataRevision) -> Option<(Vec<u8>, &'static str)> {
+    revision.visuals().first().map(|visual| {
+        let extension = if visual.media_type == "image/png" {
+            "png"
+        } else {
+            "jpg"
+        };
+
+        (visual.data.to_vec(), extension)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::direct::tests::block_on;
+
+    #[test]
+    fn finds_supported_files_in_subfolders() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+
+        fs::create_dir_all(root.join("album/disc 2")).unwrap();
+
+        for file in ["b.mp3", "notes.txt", "album/a.FLAC", "album/disc 2/c.ogg"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+
+        let (paths, unreadable) = find_audio_files(root);
+
+        assert_eq!(
+            paths,
+            [
+                root.join("album/a.FLAC"),
+                root.join("album/disc 2/c.ogg"),
+                root.join("b.mp3")
+            ]
+        );
+        assert!(unreadable.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_symlinked_folders() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+
+        fs::create_dir(root.join("album")).unwrap();
+        fs::write(root.join("album/a.mp3"), b"").unwrap();
+        std::os::unix::fs::symlink(root, root.join("album/loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("album/a.mp3"), root.join("link.mp3")).unwrap();
+
+        let (paths, unreadable) = find_audio_files(root);
+
+        assert_eq!(paths, [root.join("album/a.mp3"), root.join("link.mp3")]);
+        assert!(unreadable.is_empty());
+    }
+
+    #[test]
+    fn reports_unreadable_folder() {
+        let directory = tempfile::tempdir().unwrap();
+        let missing = directory.path().join("missing");
+
+        let summary = block_on(import_folder(missing.clone()));
+
+        assert!(summary.imported.is_empty());
+        assert!(matches!(
+            summary.failed.as_slice(),
+            [(path, ImportError::ReadError(_))] if *path == missing
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::db;
+use super::decode::{self, SampleError};
+
+use ebur128::{EbuR128, Mode};
+use log;
+use serde::{Deserialize, Serialize};
+
+// The loudness every track is brought to, as used by ReplayGain 2.0.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+static ANALYSING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NormalisationMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+}
+
+impl NormalisationMode {
+    pub const ALL: &'static [Self] = &[Self::Off, Self::Track, Self::Album];
+}
+
+impl fmt::Display for NormalisationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Off => "Off",
+            Self::Track => "Track gain",
+            Self::Album => "Album gain",
+        }
+        .fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoudnessError {
+    ReadError(String),
+    DecodeError(String),
+    AnalysisError(String),
+}
+
+impl From<SampleError> for LoudnessError {
+    fn from(error: SampleError) -> Self {
+        match error {
+            SampleError::ReadError(e) => LoudnessError::ReadError(e),
+            SampleError::DecodeError(e) => LoudnessError::DecodeError(e),
+        }
+    }
+}
+
+// How many tracks an analysis run measured and how many couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AnalysisSummary {
+    pub analysed: usize,
+    pub failed: usize,
+}
+
+// The gain to play `track` at in `mode`, as a linear factor. It is lowered
+// where needed so the true peak never goes above full scale. Album mode falls
+// back to the track's own values for tracks that aren't part of an album, and
+// tracks that haven't been analysed yet are played as they are.
+pub fn gain(track: &db::Track, mode: NormalisationMode) -> f32 {
+    let measured = match mode {
+        NormalisationMode::Off => None,
+        NormalisationMode::Track => track.loudness.zip(track.true_peak),
+        NormalisationMode::Album => track
+            .album_loudness
+            .zip(track.album_peak)
+            .or(track.loudness.zip(track.true_peak)),
+    };
+
+    let Some((loudness, peak)) = measured else {
+        return 1.0;
+    };
+
+    let gain = 10f64.powf((REFERENCE_LOUDNESS - loudness) / 20.0);
+
+    if peak > 0.0 {
+        gain.min(1.0 / peak) as f32
+    } else {
+        gain as f32
+    }
+}
+
+// Measures every track that hasn't been analysed yet, then recomputes the
+// loudness of each album from its tracks. Only one run happens at a time;
+// starting another while one is going returns straight away.
+pub async fn analyse_library() -> Result<AnalysisSummary, db::DatabaseError> {
+    let mut summary = AnalysisSummary::default();
+
+    if ANALYSING.swap(true, Ordering::SeqCst) {
+        return Ok(summary);
+    }
+
+    let result = analyse_tracks(&mut summary).await;
+
+    ANALYSING.store(false, Ordering::SeqCst);
+
+    result?;
+
+    log::info!(
+        "Analysed loudness: {} tracks measured, {} failed.",
+        summary.analysed,
+        summary.failed
+    );
+
+    Ok(summary)
+}
+
+async fn analyse_tracks(summary: &mut AnalysisSummary) -> Result<(), db::DatabaseError> {
+    let tracks = db::get_unanalysed_music().await?;
+
+    if !tracks.is_empty() {
+        log::info!("Analysing loudness of {} tracks.", tracks.len());
+    }
+
+    for track in tracks {
+        let path = track.audio_path();
+
+        let (loudness, true_peak) = match tokio::task::spawn_blocking(move || analyse(&path)).await
+        {
+            Ok(Ok(measured)) => measured,
+            // A file that can't be opened may be back next run, so it is
+            // tried again then.
+            Ok(Err(e @ LoudnessError::ReadError(_))) => {
+                log::error!("Failed to analyse {}: {:?}", track.video_id, e);
+
+                summary.failed += 1;
+                continue;
+            }
+            Ok(Err(e)) => {
+                log::error!("Failed to analyse {}: {:?}", track.video_id, e);
+
+                db::set_unmeasurable(track.music_id).await?;
+
+                summary.failed += 1;
+                continue;
+            }
+            Err(e) => {
+                log::error!("Loudness analyser panicked for {}: {}", track.video_id, e);
+
+                db::set_unmeasurable(track.music_id).await?;
+
+                summary.failed += 1;
+                continue;
+            }
+        };
+
+        db::set_loudness(track.music_id, loudness, true_peak).await?;
+
+        summary.analysed += 1;
+    }
+
+    update_albums().await
+}
+
+// An album's loudness is the duration-weighted mean of its tracks' loudness
+// in the power domain, and its peak is the highest of theirs. Albums are
+// recomputed every run since tag edits can move tracks between them.
+async fn update_albums() -> Result<(), db::DatabaseError> {
+    let mut albums: HashMap<(String, Option<String>), (f64, f64, f64)> = HashMap::new();
+
+    for track in db::get_all_music().await? {
+        let (Some(album), Some(loudness), Some(peak)) =
+            (track.album, track.loudness, track.true_peak)
+        else {
+            continue;
+        };
+
+        let seconds = track.duration.as_secs_f64().max(1.0);
+        let entry = albums
+            .entry((album, track.album_artist))
+            .or_insert((0.0, 0.0, 0.0));
+
+        entry.0 += seconds * 10f64.powf(loudness / 10.0);
+        entry.1 += seconds;
+        entry.2 = entry.2.max(peak);
+    }
+
+    for ((album, album_artist), (power, seconds, peak)) in albums {
+        let loudness = 10.0 * (power / seconds).log10();
+
+        db::set_album_loudness(album, album_artist, loudness, peak).await?;
+    }
+
+    Ok(())
+}
+
+// Decodes the file at `path` and returns its integrated loudness in LUFS and
+// its true peak across all channels as a linear amplitude.
+fn analyse(path: &Path) -> Result<(f64, f64), LoudnessError> {
+    let mut meter: Option<(EbuR128, u32, u32)> = None;
+
+    decode::decode_samples(path, |samples, rate, channels| {
+        let channels = channels as u32;
+
+        let (analyser, meter_rate, meter_channels) = match meter.as_mut() {
+            Some(meter) => meter,
+            None => {
+                let analyser = EbuR128::new(channels, rate, Mode::I | Mode::TRUE_PEAK)
+                    .map_err(|e| LoudnessError::AnalysisError(e.to_string()))?;
+
+                meter.insert((analyser, rate, channels))
+            }
+        };
+
+        // The meter is set up for the format of the first packet, and would
+        // measure anything else wrongly.
+        check_format((*meter_rate, *meter_channels), (rate, channels))?;
+
+        analyser
+            .add_frames_f32(samples)
+            .map_err(|e| LoudnessError::AnalysisError(e.to_string()))
+    })?;
+
+    let (meter, _, channels) =
+        meter.ok_or_else(|| LoudnessError::DecodeError("No audio decoded".to_string()))?;
+
+    let loudness = meter
+        .loudness_global()
+        .map_err(|e| LoudnessError::AnalysisError(e.to_string()))?;
+
+    let mut peak: f64 = 0.0;
+
+    for channel in 0..channels {
+        peak = peak.max(
+            meter
+                .true_peak(channel)
+                .map_err(|e| LoudnessError::AnalysisError(e.to_string()))?,
+        );
+    }
+
+    // Silent tracks report negative infinity, which can't be normalised.
+    if !loudness.is_finite() {
+        return Err(LoudnessError::AnalysisError("Track is silent".to_string()));
+    }
+
+    Ok((loudness, peak))
+}
+
+// Fails unless a packet's sample rate and channel count match the ones the
+// meter was set up with.
+fn check_format(expected: (u32, u32), found: (u32, u32)) -> Result<(), LoudnessError> {
+    if found == expected {
+        return Ok(());
+    }
+
+    Err(LoudnessError::AnalysisError(format!(
+        "Format changed from {} Hz with {} channels to {} Hz with {} channels",
+        expected.0, expected.1, found.0, found.1
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::db::tests::track;
+
+    fn measured(loudness: f64, peak: f64) -> db::Track {
+        db::Track {
+            loudness: Some(loudness),
+            true_peak: Some(peak),
+            ..track(1)
+        }
+    }
+
+    fn assert_gain(track: &db::Track, mode: NormalisationMode, expected: f32) {
+        let gain = gain(track, mode);
+
+        assert!(
+            (gain - expected).abs() < 1e-4,
+            "{:?}: {}, expected {}",
+            mode,
+            gain,
+            expected
+        );
+    }
+
+    #[test]
+    fn off_plays_tracks_as_they_are() {
+        assert_gain(&measured(-30.0, 0.1), NormalisationMode::Off, 1.0);
+    }
+
+    #[test]
+    fn track_gain_brings_track_to_reference() {
+        // 6 dB quieter than the reference, so doubled.
+        assert_gain(&measured(-24.0, 0.1), NormalisationMode::Track, 1.9953);
+
+        // Tracks that haven't been analysed are played as they are.
+        assert_gain(&track(1), NormalisationMode::Track, 1.0);
+    }
+
+    #[test]
+    fn album_gain_falls_back_to_track_values() {
+        let mut track = measured(-24.0, 0.1);
+
+        assert_gain(&track, NormalisationMode::Album, 1.9953);
+
+        track.album_loudness = Some(-18.0);
+        track.album_peak = Some(0.5);
+
+        assert_gain(&track, NormalisationMode::Album, 1.0);
+
+        // An album loudness without a peak isn't usable.
+        track.album_peak = None;
+
+        assert_gain(&track, NormalisationMode::Album, 1.9953);
+    }
+
+    #[test]
+    fn gain_is_limited_by_true_peak() {
+        // Wants +12 dB, but the peak leaves room for only 2x.
+        assert_gain(&measured(-30.0, 0.5), NormalisationMode::Track, 2.0);
+
+        // Loud tracks are turned down whatever their peak.
+        assert_gain(&measured(-12.0, 1.2), NormalisationMode::Track, 0.5012);
+    }
+
+    #[test]
+    fn rejects_packets_in_another_format() {
+        assert_eq!(check_format((44100, 2), (44100, 2)), Ok(()));
+        assert!(matches!(
+            check_format((44100, 2), (48000, 2)),
+            Err(LoudnessError::AnalysisError(_))
+        ));
+        assert!(matches!(
+            check_format((44100, 2), (44100, 1)),
+            Err(LoudnessError::AnalysisError(_))
+        ));
+    }
+}
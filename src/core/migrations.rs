@@ -0,0 +1,287 @@
+use std::fs;
+use std::path::Path;
+
+use super::db::DatabaseError;
+
+use log;
+use rusqlite::Connection;
+
+// Every schema change is appended to this list and must never be edited once
+// released. The SQLite `user_version` pragma records how many of these have
+// been applied to a database, so old databases are upgraded in place by
+// running the remaining entries in order.
+//
+// Version 1 is the schema that `create_database_tables` used to create. Its
+// statements use `IF NOT EXISTS` so that databases created before versioning
+// existed (which all report `user_version` 0) pass through it unchanged.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE IF NOT EXISTS music (
+        music_id INTEGER PRIMARY KEY NOT NULL,
+        video_id TEXT NOT NULL,
+        extension TEXT NOT NULL,
+        duration INTEGER,
+        display_name TEXT
+    );
+    CREATE TABLE IF NOT EXISTS playlists (
+        playlist_id INTEGER PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS music_playlists (
+        music_playlists INTEGER PRIMARY KEY NOT NULL,
+        music_id INTEGER REFERENCES music(music_id),
+        playlist_id INTEGER REFERENCES playlists(playlist_id)
+    );",
+    // 2: tag columns
+    "ALTER TABLE music ADD COLUMN artist TEXT;
+    ALTER TABLE music ADD COLUMN album TEXT;
+    ALTER TABLE music ADD COLUMN album_artist TEXT;
+    ALTER TABLE music ADD COLUMN track_number INTEGER;
+    ALTER TABLE music ADD COLUMN disc_number INTEGER;
+    ALTER TABLE music ADD COLUMN year INTEGER;
+    ALTER TABLE music ADD COLUMN genre TEXT;",
+    // 3: loudness analysis
+    "ALTER TABLE music ADD COLUMN loudness REAL;
+    ALTER TABLE music ADD COLUMN true_peak REAL;
+    ALTER TABLE music ADD COLUMN album_loudness REAL;
+    ALTER TABLE music ADD COLUMN album_peak REAL;",
+    // 4: per-track playback speed
+    "ALTER TABLE music ADD COLUMN speed REAL;",
+    // 5: bookmarks
+    "CREATE TABLE bookmarks (
+        bookmark_id INTEGER PRIMARY KEY NOT NULL,
+        music_id INTEGER NOT NULL REFERENCES music(music_id),
+        name TEXT NOT NULL,
+        position INTEGER NOT NULL
+    );",
+    // 6: tracks the loudness analyser has been through, including those it
+    // couldn't measure
+    "ALTER TABLE music ADD COLUMN analysed INTEGER NOT NULL DEFAULT 0;
+    UPDATE music SET analysed = 1 WHERE loudness IS NOT NULL;",
+];
+
+pub fn schema_version(conn: &Connection) -> Result<u32, DatabaseError> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+// Brings the database at `path` up to the latest version, creating it if it
+// doesn't exist. Existing databases are copied to `<path>.v<version>.bak`
+// before anything is changed, and all pending migrations run in a single
+// transaction so a failure leaves the database as it was.
+pub fn run(path: &Path) -> Result<(), DatabaseError> {
+    migrate(path, MIGRATIONS)
+}
+
+fn migrate(path: &Path, migrations: &[&str]) -> Result<(), DatabaseError> {
+    let latest = migrations.len() as u32;
+    let existed = path.exists();
+    let mut conn = Connection::open(path)?;
+
+    let version = schema_version(&conn)?;
+
+    if version > latest {
+        log::error!(
+            "Database version {} is newer than this build supports ({}).",
+            version,
+            latest
+        );
+
+        return Err(DatabaseError::UnsupportedVersion(version));
+    }
+
+    if version == latest {
+        return Ok(());
+    }
+
+    if existed {
+        let backup = path.with_extension(format!("db.v{}.bak", version));
+
+        log::info!("Backing up database to {}.", backup.display());
+
+        fs::copy(path, &backup)?;
+    }
+
+    log::info!("Migrating database from version {} to {}.", version, latest);
+
+    let transaction = conn.transaction()?;
+
+    for (index, migration) in migrations.iter().enumerate().skip(version as usize) {
+        log::info!("Applying migration {}.", index + 1);
+
+        transaction.execute_batch(migration)?;
+    }
+
+    transaction.pragma_update(None, "user_version", latest)?;
+    transaction.commit()?;
+
+    log::info!("Database migrated.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATEST_VERSION: u32 = MIGRATIONS.len() as u32;
+
+    // The schema every database had before migrations existed, which all
+    // report `user_version` 0.
+    const BASELINE_SCHEMA: &str = "
+        CREATE TABLE music (
+            music_id INTEGER PRIMARY KEY NOT NULL,
+            video_id TEXT NOT NULL,
+            extension TEXT NOT NULL,
+            duration INTEGER,
+            display_name TEXT
+        );
+        CREATE TABLE playlists (
+            playlist_id INTEGER PRIMARY KEY NOT NULL,
+            name TEXT NOT NULL
+        );
+        CREATE TABLE music_playlists (
+            music_playlists INTEGER PRIMARY KEY NOT NULL,
+            music_id INTEGER REFERENCES music(music_id),
+            playlist_id INTEGER REFERENCES playlists(playlist_id)
+        );
+        INSERT INTO music (video_id, extension, duration, display_name)
+            VALUES ('dQw4w9WgXcQ', 'mp3', 212, 'Never Gonna Give You Up');";
+
+    fn baseline_database(directory: &Path) -> std::path::PathBuf {
+        let path = directory.join("data.db");
+
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(BASELINE_SCHEMA)
+            .unwrap();
+
+        path
+    }
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut statement = conn
+            .prepare(&format!("PRAGMA table_info({})", table))
+            .unwrap();
+
+        statement
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn upgrades_baseline_database() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = baseline_database(directory.path());
+
+        run(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+
+        let music = columns(&conn, "music");
+
+        for column in [
+            "artist",
+            "genre",
+            "loudness",
+            "album_peak",
+            "speed",
+            "analysed",
+        ] {
+            assert!(music.iter().any(|name| name == column), "{}", column);
+        }
+
+        assert!(!columns(&conn, "bookmarks").is_empty());
+
+        let name: String = conn
+            .query_row("SELECT display_name FROM music", [], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(name, "Never Gonna Give You Up");
+        assert!(directory.path().join("data.db.v0.bak").exists());
+    }
+
+    #[test]
+    fn creates_new_database_without_backup() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("data.db");
+
+        run(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+        assert!(!directory.path().join("data.db.v0.bak").exists());
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = baseline_database(directory.path());
+
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push("ALTER TABLE missing ADD COLUMN broken TEXT;");
+
+        assert!(migrate(&path, &migrations).is_err());
+
+        let conn = Connection::open(&path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+        assert!(!columns(&conn, "music").iter().any(|name| name == "artist"));
+        assert!(columns(&conn, "bookmarks").is_empty());
+
+        let backup = directory.path().join("data.db.v0.bak");
+        let backup = Connection::open(backup).unwrap();
+
+        assert_eq!(schema_version(&backup).unwrap(), 0);
+        assert_eq!(columns(&backup, "music").len(), 5);
+    }
+
+    #[test]
+    fn keeps_measured_tracks_analysed() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = baseline_database(directory.path());
+
+        migrate(&path, &MIGRATIONS[..5]).unwrap();
+
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "INSERT INTO music (video_id, extension, loudness, true_peak)
+                    VALUES ('measured', 'flac', -14.0, 0.9);",
+            )
+            .unwrap();
+
+        run(&path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let analysed = conn
+            .prepare("SELECT video_id FROM music WHERE analysed = 1")
+            .unwrap()
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(analysed, ["measured"]);
+    }
+
+    #[test]
+    fn rejects_newer_database() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("data.db");
+
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", LATEST_VERSION + 1)
+            .unwrap();
+
+        assert!(matches!(
+            run(&path),
+            Err(DatabaseError::UnsupportedVersion(version)) if version == LATEST_VERSION + 1
+        ));
+    }
+}
@@ -1,8 +1,24 @@
+pub mod crossfade;
 pub mod db;
+pub mod decode;
+pub mod direct;
+pub mod download;
+pub mod envelope;
+pub mod equalizer;
 pub mod file;
 pub mod format;
+pub mod import;
 pub mod json;
+pub mod loudness;
+pub mod migrations;
 pub mod playback;
+pub mod queue;
 pub mod request;
 pub mod rpc;
+pub mod session;
+pub mod source;
+pub mod stretch;
+pub mod tags;
+pub mod transcode;
+pub mod visualiser;
 pub mod youtube;
@@ -1,31 +1,339 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use rodio::{OutputStream, Sink};
+use super::crossfade::{Crossfade, NextSlot};
+use super::db::{MusicId, Track};
+use super::decode::FileSource;
+use super::equalizer::{EqGains, Equalizer, BAND_COUNT};
+use super::loudness::{self, NormalisationMode};
+use super::queue::{Queue, RepeatMode};
+use super::stretch::{self, Tempo, TimeStretch};
+use super::visualiser::{Block, BlockSender, Tap};
+
+use iced::futures::SinkExt;
+use iced::{stream, Subscription};
+use rodio::cpal::traits::HostTrait;
+use rodio::source::{Amplify, SkipDuration};
+use rodio::{cpal, Decoder, DeviceTrait, OutputStream, Sink, Source};
+use tokio::sync::mpsc as async_mpsc;
+
+// File extensions of the containers the decoder can play. Opus is missing
+// because rodio has no Opus decoder; such files need transcoding first.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "wav", "m4a", "mp4", "aac"];
+
+// Pressing back within this long of a track starting goes to the previous
+// track instead of restarting the current one.
+const PREVIOUS_THRESHOLD: Duration = Duration::from_secs(3);
+
+// How long before the end of a track the next one is decoded and queued in the
+// sink, so that it follows on without a gap.
+const PRELOAD_WINDOW: Duration = Duration::from_secs(10);
+
+// The range of the volume slider in dB. Levels are spread evenly over it so
+// that equal steps sound like equal changes in loudness.
+const VOLUME_RANGE: f32 = 50.0;
+
+// How long playback fades out for before a sleep timer pauses it.
+const SLEEP_FADE: Duration = Duration::from_secs(10);
+
+// How often opening an output device is tried again while there is none.
+const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Containers rodio's decoder can't open, which are decoded by `FileSource`.
+const MP4_EXTENSIONS: &[&str] = &["m4a", "mp4"];
+
+pub fn is_supported(extension: &str) -> bool {
+    SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+// The amplitude the sink plays at for a volume slider level between 0 and 1.
+// The bottom of the slider is silent.
+fn volume_curve(level: f32) -> f32 {
+    if level <= 0.0 {
+        0.0
+    } else {
+        10f32.powf(VOLUME_RANGE * (level.min(1.0) - 1.0) / 20.0)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum AudioEvent {
-    Queue(String, Option<Vec<HashMap<String, String>>>),
+    // Replaces the queue and starts playing its current track.
+    Queue(Queue),
+    // Replaces the queue with a saved one, ready to play from the position in
+    // its current track but paused.
+    Restore(Queue, Duration),
+    // Edits to the queue. Indices are positions in play order.
+    PlayNext(Track),
+    Enqueue(Track),
+    Remove(usize),
+    Move(usize, usize),
+    Clear,
+    JumpTo(usize),
+    SetShuffle(bool),
+    SetRepeat(RepeatMode),
+    SetGapless(bool),
+    SetCrossfade(Duration),
+    SetNormalisation(NormalisationMode),
+    // Gains of the equalizer bands in dB, applied to the playing track.
+    SetEqualizer([f32; BAND_COUNT]),
+    // Plays the current track at a new speed, which is remembered for it.
+    SetSpeed(f32),
+    // Whether speed changes keep the pitch of the track, instead of raising
+    // or lowering it along with the speed.
+    SetPreservePitch(bool),
+    // Moves playback to the named output device, or the system default.
+    SetOutputDevice(Option<String>),
     SeekTo(u64),
+    // Marks the start of a loop at the current position. Loops are marked
+    // rather than given as a `SetLoop(start, end)` because only this thread
+    // knows the position precisely; the UI hears it once a second.
+    SetLoopStart,
+    // Marks the end of the loop at the current position and repeats the part
+    // of the track since its start until cleared or the track changes. An
+    // end at or before the start is ignored.
+    SetLoopEnd,
+    ClearLoop,
+    // Sets the volume slider level, from 0 to 1, and unmutes.
     SetVolume(f32),
     PauseToggle,
+    // Silences playback, keeping the level to go back to on unmuting.
     Mute,
     Unmute,
     Backward,
     Forward,
+    // Starts a sleep timer, replacing any running one, or cancels it.
+    SetSleepTimer(Option<SleepTimer>),
+    // Registers the channel the thread publishes its status to.
+    Subscribe(async_mpsc::UnboundedSender<PlaybackStatus>),
+    // Registers the channel what is played is copied to for the visualiser.
+    Visualise(mpsc::SyncSender<Block>),
+}
+
+// What the playback thread reports back to the UI, which renders from these
+// rather than keeping its own idea of what is playing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaybackStatus {
+    TrackStarted(Track),
+    Position(Duration),
+    TrackEnded(Track),
+    QueueChanged(Queue),
+    // The name of the output device in use, or None when no device could be
+    // opened and nothing can be heard.
+    OutputChanged(Option<String>),
+    SpeedChanged(f32),
+    // Where a loop starts while its end hasn't been marked yet.
+    LoopStartChanged(Option<Duration>),
+    // The start and end of the part of the current track being looped.
+    LoopChanged(Option<(Duration, Duration)>),
+    // How long until the sleep timer pauses playback, or None once it has
+    // been cancelled or has gone off.
+    SleepChanged(Option<Duration>),
+    // Whether a track is playing, sent whenever that changes, including when
+    // the sleep timer pauses playback or the queue runs out.
+    Playing,
+    Paused,
+    Error(String),
+}
+
+// When a sleep timer pauses playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepTimer {
+    Minutes(u64),
+    EndOfTrack,
+    EndOfQueue,
+}
+
+impl SleepTimer {
+    pub const ALL: &'static [Self] = &[
+        Self::Minutes(15),
+        Self::Minutes(30),
+        Self::Minutes(45),
+        Self::Minutes(60),
+        Self::Minutes(90),
+        Self::EndOfTrack,
+        Self::EndOfQueue,
+    ];
+}
+
+impl fmt::Display for SleepTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Minutes(minutes) => write!(f, "{} minutes", minutes),
+            Self::EndOfTrack => "End of track".fmt(f),
+            Self::EndOfQueue => "End of queue".fmt(f),
+        }
+    }
+}
+
+// A running sleep timer. Timers of a fixed length count down from when they
+// were set, whether or not anything is playing.
+struct Sleep {
+    timer: SleepTimer,
+    deadline: Instant,
+    // The last whole second published, so the countdown is only sent when it
+    // changes.
+    published: Option<u64>,
+}
+
+impl Sleep {
+    fn new(timer: SleepTimer) -> Self {
+        let length = match timer {
+            SleepTimer::Minutes(minutes) => Duration::from_secs(minutes * 60),
+            SleepTimer::EndOfTrack | SleepTimer::EndOfQueue => Duration::ZERO,
+        };
+
+        Self {
+            timer,
+            deadline: Instant::now() + length,
+            published: None,
+        }
+    }
+}
+
+// An entry in the list of output devices to choose from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputDevice {
+    Default,
+    Named(String),
+}
+
+impl OutputDevice {
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Self::Default => None,
+            Self::Named(name) => Some(name.clone()),
+        }
+    }
 }
 
+impl From<Option<String>> for OutputDevice {
+    fn from(name: Option<String>) -> Self {
+        match name {
+            Some(name) => Self::Named(name),
+            None => Self::Default,
+        }
+    }
+}
+
+impl fmt::Display for OutputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => "System default".fmt(f),
+            Self::Named(name) => name.fmt(f),
+        }
+    }
+}
+
+// What the playback thread is playing, and how it moves through the queue
+// when a track ends.
+struct Player {
+    sink: Sink,
+    // Keeps the device the sink plays to open. Without one the sink is idle
+    // and tracks aren't started.
+    output: Option<OutputStream>,
+    // The device chosen in the settings, None for the system default.
+    device: Option<String>,
+    // The name of the device actually in use, which is the default when the
+    // chosen one can't be opened.
+    device_name: Option<String>,
+    next_retry: Instant,
+    // Where to pick the current track up from once a device is available.
+    resume: Option<Duration>,
+    queue: Queue,
+    repeat: RepeatMode,
+    // Whether the sink holds a track from the queue. Cleared once the queue
+    // runs out so an empty sink isn't mistaken for a track ending.
+    active: bool,
+    status: Option<async_mpsc::UnboundedSender<PlaybackStatus>>,
+    // The last whole second published, so the position is only sent when it
+    // changes.
+    last_position: Option<u64>,
+    // Whether playback was last published as paused.
+    last_paused: Option<bool>,
+    // The volume slider level, which is kept while muted.
+    volume: f32,
+    muted: bool,
+    gapless: bool,
+    crossfade: Duration,
+    normalisation: NormalisationMode,
+    equalizer: EqGains,
+    visualiser: BlockSender,
+    speed: f32,
+    preserve_pitch: bool,
+    // The tempo the time stretch plays at, which is the speed when the pitch
+    // is preserved and 1 otherwise.
+    tempo: Tempo,
+    // Speeds chosen this session, which the tracks in the queue don't know.
+    speeds: HashMap<MusicId, f32>,
+    // The sink position and the position in the track when the speed was
+    // last set, since the sink counts time played rather than time in the
+    // track.
+    clock: (Duration, Duration),
+    // Where the opening of the next track goes for the current one to fade
+    // into.
+    slot: NextSlot,
+    // How far into its track the current source started, when its opening was
+    // played by the previous crossfade.
+    offset: Duration,
+    // The next track, once it has been queued in the sink behind the current.
+    prepared: Option<Prepared>,
+    // Whether preparing the next track was already attempted for this one.
+    next_checked: bool,
+    sleep: Option<Sleep>,
+    // The start of a loop whose end hasn't been marked yet.
+    loop_start: Option<Duration>,
+    // The part of the current track being looped.
+    ab_loop: Option<(Duration, Duration)>,
+}
+
+struct Prepared {
+    track: Track,
+    slot: NextSlot,
+    offset: Duration,
+}
+
+type SinkSource = Tap<TimeStretch<Equalizer<Crossfade<TrackSource>>>>;
+
+type TrackSource = Amplify<SkipDuration<Box<dyn Source<Item = f32> + Send>>>;
+
 pub fn start_receiver(reciever: mpsc::Receiver<AudioEvent>) {
     thread::spawn(move || {
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+        let mut player = Player::new();
+
+        player.switch_output();
 
         loop {
             if let Ok(command) = reciever.try_recv() {
-                process_audio_command(command, &sink);
+                process_audio_command(command, &mut player);
+            }
+
+            // The sink holds the current track, plus the next one once it is
+            // prepared, so it holding fewer means the current track ended.
+            if player.active && player.sink.len() < 1 + player.prepared.is_some() as usize {
+                player.finish_track();
+            }
+
+            if player.active {
+                player.check_loop();
+                player.prepare_if_due();
+                player.publish_position();
+            }
+
+            if player.sleep.is_some() {
+                player.update_sleep();
+            }
+
+            player.publish_paused();
+
+            if player.output.is_none() && Instant::now() >= player.next_retry {
+                player.switch_output();
             }
 
             thread::sleep(std::time::Duration::from_millis(100));
@@ -33,46 +341,231 @@ pub fn start_receiver(reciever: mpsc::Receiver<AudioEvent>) {
     });
 }
 
-fn process_audio_command(command: AudioEvent, sink: &Sink) {
+fn process_audio_command(command: AudioEvent, player: &mut Player) {
+    let sink = &player.sink;
+
     match command {
         AudioEvent::Backward => {
-            let try_seek = sink.try_seek(Duration::from_secs(0));
+            if player.position() < PREVIOUS_THRESHOLD {
+                let previous = player.queue.previous(player.repeat).cloned();
 
-            match try_seek {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("Failed to seek: {}", e);
-                }
+                player.skip_to(previous);
+                player.publish_queue();
+
+                return;
             }
+
+            player.seek(Duration::ZERO);
         }
 
         AudioEvent::Forward => {
-            sink.skip_one();
+            let next = player.queue.advance(player.repeat, true).cloned();
+
+            player.skip_to(next);
+            player.publish_queue();
+        }
+
+        AudioEvent::PlayNext(track) => {
+            let was_empty = player.queue.is_empty();
+
+            player.queue.play_next(track);
+            player.start_if_idle(was_empty);
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::Enqueue(track) => {
+            let was_empty = player.queue.is_empty();
+
+            player.queue.enqueue(track);
+            player.start_if_idle(was_empty);
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::Remove(index) => {
+            let was_current = index == player.queue.position();
+            let was_last = index + 1 == player.queue.len();
+
+            let Some(removed) = player.queue.remove(index) else {
+                return;
+            };
+
+            if was_current && player.active {
+                if was_last {
+                    player.stop(removed);
+                } else {
+                    let next = player.queue.current().cloned();
+
+                    player.skip_to(next);
+                }
+            }
+
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::Move(from, to) => {
+            player.queue.move_track(from, to);
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::Clear => {
+            player.queue.clear();
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::JumpTo(index) => {
+            let track = player.queue.jump_to(index).cloned();
+
+            if track.is_some() {
+                player.skip_to(track);
+                player.publish_queue();
+            }
+        }
+
+        AudioEvent::SetRepeat(repeat) => {
+            player.repeat = repeat;
+            player.check_prepared();
+        }
+
+        AudioEvent::SetGapless(gapless) => {
+            player.gapless = gapless;
+        }
+
+        // The current track is queued again so it fades out over the new
+        // length.
+        AudioEvent::SetCrossfade(crossfade) => {
+            if crossfade != player.crossfade {
+                player.crossfade = crossfade;
+
+                if player.active {
+                    let position = player.position();
+
+                    player.requeue_current(position);
+                }
+            }
+        }
+
+        // The gain is applied when a track is decoded, so the current track is
+        // queued again to pick up the new one.
+        AudioEvent::SetNormalisation(normalisation) => {
+            if normalisation != player.normalisation {
+                player.normalisation = normalisation;
+
+                if player.active {
+                    let position = player.position();
+
+                    player.requeue_current(position);
+                }
+            }
+        }
+
+        AudioEvent::SetSpeed(speed) => {
+            if let Some(track) = player.queue.current() {
+                player.speeds.insert(track.music_id, speed);
+            }
+
+            player.speed = speed;
+            player.apply_speed();
+        }
+
+        AudioEvent::SetPreservePitch(preserve_pitch) => {
+            player.preserve_pitch = preserve_pitch;
+            player.apply_speed();
+        }
+
+        // Playing sources pick the new gains up themselves.
+        AudioEvent::SetEqualizer(gains) => {
+            *player.equalizer.lock().unwrap() = gains;
+        }
+
+        AudioEvent::SetShuffle(shuffle) => {
+            if shuffle {
+                player.queue.shuffle();
+            } else {
+                player.queue.unshuffle();
+            }
+
+            player.check_prepared();
+            player.publish_queue();
+        }
+
+        AudioEvent::Subscribe(status) => {
+            player.status = Some(status);
+            player.last_paused = None;
+
+            player.publish(PlaybackStatus::OutputChanged(player.device_name.clone()));
+        }
+
+        AudioEvent::Visualise(sender) => {
+            *player.visualiser.lock().unwrap() = Some(sender);
+        }
+
+        AudioEvent::SetOutputDevice(device) => {
+            if device != player.device {
+                player.device = device;
+                player.switch_output();
+            }
         }
 
         AudioEvent::SetVolume(volume) => {
-            sink.set_volume(volume);
+            player.volume = volume;
+            player.muted = false;
+
+            sink.set_volume(volume_curve(volume));
         }
 
         AudioEvent::Mute => {
+            player.muted = true;
+
             sink.set_volume(0.0);
         }
 
         AudioEvent::Unmute => {
-            sink.set_volume(0.5);
+            player.muted = false;
+
+            sink.set_volume(volume_curve(player.volume));
+        }
+
+        AudioEvent::SetSleepTimer(timer) => {
+            player.sleep = timer.map(Sleep::new);
+
+            // A timer that was fading has to give the volume back.
+            player.restore_volume();
+
+            if player.sleep.is_none() {
+                player.publish(PlaybackStatus::SleepChanged(None));
+            }
         }
 
         AudioEvent::SeekTo(position) => {
-            let try_seek = sink.try_seek(Duration::from_secs(position));
+            player.seek(Duration::from_secs(position));
+        }
 
-            match try_seek {
-                Ok(_) => (),
-                Err(e) => {
-                    log::error!("Failed to seek-to: {}", e);
-                }
+        // The loop is marked where the sink is rather than where the UI last
+        // heard it was, which is only updated every second.
+        AudioEvent::SetLoopStart => {
+            if player.active {
+                player.clear_loop();
+
+                player.loop_start = Some(player.position());
+                player.publish(PlaybackStatus::LoopStartChanged(player.loop_start));
             }
         }
 
+        AudioEvent::SetLoopEnd => {
+            if let Some(start) = player.loop_start.filter(|_| player.active) {
+                player.set_loop(start, player.position());
+            }
+        }
+
+        AudioEvent::ClearLoop => {
+            player.clear_loop();
+        }
+
         AudioEvent::PauseToggle => {
             if sink.is_paused() {
                 sink.play();
@@ -81,33 +574,921 @@ fn process_audio_command(command: AudioEvent, sink: &Sink) {
             }
         }
 
-        AudioEvent::Queue(video_id, tracks) => {
-            if tracks.is_some() {
-                sink.clear();
+        AudioEvent::Queue(queue) => {
+            sink.clear();
+
+            let current = queue.current().cloned();
+            player.queue = queue;
+            player.play(current);
+            player.publish_queue();
+
+            player.sink.play();
+        }
+
+        AudioEvent::Restore(queue, position) => {
+            sink.clear();
+
+            let current = queue.current().cloned();
+            player.queue = queue;
+            player.play(current);
+
+            if player.resume.is_some() {
+                player.resume = Some(position);
+            } else if player.active && !position.is_zero() {
+                player.seek(position);
+            }
+
+            player.publish_queue();
+        }
+    }
+}
+
+impl Player {
+    // A player with nothing to play and no output device open yet.
+    fn new() -> Self {
+        Self {
+            sink: Sink::new_idle().0,
+            output: None,
+            device: None,
+            device_name: None,
+            next_retry: Instant::now(),
+            resume: None,
+            queue: Queue::default(),
+            repeat: RepeatMode::Off,
+            active: false,
+            status: None,
+            last_position: None,
+            last_paused: None,
+            volume: 1.0,
+            muted: false,
+            gapless: true,
+            crossfade: Duration::ZERO,
+            normalisation: NormalisationMode::Off,
+            equalizer: EqGains::default(),
+            visualiser: BlockSender::default(),
+            speed: 1.0,
+            preserve_pitch: false,
+            tempo: stretch::new_tempo(1.0),
+            speeds: HashMap::new(),
+            clock: (Duration::ZERO, Duration::ZERO),
+            slot: NextSlot::default(),
+            offset: Duration::ZERO,
+            prepared: None,
+            next_checked: false,
+            sleep: None,
+            loop_start: None,
+            ab_loop: None,
+        }
+    }
+
+    // Appends `track` to the sink, moving further through the queue past any
+    // tracks that fail to load. Playback stops once the queue runs out.
+    fn play(&mut self, mut track: Option<Track>) {
+        if self.output.is_none() {
+            if track.is_some() {
+                self.resume = Some(Duration::ZERO);
+                self.publish(PlaybackStatus::Error(
+                    "No audio device is available.".to_string(),
+                ));
+            }
+
+            self.active = false;
+
+            return;
+        }
+
+        let mut attempts = self.queue.len();
+
+        while let Some(next) = track {
+            match self.load(&next) {
+                Ok(()) => {
+                    self.active = true;
+                    self.clear_loop();
+                    self.use_track_speed(&next);
+                    self.publish(PlaybackStatus::TrackStarted(next));
+                    return;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.publish(PlaybackStatus::Error(e));
+                }
+            }
+
+            attempts = attempts.saturating_sub(1);
+
+            if attempts == 0 {
+                break;
+            }
+
+            track = self.queue.advance(self.repeat, true).cloned();
+        }
+
+        self.active = false;
+    }
+
+    // Appends `track` to the sink from its start as the current source.
+    fn load(&mut self, track: &Track) -> Result<(), String> {
+        let slot = NextSlot::default();
+        let source = open_track(track, Duration::ZERO, self.normalisation)?;
+
+        self.sink.append(self.chain(Crossfade::new(
+            source,
+            Duration::ZERO,
+            track.duration,
+            self.crossfade,
+            slot.clone(),
+        )));
+
+        self.slot = slot;
+        self.offset = Duration::ZERO;
+        self.clock = (Duration::ZERO, Duration::ZERO);
+        self.prepared = None;
+        self.next_checked = false;
+        self.last_position = None;
+
+        Ok(())
+    }
+
+    // Moves on to the next track once the current one has ended, which is
+    // already playing if it was prepared.
+    fn finish_track(&mut self) {
+        if let Some(track) = self.queue.current().cloned() {
+            self.publish(PlaybackStatus::TrackEnded(track));
+        }
+
+        let sleep = match self.sleep.as_ref().map(|sleep| sleep.timer) {
+            Some(SleepTimer::EndOfTrack) => true,
+            Some(SleepTimer::EndOfQueue) => self.queue.position() + 1 >= self.queue.len(),
+            _ => false,
+        };
+
+        match self.prepared.take() {
+            Some(prepared) => {
+                // The opening is still waiting if the track ended before it
+                // could be faded in, and the prepared source skips it.
+                let missed_opening = self.slot.lock().unwrap().take().is_some();
+
+                self.queue.advance(self.repeat, false);
+
+                self.slot = prepared.slot;
+                self.offset = prepared.offset;
+                self.clock = (Duration::ZERO, Duration::ZERO);
+                self.next_checked = false;
+                self.last_position = None;
+
+                self.clear_loop();
+                self.use_track_speed(&prepared.track);
+                self.publish(PlaybackStatus::TrackStarted(prepared.track));
+
+                if missed_opening {
+                    self.requeue_current(Duration::ZERO);
+                }
+            }
+            None => {
+                let next = self.queue.advance(self.repeat, false).cloned();
+
+                self.play(next);
+            }
+        }
+
+        self.publish_queue();
+
+        if sleep {
+            self.sleep_now();
+
+            // The next track may already have started, so it is put back to
+            // its beginning.
+            if self.active {
+                self.requeue_current(Duration::ZERO);
+            }
+        }
+    }
+
+    // Fades playback out as the sleep timer runs down, and pauses it when a
+    // timer of a fixed length runs out. Timers that end with a track go off
+    // as it finishes.
+    fn update_sleep(&mut self) {
+        let Some(remaining) = self.sleep_remaining() else {
+            return;
+        };
+
+        if remaining.is_zero() {
+            self.sleep_now();
+
+            return;
+        }
+
+        if !self.muted {
+            let fade = remaining.as_secs_f32() / SLEEP_FADE.as_secs_f32();
+
+            self.sink
+                .set_volume(volume_curve(self.volume) * fade.min(1.0));
+        }
+
+        let Some(sleep) = &mut self.sleep else {
+            return;
+        };
+
+        if sleep.published != Some(remaining.as_secs()) {
+            sleep.published = Some(remaining.as_secs());
+
+            self.publish(PlaybackStatus::SleepChanged(Some(remaining)));
+        }
+    }
+
+    // How long until the sleep timer goes off. Timers that end with a track
+    // have no time left to count while nothing is playing.
+    fn sleep_remaining(&self) -> Option<Duration> {
+        let sleep = self.sleep.as_ref()?;
+
+        if let SleepTimer::Minutes(_) = sleep.timer {
+            return Some(sleep.deadline.saturating_duration_since(Instant::now()));
+        }
+
+        if !self.active {
+            return None;
+        }
+
+        let current = self.queue.current()?;
+        let mut remaining = current.duration.saturating_sub(self.position());
+
+        if sleep.timer == SleepTimer::EndOfQueue {
+            remaining += self
+                .queue
+                .entries()
+                .skip(self.queue.position() + 1)
+                .map(|track| track.duration)
+                .sum::<Duration>();
+        }
+
+        Some(remaining.div_f32(self.speed))
+    }
+
+    // Pauses playback for the sleep timer and puts the volume back for when
+    // it is resumed.
+    fn sleep_now(&mut self) {
+        log::info!("Sleep timer went off.");
+
+        self.sink.pause();
+        self.sleep = None;
+        self.restore_volume();
+
+        self.publish(PlaybackStatus::SleepChanged(None));
+    }
+
+    fn restore_volume(&self) {
+        if !self.muted {
+            self.sink.set_volume(volume_curve(self.volume));
+        }
+    }
+
+    // Goes back to the start of the loop once its end is reached.
+    fn check_loop(&mut self) {
+        if let Some((start, end)) = self.ab_loop {
+            if self.position() >= end {
+                self.seek(start);
+            }
+        }
+    }
+
+    // Loops the current track from `start` to `end`, going back to `start`
+    // straight away if playback is outside the loop. An empty or backwards
+    // loop is refused, leaving any marked start in place.
+    fn set_loop(&mut self, start: Duration, end: Duration) {
+        if start >= end {
+            log::info!("Ignoring loop from {:?} to {:?}.", start, end);
+
+            return;
+        }
+
+        self.loop_start = None;
+        self.ab_loop = Some((start, end));
+
+        self.publish(PlaybackStatus::LoopStartChanged(None));
+        self.publish(PlaybackStatus::LoopChanged(self.ab_loop));
+
+        let position = self.position();
+
+        if position < start || position >= end {
+            self.seek(start);
+        }
+    }
+
+    fn clear_loop(&mut self) {
+        if self.loop_start.take().is_some() {
+            self.publish(PlaybackStatus::LoopStartChanged(None));
+        }
+
+        if self.ab_loop.take().is_some() {
+            self.publish(PlaybackStatus::LoopChanged(None));
+        }
+    }
+
+    fn prepare_if_due(&mut self) {
+        if self.next_checked || (!self.gapless && self.crossfade.is_zero()) {
+            return;
+        }
+
+        let Some(current) = self.queue.current() else {
+            return;
+        };
+
+        if current.duration.saturating_sub(self.position()) <= PRELOAD_WINDOW + self.crossfade {
+            self.prepare_next();
+        }
+    }
+
+    // Decodes the next track and queues it in the sink behind the current one.
+    // With crossfade on, its opening is handed to the current track to fade
+    // into and the queued source starts after it.
+    fn prepare_next(&mut self) {
+        self.next_checked = true;
+
+        let Some(track) = self.queue.peek_next(self.repeat).cloned() else {
+            return;
+        };
 
-                // find video_id in tracks and get all of the elements after that
-                let tracks = tracks.unwrap();
-                let index = tracks
-                    .iter()
-                    .position(|x| x.get("video_id").unwrap() == &video_id)
-                    .unwrap();
-                let tracks = &tracks[index..];
+        let fade = self.crossfade.min(track.duration / 2);
 
-                for track in tracks {
-                    let file = File::open(format!(
-                        "./data/audio/{}.mp3",
-                        track.get("video_id").unwrap()
-                    ))
-                    .unwrap();
-                    sink.append(rodio::Decoder::new(file).unwrap());
+        if !fade.is_zero() {
+            match open_track(&track, Duration::ZERO, self.normalisation) {
+                Ok(opening) => {
+                    *self.slot.lock().unwrap() = Some(Box::new(opening.take_duration(fade)));
                 }
+                Err(e) => {
+                    log::error!("Failed to prepare the next track: {}", e);
+                    return;
+                }
+            }
+        }
+
+        match open_track(&track, fade, self.normalisation) {
+            Ok(source) => {
+                let slot = NextSlot::default();
+
+                self.sink.append(self.chain(Crossfade::new(
+                    source,
+                    fade,
+                    track.duration,
+                    self.crossfade,
+                    slot.clone(),
+                )));
+
+                self.prepared = Some(Prepared {
+                    track,
+                    slot,
+                    offset: fade,
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to prepare the next track: {}", e);
+
+                self.slot.lock().unwrap().take();
+            }
+        }
+    }
+
+    // Queues the current track again if the one prepared after it is no
+    // longer next, e.g. after the queue was edited.
+    fn check_prepared(&mut self) {
+        let Some(prepared) = &self.prepared else {
+            return;
+        };
+
+        if self.queue.peek_next(self.repeat) != Some(&prepared.track) {
+            let position = self.position();
+
+            self.requeue_current(position);
+        }
+    }
+
+    // Replaces everything in the sink with the current track from `position`,
+    // dropping the prepared next track and any opening handed to the current
+    // one. The next track is prepared again when it is due.
+    fn requeue_current(&mut self, position: Duration) {
+        let Some(current) = self.queue.current().cloned() else {
+            return;
+        };
+
+        let paused = self.sink.is_paused();
+
+        self.sink.clear();
+
+        if let Err(e) = self.load(&current) {
+            log::error!("{}", e);
+            self.publish(PlaybackStatus::Error(e));
+            self.active = false;
+
+            return;
+        }
+
+        self.seek(position);
+
+        if !paused {
+            self.sink.play();
+        }
+    }
+
+    fn seek(&mut self, position: Duration) {
+        // A seek abandons a crossfade, so the opening of the next track has
+        // to be handed over again.
+        if self.prepared.is_some() && !self.crossfade.is_zero() {
+            self.requeue_current(position);
+
+            return;
+        }
+
+        // The sink scales seeks by its own speed, so the target is scaled back
+        // to land on `position` in the track.
+        let target = position.div_f32(self.sink_speed());
+
+        match self.sink.try_seek(target) {
+            // Seeking is done from the start of the track, whatever opening
+            // the source skipped.
+            Ok(_) => {
+                self.offset = Duration::ZERO;
+                self.clock = (target, position);
             }
+            Err(e) => {
+                log::error!("Failed to seek-to: {}", e);
+            }
+        }
+    }
+
+    // The position in the current track.
+    fn position(&self) -> Duration {
+        let played = self.sink.get_pos().saturating_sub(self.clock.0);
+
+        self.offset + self.clock.1 + played.mul_f32(self.speed)
+    }
+
+    // Wraps a track's source in the effects every source is played through.
+    fn chain(&self, source: Crossfade<TrackSource>) -> SinkSource {
+        Tap::new(
+            TimeStretch::new(
+                Equalizer::new(source, self.equalizer.clone()),
+                self.tempo.clone(),
+            ),
+            self.visualiser.clone(),
+        )
+    }
+
+    // Plays at the speed last chosen for `track`.
+    fn use_track_speed(&mut self, track: &Track) {
+        let speed = self
+            .speeds
+            .get(&track.music_id)
+            .copied()
+            .or(track.speed)
+            .unwrap_or(1.0);
+
+        if speed != self.speed {
+            self.speed = speed;
+            self.apply_speed();
+        }
+    }
+
+    // Sets the sink and time stretch to the current speed. With the pitch
+    // preserved the stretch changes the tempo, otherwise the sink plays the
+    // samples faster or slower.
+    fn apply_speed(&mut self) {
+        let position = self.position();
+
+        self.clock = (self.sink.get_pos(), position.saturating_sub(self.offset));
+
+        self.sink.set_speed(self.sink_speed());
+
+        if self.preserve_pitch {
+            stretch::set_tempo(&self.tempo, self.speed);
+        } else {
+            stretch::set_tempo(&self.tempo, 1.0);
+        }
+
+        self.publish(PlaybackStatus::SpeedChanged(self.speed));
+    }
+
+    // The speed the sink plays samples at, which is left at 1 while the time
+    // stretch changes the tempo instead.
+    fn sink_speed(&self) -> f32 {
+        if self.preserve_pitch {
+            1.0
+        } else {
+            self.speed
+        }
+    }
 
-            let file = File::open(format!("./data/audio/{}.mp3", video_id)).unwrap();
+    // Replaces the playing track, staying paused if playback was paused.
+    fn skip_to(&mut self, track: Option<Track>) {
+        let paused = self.sink.is_paused();
 
-            sink.append(rodio::Decoder::new(file).unwrap());
+        self.sink.clear();
+        self.play(track);
+
+        if !paused {
+            self.sink.play();
+        }
+    }
 
-            sink.play();
+    // Starts playing tracks added while nothing was playing. `was_empty` tells
+    // whether the new track is the current one, or comes after a queue that
+    // already ran out.
+    fn start_if_idle(&mut self, was_empty: bool) {
+        if self.active {
+            return;
         }
+
+        let track = if was_empty {
+            self.queue.current().cloned()
+        } else {
+            self.queue.advance(RepeatMode::Off, true).cloned()
+        };
+
+        self.play(track);
+        self.sink.play();
+    }
+
+    // Opens the chosen output device, or the default if it can't be, and
+    // moves playback over to it at the same position. With no device at all
+    // the player waits, trying again every `DEVICE_RETRY_INTERVAL`.
+    fn switch_output(&mut self) {
+        self.next_retry = Instant::now() + DEVICE_RETRY_INTERVAL;
+
+        let Some((output, sink, name)) = open_output(self.device.as_deref()) else {
+            log::error!("No audio device is available.");
+
+            if self.active {
+                self.resume = Some(self.position());
+            }
+
+            let idle = Sink::new_idle().0;
+
+            idle.set_volume(self.sink.volume());
+
+            if self.sink.is_paused() {
+                idle.pause();
+            }
+
+            self.sink = idle;
+            self.output = None;
+            self.active = false;
+            self.prepared = None;
+
+            if self.device_name.take().is_some() {
+                self.publish(PlaybackStatus::OutputChanged(None));
+            }
+
+            return;
+        };
+
+        log::info!("Playing to {}.", name);
+
+        let position = self
+            .resume
+            .take()
+            .or_else(|| self.active.then(|| self.position()));
+
+        sink.set_volume(self.sink.volume());
+
+        if self.sink.is_paused() {
+            sink.pause();
+        }
+
+        // The old sink is replaced before the old device is closed.
+        self.sink = sink;
+        self.output = Some(output);
+        self.device_name = Some(name.clone());
+
+        self.publish(PlaybackStatus::OutputChanged(Some(name)));
+
+        self.apply_speed();
+
+        if let Some(position) = position {
+            self.active = true;
+            self.requeue_current(position);
+        }
+    }
+
+    fn stop(&mut self, track: Track) {
+        self.sink.clear();
+        self.active = false;
+        self.prepared = None;
+
+        self.publish(PlaybackStatus::TrackEnded(track));
+    }
+
+    fn publish(&self, status: PlaybackStatus) {
+        if let Some(sender) = &self.status {
+            let _ = sender.send(status);
+        }
+    }
+
+    fn publish_queue(&self) {
+        self.publish(PlaybackStatus::QueueChanged(self.queue.clone()));
+    }
+
+    fn publish_position(&mut self) {
+        let position = self.position();
+
+        if self.last_position != Some(position.as_secs()) {
+            self.last_position = Some(position.as_secs());
+            self.publish(PlaybackStatus::Position(position));
+        }
+    }
+
+    // Publishes whether a track is playing once that changes. Nothing is
+    // playing while the sink is empty, which is also left paused by clearing
+    // it.
+    fn publish_paused(&mut self) {
+        let paused = !self.active || self.sink.is_paused();
+
+        if self.last_paused != Some(paused) {
+            self.last_paused = Some(paused);
+            self.publish(if paused {
+                PlaybackStatus::Paused
+            } else {
+                PlaybackStatus::Playing
+            });
+        }
+    }
+}
+
+// Streams the status of the playback thread to the UI.
+pub fn status_subscription(sender: mpsc::Sender<AudioEvent>) -> Subscription<PlaybackStatus> {
+    Subscription::run_with_id(
+        "playback-status",
+        stream::channel(100, move |mut output| async move {
+            let (status_sender, mut status_receiver) = async_mpsc::unbounded_channel();
+
+            if sender.send(AudioEvent::Subscribe(status_sender)).is_err() {
+                log::error!("Failed to subscribe to playback status.");
+            }
+
+            while let Some(status) = status_receiver.recv().await {
+                if output.send(status).await.is_err() {
+                    break;
+                }
+            }
+        }),
+    )
+}
+
+// The names of the output devices that can be played to.
+pub async fn output_devices() -> Vec<String> {
+    tokio::task::spawn_blocking(|| match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            log::error!("Failed to list output devices: {}", e);
+
+            Vec::new()
+        }
+    })
+    .await
+    .unwrap_or_default()
+}
+
+// Opens `device`, falling back to the default device if it is missing or
+// fails, and returns the stream, a sink playing to it and the device's name.
+fn open_output(device: Option<&str>) -> Option<(OutputStream, Sink, String)> {
+    let host = cpal::default_host();
+
+    if let Some(name) = device {
+        let found = host.output_devices().ok().and_then(|mut devices| {
+            devices.find(|device| device.name().is_ok_and(|device_name| device_name == name))
+        });
+
+        match found.map(|device| OutputStream::try_from_device(&device)) {
+            Some(Ok((output, handle))) => match Sink::try_new(&handle) {
+                Ok(sink) => return Some((output, sink, name.to_string())),
+                Err(e) => log::error!("Failed to play to {}: {}", name, e),
+            },
+            Some(Err(e)) => log::error!("Failed to open {}: {}", name, e),
+            None => log::error!("Output device {} was not found.", name),
+        }
+
+        log::info!("Falling back to the default output device.");
+    }
+
+    let (output, handle) = OutputStream::try_default().ok()?;
+    let sink = Sink::try_new(&handle).ok()?;
+
+    let name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok())
+        .unwrap_or_else(|| "System default".to_string());
+
+    Some((output, sink, name))
+}
+
+// Decodes `track`, skipping its first `start` of audio, at the gain
+// `normalisation` calls for.
+fn open_track(
+    track: &Track,
+    start: Duration,
+    normalisation: NormalisationMode,
+) -> Result<TrackSource, String> {
+    open_file(
+        &track.audio_path(),
+        &track.extension,
+        start,
+        loudness::gain(track, normalisation),
+    )
+}
+
+// Formats the decoder can't play are refused with "Unsupported audio format"
+// before the file is opened.
+fn open_file(
+    path: &Path,
+    extension: &str,
+    start: Duration,
+    gain: f32,
+) -> Result<TrackSource, String> {
+    if !is_supported(extension) {
+        return Err(format!("Unsupported audio format: {}", extension));
+    }
+
+    let source: Box<dyn Source<Item = f32> + Send> =
+        if MP4_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            Box::new(
+                FileSource::open(path)
+                    .map_err(|e| format!("Failed to decode {}: {:?}", path.display(), e))?,
+            )
+        } else {
+            let file = File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+            Box::new(
+                Decoder::new(file)
+                    .map_err(|e| format!("Failed to decode {}: {}", path.display(), e))?
+                    .convert_samples(),
+            )
+        };
+
+    Ok(source.skip_duration(start).amplify(gain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use rodio::buffer::SamplesBuffer;
+
+    fn fixture(extension: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(format!("short.{}", extension))
+    }
+
+    #[test]
+    fn decodes_every_supported_fixture() {
+        for extension in ["mp3", "flac", "ogg", "wav", "aac", "m4a"] {
+            assert!(is_supported(extension));
+
+            let source = open_file(&fixture(extension), extension, Duration::ZERO, 1.0)
+                .unwrap_or_else(|e| panic!("{}", e));
+
+            let rate = source.sample_rate() as f32 * source.channels() as f32;
+            let seconds = source.count() as f32 / rate;
+
+            // Each fixture is about a quarter of a second long.
+            assert!((0.2..0.3).contains(&seconds), "{}: {}s", extension, seconds);
+        }
+    }
+
+    #[test]
+    fn skips_start_of_fixture() {
+        let full = open_file(&fixture("wav"), "wav", Duration::ZERO, 1.0)
+            .unwrap()
+            .count();
+        let skipped = open_file(&fixture("wav"), "wav", Duration::from_millis(100), 1.0)
+            .unwrap()
+            .count();
+
+        assert_eq!(full - skipped, 4410);
+    }
+
+    #[test]
+    fn rejects_opus() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("track.opus");
+        std::fs::write(&path, b"OggS").unwrap();
+
+        assert!(!is_supported("opus"));
+        assert_eq!(
+            open_file(&path, "opus", Duration::ZERO, 1.0).err(),
+            Some("Unsupported audio format: opus".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_undecodable_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("track.mp3");
+        std::fs::write(&path, b"not audio").unwrap();
+
+        let error = open_file(&path, "mp3", Duration::ZERO, 1.0).err().unwrap();
+
+        assert!(error.starts_with("Failed to decode"), "{}", error);
+    }
+
+    #[test]
+    fn seeks_to_track_position_at_double_speed() {
+        let (sink, mut output) = Sink::new_idle();
+        let mut player = Player::new();
+
+        player.sink = sink;
+        player.speed = 2.0;
+        player.apply_speed();
+
+        // Each sample is one more than the second of the track it is at, so
+        // what is being played can be read back from the output.
+        let samples = (0..60_000)
+            .map(|i| 1.0 + i as f32 / 1000.0)
+            .collect::<Vec<_>>();
+
+        player.sink.append(SamplesBuffer::new(1, 1000, samples));
+        player.sink.pause();
+
+        let last = Arc::new(AtomicU32::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Pulls samples through the sink as an output device would.
+        let device = {
+            let last = last.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::SeqCst) {
+                    for _ in 0..20 {
+                        match output.next() {
+                            Some(sample) if sample != 0.0 => {
+                                last.store(sample.to_bits(), Ordering::SeqCst)
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    thread::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        player.seek(Duration::from_secs(10));
+
+        assert!((player.position().as_secs_f32() - 10.0).abs() < 0.01);
+
+        player.sink.play();
+        thread::sleep(Duration::from_millis(50));
+        player.sink.pause();
+        thread::sleep(Duration::from_millis(20));
+
+        let played = f32::from_bits(last.load(Ordering::SeqCst)) - 1.0;
+
+        stop.store(true, Ordering::SeqCst);
+        device.join().unwrap();
+
+        assert!(played > 10.0, "played up to {}", played);
+        assert!(
+            (player.position().as_secs_f32() - played).abs() < 0.1,
+            "position {:?}, played up to {}",
+            player.position(),
+            played
+        );
+    }
+
+    #[test]
+    fn refuses_empty_and_backwards_loops() {
+        let mut player = Player::new();
+        let at = |player: &mut Player, seconds| player.clock = (Duration::ZERO, secs(seconds));
+
+        player.active = true;
+
+        at(&mut player, 5);
+        process_audio_command(AudioEvent::SetLoopStart, &mut player);
+
+        assert_eq!(player.loop_start, Some(secs(5)));
+
+        // Marking the end where the loop starts, or before it, is ignored.
+        process_audio_command(AudioEvent::SetLoopEnd, &mut player);
+        at(&mut player, 2);
+        process_audio_command(AudioEvent::SetLoopEnd, &mut player);
+
+        assert_eq!(player.ab_loop, None);
+        assert_eq!(player.loop_start, Some(secs(5)));
+
+        // Playback already past the end goes back to the start.
+        at(&mut player, 8);
+        process_audio_command(AudioEvent::SetLoopEnd, &mut player);
+
+        assert_eq!(player.ab_loop, Some((secs(5), secs(8))));
+        assert_eq!(player.loop_start, None);
+        assert_eq!(player.position(), secs(5));
+    }
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
     }
 }
@@ -0,0 +1,479 @@
+use super::db::Track;
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+impl RepeatMode {
+    // The mode the repeat button switches to next.
+    pub fn cycle(self) -> Self {
+        match self {
+            Self::Off => Self::All,
+            Self::All => Self::One,
+            Self::One => Self::Off,
+        }
+    }
+}
+
+// The tracks queued for playback. `tracks` keeps the order they were queued
+// in, while `order` is the order they are played in: the identity when not
+// shuffled, or a permutation of it when shuffled. Keeping both means shuffle
+// can be turned off again without losing the original order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Queue {
+    tracks: Vec<Track>,
+    order: Vec<usize>,
+    position: usize,
+}
+
+impl Queue {
+    // Queues `tracks`, starting playback at `tracks[start]`.
+    pub fn new(tracks: Vec<Track>, start: usize, shuffle: bool) -> Self {
+        let mut queue = Self {
+            order: (0..tracks.len()).collect(),
+            position: start.min(tracks.len().saturating_sub(1)),
+            tracks,
+        };
+
+        if shuffle {
+            queue.shuffle();
+        }
+
+        queue
+    }
+
+    // Queues `tracks` starting from the given track, or just that track when it
+    // isn't in the list.
+    pub fn from_track(track: Track, tracks: Option<Vec<Track>>, shuffle: bool) -> Self {
+        let tracks = tracks.unwrap_or_default();
+
+        match tracks.iter().position(|x| x.video_id == track.video_id) {
+            Some(index) => Self::new(tracks, index, shuffle),
+            None => Self::new(vec![track], 0, shuffle),
+        }
+    }
+
+    // Rebuilds a saved queue from its tracks in queued order, its play order
+    // and position. Tracks that no longer exist are given as None and left
+    // out; if the current one is gone the track after it becomes current.
+    pub fn restore(tracks: Vec<Option<Track>>, order: Vec<usize>, position: usize) -> Self {
+        let mut sorted = order.clone();
+        sorted.sort_unstable();
+
+        // A play order that doesn't cover every track exactly once is ignored.
+        let order = if sorted.into_iter().eq(0..tracks.len()) {
+            order
+        } else {
+            (0..tracks.len()).collect()
+        };
+
+        let mut new_indices = Vec::with_capacity(tracks.len());
+        let mut kept = Vec::new();
+
+        for track in tracks {
+            new_indices.push(track.is_some().then_some(kept.len()));
+            kept.extend(track);
+        }
+
+        let position = order
+            .iter()
+            .take(position)
+            .filter(|&&index| new_indices[index].is_some())
+            .count();
+
+        let order: Vec<usize> = order
+            .into_iter()
+            .filter_map(|index| new_indices[index])
+            .collect();
+
+        Self {
+            position: position.min(order.len().saturating_sub(1)),
+            order,
+            tracks: kept,
+        }
+    }
+
+    // The queued tracks in the order they were queued, and the play order as
+    // indices into them, for saving the queue.
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    // The index of the current track in play order.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    // The queued tracks in the order they are played, including those already
+    // played.
+    pub fn entries(&self) -> impl Iterator<Item = &Track> {
+        self.order
+            .iter()
+            .filter_map(|&index| self.tracks.get(index))
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.order
+            .get(self.position)
+            .and_then(|&index| self.tracks.get(index))
+    }
+
+    // Moves to the track played after the current one. Repeat-one only holds
+    // on the current track when it ends by itself; skipping always moves on.
+    pub fn advance(&mut self, repeat: RepeatMode, skipped: bool) -> Option<&Track> {
+        self.position = self.next_position(repeat, skipped)?;
+
+        self.current()
+    }
+
+    // The track played after the current one when it ends by itself.
+    pub fn peek_next(&self, repeat: RepeatMode) -> Option<&Track> {
+        self.next_position(repeat, false)
+            .and_then(|position| self.order.get(position))
+            .and_then(|&index| self.tracks.get(index))
+    }
+
+    fn next_position(&self, repeat: RepeatMode, skipped: bool) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        if repeat == RepeatMode::One && !skipped {
+            return Some(self.position);
+        }
+
+        if self.position + 1 < self.order.len() {
+            Some(self.position + 1)
+        } else if repeat != RepeatMode::Off {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    // Moves to the track played before the current one, wrapping around to the
+    // end of the queue when repeating all.
+    pub fn previous(&mut self, repeat: RepeatMode) -> Option<&Track> {
+        if self.is_empty() {
+            return None;
+        }
+
+        if self.position > 0 {
+            self.position -= 1;
+        } else if repeat == RepeatMode::All {
+            self.position = self.order.len() - 1;
+        }
+
+        self.current()
+    }
+
+    // Shuffles the play order, keeping the current track where it is and
+    // moving it to the front so every other track is still to come.
+    pub fn shuffle(&mut self) {
+        let Some(&current) = self.order.get(self.position) else {
+            return;
+        };
+
+        let mut rest: Vec<usize> = (0..self.tracks.len()).filter(|&i| i != current).collect();
+        rest.shuffle(&mut rand::thread_rng());
+
+        self.order = std::iter::once(current).chain(rest).collect();
+        self.position = 0;
+    }
+
+    // Restores the order the tracks were queued in, continuing from the
+    // current track.
+    pub fn unshuffle(&mut self) {
+        let current = self.order.get(self.position).copied().unwrap_or(0);
+
+        self.order = (0..self.tracks.len()).collect();
+        self.position = current;
+    }
+
+    // Queues `track` to play straight after the current one.
+    pub fn play_next(&mut self, track: Track) {
+        let index = self.push(track);
+
+        let at = (self.position + 1).min(self.order.len());
+        self.order.insert(at, index);
+    }
+
+    // Queues `track` after everything else.
+    pub fn enqueue(&mut self, track: Track) {
+        let index = self.push(track);
+
+        self.order.push(index);
+    }
+
+    fn push(&mut self, track: Track) -> usize {
+        self.tracks.push(track);
+
+        self.tracks.len() - 1
+    }
+
+    // Removes the track at `index` in play order. When the current track is
+    // removed the following one becomes current.
+    pub fn remove(&mut self, index: usize) -> Option<Track> {
+        if index >= self.order.len() {
+            return None;
+        }
+
+        let removed = self.order.remove(index);
+
+        for entry in self.order.iter_mut() {
+            if *entry > removed {
+                *entry -= 1;
+            }
+        }
+
+        if index < self.position {
+            self.position -= 1;
+        }
+
+        self.position = self.position.min(self.order.len().saturating_sub(1));
+
+        Some(self.tracks.remove(removed))
+    }
+
+    // Moves the track at `from` in play order to `to`, keeping the current
+    // track current.
+    pub fn move_track(&mut self, from: usize, to: usize) {
+        if from >= self.order.len() || to >= self.order.len() {
+            return;
+        }
+
+        let entry = self.order.remove(from);
+        self.order.insert(to, entry);
+
+        if from == self.position {
+            self.position = to;
+        } else if from < self.position && to >= self.position {
+            self.position -= 1;
+        } else if from > self.position && to <= self.position {
+            self.position += 1;
+        }
+    }
+
+    // Removes every track but the current one. The queue is trimmed in place
+    // rather than rebuilt, so nothing else about it is reset.
+    pub fn clear(&mut self) {
+        let Some(current) = self.order.get(self.position).copied() else {
+            return;
+        };
+
+        self.tracks = vec![self.tracks.swap_remove(current)];
+        self.order = vec![0];
+        self.position = 0;
+    }
+
+    // Makes the track at `index` in play order current.
+    pub fn jump_to(&mut self, index: usize) -> Option<&Track> {
+        if index >= self.order.len() {
+            return None;
+        }
+
+        self.position = index;
+
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::core::db::tests::track;
+
+    fn queue(len: usize, start: usize) -> Queue {
+        Queue::new((0..len as i32).map(track).collect(), start, false)
+    }
+
+    // The ids of the queued tracks in play order.
+    fn ids(queue: &Queue) -> Vec<String> {
+        queue
+            .entries()
+            .map(|track| track.video_id.clone())
+            .collect()
+    }
+
+    fn current(queue: &Queue) -> String {
+        queue.current().unwrap().video_id.clone()
+    }
+
+    #[test]
+    fn shuffle_keeps_current_track_first() {
+        for _ in 0..20 {
+            let mut queue = queue(10, 4);
+
+            queue.shuffle();
+
+            assert_eq!(queue.position(), 0);
+            assert_eq!(current(&queue), "4");
+
+            let mut order = queue.order().to_vec();
+            order.sort_unstable();
+
+            assert_eq!(order, (0..10).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn unshuffle_restores_queued_order_at_current_track() {
+        let mut queue = queue(10, 4);
+
+        queue.shuffle();
+        queue.advance(RepeatMode::Off, true);
+
+        let playing = current(&queue);
+
+        queue.unshuffle();
+
+        assert_eq!(
+            ids(&queue),
+            (0..10).map(|id| id.to_string()).collect::<Vec<_>>()
+        );
+        assert_eq!(current(&queue), playing);
+        assert_eq!(queue.position().to_string(), playing);
+    }
+
+    #[test]
+    fn remove_before_current_keeps_current() {
+        let mut queue = queue(5, 2);
+
+        assert_eq!(queue.remove(0).unwrap().video_id, "0");
+        assert_eq!(current(&queue), "2");
+        assert_eq!(queue.position(), 1);
+        assert_eq!(ids(&queue), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn remove_current_moves_to_following_track() {
+        let mut queue = queue(5, 2);
+
+        assert_eq!(queue.remove(2).unwrap().video_id, "2");
+        assert_eq!(current(&queue), "3");
+        assert_eq!(queue.position(), 2);
+
+        let mut queue = self::queue(3, 2);
+
+        queue.remove(2);
+
+        assert_eq!(current(&queue), "1");
+    }
+
+    #[test]
+    fn remove_after_current_keeps_current() {
+        let mut queue = queue(5, 2);
+
+        assert_eq!(queue.remove(4).unwrap().video_id, "4");
+        assert_eq!(current(&queue), "2");
+        assert_eq!(queue.position(), 2);
+        assert_eq!(queue.remove(4), None);
+    }
+
+    #[test]
+    fn remove_from_shuffled_queue_keeps_order() {
+        let mut queue = queue(5, 0);
+
+        queue.shuffle();
+
+        let mut expected = ids(&queue);
+        let removed = expected.remove(3);
+
+        assert_eq!(queue.remove(3).unwrap().video_id, removed);
+        assert_eq!(ids(&queue), expected);
+    }
+
+    #[test]
+    fn move_track_keeps_current_track_current() {
+        let mut queue = queue(5, 2);
+
+        queue.move_track(0, 4);
+
+        assert_eq!(ids(&queue), ["1", "2", "3", "4", "0"]);
+        assert_eq!(current(&queue), "2");
+
+        queue.move_track(4, 0);
+
+        assert_eq!(ids(&queue), ["0", "1", "2", "3", "4"]);
+        assert_eq!(current(&queue), "2");
+
+        queue.move_track(2, 4);
+
+        assert_eq!(ids(&queue), ["0", "1", "3", "4", "2"]);
+        assert_eq!(queue.position(), 4);
+
+        queue.move_track(0, 5);
+
+        assert_eq!(ids(&queue), ["0", "1", "3", "4", "2"]);
+    }
+
+    #[test]
+    fn play_next_queues_after_current_track() {
+        let mut queue = queue(3, 1);
+
+        queue.play_next(track(7));
+
+        assert_eq!(ids(&queue), ["0", "1", "7", "2"]);
+        assert_eq!(queue.advance(RepeatMode::Off, true).unwrap().video_id, "7");
+
+        let mut queue = Queue::default();
+
+        queue.play_next(track(7));
+
+        assert_eq!(current(&queue), "7");
+    }
+
+    #[test]
+    fn clear_keeps_only_current_track() {
+        let mut queue = queue(5, 3);
+
+        queue.shuffle();
+        queue.clear();
+
+        assert_eq!(ids(&queue), ["3"]);
+        assert_eq!(queue.position(), 0);
+
+        queue.enqueue(track(8));
+        queue.unshuffle();
+
+        assert_eq!(ids(&queue), ["3", "8"]);
+        assert_eq!(current(&queue), "3");
+    }
+
+    #[test]
+    fn restore_drops_missing_tracks() {
+        let tracks = vec![Some(track(0)), None, Some(track(2)), Some(track(3))];
+
+        // Playing the missing track, which moves on to the one after it.
+        let queue = Queue::restore(tracks.clone(), vec![3, 1, 0, 2], 1);
+
+        assert_eq!(ids(&queue), ["3", "0", "2"]);
+        assert_eq!(current(&queue), "0");
+
+        // An order that doesn't cover every track falls back to queued order.
+        let queue = Queue::restore(tracks, vec![0, 0, 1, 2], 2);
+
+        assert_eq!(ids(&queue), ["0", "2", "3"]);
+        assert_eq!(current(&queue), "2");
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::path::PathBuf;
 
+use super::db;
+
 use iced::advanced::image::Bytes;
 use iced::futures;
 use iced::Subscription;
@@ -28,14 +30,10 @@ pub async fn request_thumbnail(url: String) -> Result<Bytes, reqwest::Error> {
     Ok(bytes)
 }
 
-pub async fn request_all_thumbnails(
-    results: Vec<HashMap<String, String>>,
-) -> Result<Vec<Vec<u8>>, RequestError> {
+pub async fn request_all_thumbnails(urls: Vec<String>) -> Result<Vec<Vec<u8>>, RequestError> {
     let mut thumbnails = Vec::new();
 
-    for result in results {
-        let url = result.get("thumbnail").unwrap().clone();
-
+    for url in urls {
         let bytes = request_thumbnail(url)
             .await
             .map_err(|_| RequestError::RequestError)?;
@@ -86,12 +84,12 @@ pub async fn request_thumbnails(
 }
 
 pub async fn request_thumbnail_from_playlist(
-    results: Vec<HashMap<String, String>>,
+    tracks: Vec<db::Track>,
 ) -> Vec<HashMap<String, iced::advanced::image::Handle>> {
     let mut handles = Vec::new();
 
-    for result in results {
-        let video_id = result.get("video_id").unwrap().clone();
+    for track in tracks {
+        let video_id = track.video_id;
 
         let mut dir = tokio::fs::read_dir("./data/thumbnails")
             .await
@@ -7,8 +7,8 @@ use discord_presence::Client;
 
 #[derive(Debug, Clone)]
 pub enum RpcEvent {
-    Set(String, String),
-    SetProgress(String, String, String),
+    Set(String, u64),
+    SetProgress(String, u64, u64),
     Hide,
 }
 
@@ -31,9 +31,8 @@ pub fn start_receiver(reciever: mpsc::Receiver<RpcEvent>) {
 fn process_rpc_command(command: RpcEvent, client: &mut Client) {
     match command {
         RpcEvent::Set(display_name, duration) => {
-            let duration_u64: u64 = duration.parse().expect("Failed to parse duration");
             let _ = client.set_activity(|a| {
-                a.state(format!("0:00 / {}", format::duration(duration_u64)).as_str())
+                a.state(format!("0:00 / {}", format::duration(duration)).as_str())
                     .assets(|ass| {
                         ass.large_image("icon")
                             .large_text("wavey by ISgood Development")
@@ -44,14 +43,11 @@ fn process_rpc_command(command: RpcEvent, client: &mut Client) {
         }
 
         RpcEvent::SetProgress(display_name, progress, duration) => {
-            let duration_u64: u64 = duration.parse().expect("Failed to parse duration");
-            let progress_u64: u64 = progress.parse().expect("Failed to parse progress");
-
             let _ = client.set_activity(|a: discord_presence::models::Activity| {
                 a.state(format!(
                     "{} / {}",
-                    format::duration(progress_u64),
-                    format::duration(duration_u64)
+                    format::duration(progress),
+                    format::duration(duration)
                 ))
                 .assets(|ass| {
                     ass.large_image("icon")
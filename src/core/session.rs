@@ -0,0 +1,130 @@
+use std::time::Duration;
+
+use super::db::{self, MusicId};
+use super::queue::{Queue, RepeatMode};
+
+use log;
+use serde::{Deserialize, Serialize};
+
+const SESSION_PATH: &str = "./data/session.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionError {
+    WriteError(String),
+}
+
+// What was playing when wavey was last closed. The queue is stored as the ids
+// of its tracks so that edits made to them since are picked up on restore.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub tracks: Vec<i32>,
+    pub order: Vec<usize>,
+    pub position: usize,
+    pub seconds: u64,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+}
+
+// A saved session with its tracks loaded back from the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestoredSession {
+    pub queue: Queue,
+    pub position: Duration,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+}
+
+impl Session {
+    pub fn new(queue: &Queue, seconds: u64, shuffle: bool, repeat: RepeatMode) -> Self {
+        Self {
+            tracks: queue
+                .tracks()
+                .iter()
+                .map(|track| track.music_id.0)
+                .collect(),
+            order: queue.order().to_vec(),
+            position: queue.position(),
+            seconds,
+            shuffle,
+            repeat,
+        }
+    }
+}
+
+// Writes the session to a temporary file first and moves it into place, so
+// closing wavey mid-write never leaves a half-written session behind.
+pub async fn save(session: Session) -> Result<(), SessionError> {
+    let data = serde_json::to_string_pretty(&session)
+        .map_err(|e| SessionError::WriteError(e.to_string()))?;
+
+    let temporary = format!("{}.tmp", SESSION_PATH);
+
+    tokio::fs::write(&temporary, data)
+        .await
+        .map_err(|e| SessionError::WriteError(e.to_string()))?;
+
+    tokio::fs::rename(&temporary, SESSION_PATH)
+        .await
+        .map_err(|e| SessionError::WriteError(e.to_string()))
+}
+
+// Loads the last session, if there was one with anything queued. Tracks that
+// have been deleted since are dropped from the queue.
+pub async fn restore() -> Option<RestoredSession> {
+    let contents = tokio::fs::read_to_string(SESSION_PATH).await.ok()?;
+
+    let session: Session = match serde_json::from_str(&contents) {
+        Ok(session) => session,
+        Err(e) => {
+            log::error!("Error parsing session file: {}", e);
+
+            return None;
+        }
+    };
+
+    if session.tracks.is_empty() {
+        return None;
+    }
+
+    log::info!("Restoring last session.");
+
+    let current = session
+        .order
+        .get(session.position)
+        .and_then(|&index| session.tracks.get(index))
+        .copied();
+
+    let mut tracks = Vec::with_capacity(session.tracks.len());
+
+    for &id in &session.tracks {
+        match db::get_music_from_id(MusicId(id)).await {
+            Ok(track) => tracks.push(Some(track)),
+            Err(db::DatabaseError::NotFound) => tracks.push(None),
+            Err(e) => {
+                log::error!("Failed to restore session: {:?}", e);
+
+                return None;
+            }
+        }
+    }
+
+    let queue = Queue::restore(tracks, session.order, session.position);
+
+    if queue.is_empty() {
+        return None;
+    }
+
+    // The saved position only applies if the track it was in still exists.
+    let position = if queue.current().map(|track| track.music_id.0) == current {
+        Duration::from_secs(session.seconds)
+    } else {
+        Duration::ZERO
+    };
+
+    Some(RestoredSession {
+        queue,
+        position,
+        shuffle: session.shuffle,
+        repeat: session.repeat,
+    })
+}
@@ -0,0 +1,510 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::db;
+use super::direct::DirectSource;
+use super::import;
+use super::json;
+use super::transcode;
+use super::youtube::YouTubeSource;
+use crate::state::AppSettings;
+
+use iced::futures::future::BoxFuture;
+use iced::futures::stream::BoxStream;
+use iced::futures::StreamExt;
+use log;
+use tokio::fs;
+
+// Every source a link can be downloaded from, in the order they are asked
+// whether they recognise it.
+const SOURCES: &[&dyn MusicSource] = &[&YouTubeSource, &DirectSource];
+
+const AUDIO_DIRECTORY: &str = "./data/audio";
+
+// How far a download has got, reported as it reaches each stage.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Progress {
+    FetchingInfo,
+    Title(String),
+    Downloading(f32),
+    Converting,
+    Thumbnail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusError {
+    NetworkError,
+    VideoNotFound,
+    UnknownError,
+    // The ffmpeg that couldn't be run.
+    FFmpegNotFound(String),
+    // Why ffmpeg failed, as it reported it.
+    FFmpegConversionError(String),
+    VideoOptionError,
+    VideoInfoError,
+    DownloadError,
+    ThumbnailError,
+    WriteError,
+    Unsupported,
+    // The download couldn't be added to the library.
+    DatabaseError(db::DatabaseError),
+}
+
+// A track found by searching a source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub url: String,
+    pub title: String,
+    pub channel: String,
+    pub thumbnail: String,
+}
+
+// What a source knows about a track before downloading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    // The id the track is stored under in `data/audio` and the database.
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    // None if the source doesn't know, in which case it is read from the
+    // downloaded file.
+    pub duration: Option<Duration>,
+    // The format the audio downloads in. Anything that can't be played is
+    // converted once downloaded.
+    pub extension: String,
+    pub thumbnail: Option<String>,
+}
+
+// The audio of a track as it downloads, and its size in bytes if known.
+pub struct AudioStream {
+    pub length: Option<u64>,
+    pub chunks: BoxStream<'static, Result<Vec<u8>, StatusError>>,
+}
+
+// Somewhere tracks can be searched for and downloaded from.
+pub trait MusicSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // The id of the track `url` points at, or None if this source doesn't
+    // recognise it.
+    fn resolve(&self, url: &str) -> Option<String>;
+
+    fn search(&self, query: String) -> BoxFuture<'static, Result<Vec<SearchResult>, StatusError>>;
+
+    fn metadata(&self, url: String) -> BoxFuture<'static, Result<TrackInfo, StatusError>>;
+
+    fn download(&self, info: TrackInfo) -> BoxFuture<'static, Result<AudioStream, StatusError>>;
+
+    // The track's cover, if the source has one for it.
+    fn thumbnail(
+        &self,
+        info: TrackInfo,
+    ) -> BoxFuture<'static, Result<Option<Vec<u8>>, StatusError>>;
+}
+
+// The source that can download `url`, if any.
+pub fn find(url: &str) -> Option<&'static dyn MusicSource> {
+    SOURCES
+        .iter()
+        .copied()
+        .find(|source| source.resolve(url).is_some())
+}
+
+// The source searches typed into the add music page go to.
+pub fn search_source() -> &'static dyn MusicSource {
+    &YouTubeSource
+}
+
+// Removes what a download has written so far if it doesn't finish, whether
+// it failed or was cancelled. Once the track is being added to the library
+// its files are kept, as is the downloaded file if only its conversion
+// failed.
+struct PartialFiles {
+    paths: Vec<PathBuf>,
+    committed: bool,
+}
+
+impl Drop for PartialFiles {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Downloads the track at `url` from whichever source recognises it, converts
+// it if needed and adds it to the library, calling `progress` as each stage
+// is reached.
+pub async fn download_from_url(
+    url: String,
+    progress: impl Fn(Progress),
+) -> Result<(), StatusError> {
+    let source = find(&url).ok_or(StatusError::Unsupported)?;
+
+    // A track that is already in the library, e.g. one retried after it was
+    // added, isn't downloaded again.
+    if let Some(id) = source.resolve(&url) {
+        if db::has_music(id).await.unwrap_or(false) {
+            log::info!("{} is already in the library.", url);
+
+            return Ok(());
+        }
+    }
+
+    log::info!("Downloading {} from {}.", url, source.name());
+
+    let settings = json::load_settings().await.unwrap_or_default();
+    let (info, extension) = fetch(
+        source,
+        url,
+        Path::new(AUDIO_DIRECTORY),
+        &settings,
+        &progress,
+    )
+    .await?;
+
+    progress(Progress::Thumbnail);
+
+    // The track is added to the library even if the download is cancelled
+    // from here, so it is never left half added.
+    tokio::spawn(add_to_library(
+        source,
+        info,
+        extension,
+        PathBuf::from(AUDIO_DIRECTORY),
+    ))
+    .await
+    .map_err(|_| StatusError::UnknownError)?
+}
+
+// Downloads the track at `url` from `source` into `directory` and converts it
+// as `settings` ask, returning what the source knew of it and the extension
+// it ended up with. Nothing is left in `directory` unless it succeeds.
+async fn fetch(
+    source: &dyn MusicSource,
+    url: String,
+    directory: &Path,
+    settings: &AppSettings,
+    progress: &impl Fn(Progress),
+) -> Result<(TrackInfo, String), StatusError> {
+    progress(Progress::FetchingInfo);
+
+    let info = source.metadata(url).await?;
+
+    progress(Progress::Title(info.title.clone()));
+
+    let ffmpeg = transcode::ffmpeg_binary(&settings.ffmpeg_path);
+    let target = transcode::target(settings.download_format, &info.extension);
+
+    // Better to find out ffmpeg is missing before the download than after it.
+    if target.is_some() {
+        transcode::preflight(&ffmpeg).await?;
+    }
+
+    progress(Progress::Downloading(0.0));
+
+    let path = directory.join(format!("{}.{}", info.id, info.extension));
+    let output = target.map(|format| directory.join(format!("{}.{}", info.id, format.extension())));
+
+    let mut partial = PartialFiles {
+        paths: std::iter::once(path.clone())
+            .chain(output.clone())
+            .collect(),
+        committed: false,
+    };
+
+    let mut stream = source.download(info.clone()).await?;
+    let mut file = std::fs::File::create(&path).map_err(|_| StatusError::WriteError)?;
+    let mut written = 0;
+
+    while let Some(chunk) = stream.chunks.next().await {
+        let chunk = chunk?;
+
+        file.write_all(&chunk)
+            .map_err(|_| StatusError::WriteError)?;
+
+        written += chunk.len() as u64;
+
+        if let Some(length) = stream.length.filter(|&length| length > 0) {
+            progress(Progress::Downloading(
+                (written as f32 / length as f32).min(1.0),
+            ));
+        }
+    }
+
+    drop(file);
+
+    let extension = match target.zip(output) {
+        Some((format, output)) => {
+            progress(Progress::Converting);
+
+            if let Err(error) =
+                transcode::convert(&ffmpeg, &path, &output, format, settings.download_bitrate).await
+            {
+                // Keep what was downloaded so it isn't lost to a bad ffmpeg
+                // setting and can be converted by hand.
+                partial.paths.retain(|partial_path| *partial_path != path);
+
+                log::error!(
+                    "Failed to convert {}, keeping the download: {:?}",
+                    path.display(),
+                    error
+                );
+
+                return Err(error);
+            }
+
+            if let Err(e) = fs::remove_file(&path).await {
+                log::error!("Failed to remove {}: {}", path.display(), e);
+            }
+
+            format.extension().to_string()
+        }
+        None => info.extension.clone(),
+    };
+
+    partial.committed = true;
+
+    Ok((info, extension))
+}
+
+async fn add_to_library(
+    source: &'static dyn MusicSource,
+    info: TrackInfo,
+    extension: String,
+    directory: PathBuf,
+) -> Result<(), StatusError> {
+    // Downloads rarely carry tags, but keep whatever survived the conversion.
+    let audio_path = directory.join(format!("{}.{}", info.id, extension));
+    let probed = {
+        let audio_path = audio_path.clone();
+        let extension = extension.clone();
+
+        match tokio::task::spawn_blocking(move || import::probe_file(&audio_path, &extension)).await
+        {
+            Ok(Ok(probed)) => Some(probed),
+            Ok(Err(e)) => {
+                log::error!("Failed to read downloaded file: {:?}", e);
+
+                None
+            }
+            Err(e) => {
+                log::error!("Tag reader panicked: {}", e);
+
+                None
+            }
+        }
+    };
+
+    let (tags, cover_art, probed_duration) = match probed {
+        Some(probed) => (probed.tags, probed.cover_art, probed.duration),
+        None => (Default::default(), None, Duration::ZERO),
+    };
+
+    // Prefer the title tag, falling back to the title the source gave.
+    let display_name = tags.title.clone().unwrap_or(info.title.clone());
+
+    if let Err(e) = db::add_music(
+        info.id.clone(),
+        extension,
+        display_name,
+        info.duration.unwrap_or(probed_duration),
+        tags,
+    )
+    .await
+    {
+        log::error!("Failed to add music to database: {:?}", e);
+
+        // Nothing refers to the file without its row, so it goes too and the
+        // download can be retried.
+        if let Err(e) = fs::remove_file(&audio_path).await {
+            log::error!("Failed to remove {}: {}", audio_path.display(), e);
+        }
+
+        return Err(StatusError::DatabaseError(e));
+    }
+
+    // The track is already in the library, so a missing thumbnail only
+    // leaves it without a cover.
+    let thumbnail = match source.thumbnail(info.clone()).await {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => {
+            log::error!("Failed to fetch thumbnail of {}: {:?}", info.id, e);
+
+            None
+        }
+    };
+
+    let (thumbnail, image_extension) = match thumbnail {
+        Some(thumbnail) => (thumbnail, "jpg"),
+        None => match cover_art {
+            Some(cover_art) => cover_art,
+            None => return Ok(()),
+        },
+    };
+
+    let thumbnail_path = format!("./data/thumbnails/{}.{}", info.id, image_extension);
+
+    if let Err(e) = fs::write(thumbnail_path, thumbnail).await {
+        log::error!("Failed to write thumbnail of {}: {}", info.id, e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use crate::core::direct::tests::{audio_server, block_on, fixture, serve};
+    use crate::core::transcode::OutputFormat;
+
+    // Answers every request with the headers for the whole fixture but only
+    // half of it, as a connection dropped part way through would.
+    fn truncated_server(_path: &str) -> Vec<Vec<u8>> {
+        let body = fixture("short.mp3");
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+
+        vec![header.into_bytes(), body[..body.len() / 2].to_vec()]
+    }
+
+    fn settings() -> AppSettings {
+        AppSettings {
+            download_format: OutputFormat::Original,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn downloads_into_directory_with_progress() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!("{}/short.mp3", serve(audio_server));
+        let reported = Mutex::new(Vec::new());
+
+        let (info, extension) = block_on(fetch(
+            &DirectSource,
+            url,
+            directory.path(),
+            &settings(),
+            &|progress| reported.lock().unwrap().push(progress),
+        ))
+        .unwrap();
+
+        assert_eq!(extension, "mp3");
+        assert_eq!(
+            std::fs::read(directory.path().join(format!("{}.mp3", info.id))).unwrap(),
+            fixture("short.mp3")
+        );
+
+        let reported = reported.into_inner().unwrap();
+
+        assert_eq!(
+            reported[..3],
+            [
+                Progress::FetchingInfo,
+                Progress::Title("short".to_string()),
+                Progress::Downloading(0.0)
+            ]
+        );
+
+        let downloading: Vec<f32> = reported
+            .iter()
+            .filter_map(|progress| match progress {
+                Progress::Downloading(fraction) => Some(*fraction),
+                _ => None,
+            })
+            .collect();
+
+        assert!(downloading.len() > 2, "{:?}", downloading);
+        assert!(downloading.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(downloading.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn removes_partial_download_on_error() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!("{}/short.mp3", serve(truncated_server));
+
+        let result = block_on(fetch(
+            &DirectSource,
+            url,
+            directory.path(),
+            &settings(),
+            &|_| {},
+        ));
+
+        assert_eq!(result, Err(StatusError::DownloadError));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn download_from_url_rejects_unknown_links() {
+        let result = block_on(download_from_url(
+            "https://example.com/track.txt".to_string(),
+            |_| {},
+        ));
+
+        assert_eq!(result, Err(StatusError::Unsupported));
+    }
+
+    #[test]
+    fn leaves_nothing_for_missing_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!("{}/missing.mp3", serve(audio_server));
+        let reported = Mutex::new(Vec::new());
+
+        let result = block_on(fetch(
+            &DirectSource,
+            url,
+            directory.path(),
+            &settings(),
+            &|progress| reported.lock().unwrap().push(progress),
+        ));
+
+        assert_eq!(result, Err(StatusError::VideoNotFound));
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+        assert_eq!(
+            reported.into_inner().unwrap().last(),
+            Some(&Progress::Downloading(0.0))
+        );
+    }
+
+    #[test]
+    fn removes_download_the_library_refuses() {
+        let directory = tempfile::tempdir().unwrap();
+        let url = format!("{}/short.mp3", serve(audio_server));
+
+        let (info, extension) = block_on(fetch(
+            &DirectSource,
+            url,
+            directory.path(),
+            &settings(),
+            &|_| {},
+        ))
+        .unwrap();
+
+        // The database isn't running in tests, so adding the track fails.
+        let result = block_on(add_to_library(
+            &DirectSource,
+            info,
+            extension,
+            directory.path().to_path_buf(),
+        ));
+
+        assert_eq!(
+            result,
+            Err(StatusError::DatabaseError(db::DatabaseError::Disconnected))
+        );
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+    }
+}
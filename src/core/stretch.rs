@@ -0,0 +1,353 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::Source;
+
+// The tempo every playing source reads, stored as the bits of an f32 so it can
+// change without a lock in the audio callback.
+pub type Tempo = Arc<AtomicU32>;
+
+// Length of each grain of audio that is overlapped, and how far either side
+// of its nominal position the best match is searched for.
+const GRAIN: Duration = Duration::from_millis(40);
+const TOLERANCE: Duration = Duration::from_millis(5);
+
+pub fn new_tempo(value: f32) -> Tempo {
+    Arc::new(AtomicU32::new(value.to_bits()))
+}
+
+pub fn set_tempo(tempo: &Tempo, value: f32) {
+    tempo.store(value.to_bits(), Ordering::Relaxed);
+}
+
+fn get_tempo(tempo: &Tempo) -> f32 {
+    f32::from_bits(tempo.load(Ordering::Relaxed))
+}
+
+// Changes the tempo of a source without changing its pitch, using WSOLA: the
+// input is cut into overlapping grains spaced by the tempo, and each grain is
+// shifted slightly to where it best lines up with the one before so the
+// overlap doesn't cancel out. Until the tempo first moves away from 1 the
+// source is passed through untouched.
+pub struct TimeStretch<S> {
+    input: S,
+    tempo: Tempo,
+    channels: usize,
+    window: Vec<f32>,
+    hop: usize,
+    tolerance: usize,
+    engaged: bool,
+    // Whether the input has run out; past its end it reads as silence.
+    exhausted: bool,
+    // How many whole frames the input had, once it has run out.
+    input_frames: usize,
+    channel: usize,
+    // Interleaved input starting at frame `base`. Frame positions below are
+    // counted from the start of the input, or from the last seek.
+    buffer: Vec<f32>,
+    base: usize,
+    // Where the next grain would start if no search was done.
+    analysis: f64,
+    // Where the audio following the previous grain starts, which the next
+    // grain should resemble.
+    natural: Option<usize>,
+    // The second half of the previous grain, still to be added to the next.
+    overlap: Vec<f32>,
+    output: Vec<f32>,
+    read: usize,
+}
+
+impl<S> TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, tempo: Tempo) -> Self {
+        let channels = input.channels().max(1) as usize;
+        let frames = |duration: Duration| {
+            (duration.as_secs_f64() * input.sample_rate() as f64).round() as usize
+        };
+
+        let hop = (frames(GRAIN) / 2).max(1);
+        let grain = hop * 2;
+
+        // A periodic Hann window, whose halves add up to 1 when overlapped.
+        let window = (0..grain)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / grain as f32).cos())
+            .collect();
+
+        Self {
+            tolerance: frames(TOLERANCE),
+            input,
+            tempo,
+            channels,
+            window,
+            hop,
+            engaged: false,
+            exhausted: false,
+            input_frames: 0,
+            channel: 0,
+            buffer: Vec::new(),
+            base: 0,
+            analysis: 0.0,
+            natural: None,
+            overlap: vec![0.0; hop * channels],
+            output: Vec::new(),
+            read: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.engaged = false;
+        self.exhausted = false;
+        self.input_frames = 0;
+        self.channel = 0;
+        self.buffer.clear();
+        self.base = 0;
+        self.analysis = 0.0;
+        self.natural = None;
+        self.overlap.fill(0.0);
+        self.output.clear();
+        self.read = 0;
+    }
+
+    // Reads the input until the buffer holds everything before frame `end`,
+    // padding with silence past the end of the input.
+    fn fill(&mut self, end: usize) {
+        while self.base + self.buffer.len() / self.channels < end {
+            let sample = if self.exhausted {
+                0.0
+            } else {
+                match self.input.next() {
+                    Some(sample) => sample,
+                    None => {
+                        self.exhausted = true;
+                        self.input_frames = self.base + self.buffer.len() / self.channels;
+
+                        // Finish a partly read frame with silence.
+                        0.0
+                    }
+                }
+            };
+
+            self.buffer.push(sample);
+        }
+    }
+
+    // The sum of the channels of frame `frame`, used to compare grains.
+    fn mono(&self, frame: usize) -> f32 {
+        let start = (frame - self.base) * self.channels;
+
+        self.buffer[start..start + self.channels].iter().sum()
+    }
+
+    // The start within `from..=to` whose audio lines up best with the audio
+    // at `natural`, by cross-correlation over half a grain. Every other frame
+    // is compared, which is plenty to find the alignment.
+    fn best_match(&self, from: usize, to: usize, natural: usize) -> usize {
+        let target: Vec<f32> = (0..self.hop)
+            .step_by(2)
+            .map(|n| self.mono(natural + n))
+            .collect();
+        let candidates: Vec<f32> = (from..to + self.hop)
+            .map(|frame| self.mono(frame))
+            .collect();
+
+        let mut best = (from, f32::MIN);
+
+        for start in from..=to {
+            let score: f32 = target
+                .iter()
+                .enumerate()
+                .map(|(index, sample)| sample * candidates[start - from + index * 2])
+                .sum();
+
+            if score > best.1 {
+                best = (start, score);
+            }
+        }
+
+        best.0
+    }
+
+    // Produces the next hop of output. Returns false once the input has been
+    // used up.
+    fn process(&mut self) -> bool {
+        let nominal = self.analysis.round() as usize;
+
+        if self.exhausted && nominal >= self.input_frames {
+            return false;
+        }
+
+        let from = nominal.saturating_sub(self.tolerance).max(self.base);
+        let to = nominal + self.tolerance;
+
+        self.fill(to + self.window.len());
+
+        let start = match self.natural {
+            Some(natural) => self.best_match(from, to, natural),
+            None => nominal.max(self.base),
+        };
+
+        self.output.clear();
+        self.read = 0;
+
+        let offset = (start - self.base) * self.channels;
+        let mut overlap = vec![0.0; self.hop * self.channels];
+
+        for (n, weight) in self.window.iter().enumerate() {
+            for channel in 0..self.channels {
+                let index = n * self.channels + channel;
+                let sample = self.buffer[offset + index] * weight;
+
+                if n < self.hop {
+                    self.output.push(self.overlap[index] + sample);
+                } else {
+                    overlap[index - self.hop * self.channels] = sample;
+                }
+            }
+        }
+
+        self.overlap = overlap;
+        self.natural = Some(start + self.hop);
+
+        let tempo = get_tempo(&self.tempo).clamp(0.25, 4.0) as f64;
+        self.analysis += self.hop as f64 * tempo;
+
+        // Drop the input no later grain can reach.
+        let keep = (self.analysis.round() as usize)
+            .saturating_sub(self.tolerance)
+            .min(start + self.hop)
+            .max(self.base);
+
+        self.buffer.drain(..(keep - self.base) * self.channels);
+        self.base = keep;
+
+        true
+    }
+}
+
+impl<S> Iterator for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.engaged {
+            // The stretch can only start between frames.
+            if get_tempo(&self.tempo) == 1.0 || self.channel != 0 {
+                let sample = self.input.next()?;
+                self.channel = (self.channel + 1) % self.channels;
+
+                return Some(sample);
+            }
+
+            self.engaged = true;
+        }
+
+        if self.read == self.output.len() && !self.process() {
+            return None;
+        }
+
+        let sample = self.output[self.read];
+        self.read += 1;
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for TimeStretch<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.engaged {
+            None
+        } else {
+            self.input.current_frame_len()
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    // Buffered audio belongs to the old position, so it is dropped and the
+    // stretch starts over from the new one.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+
+        self.reset();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 44100;
+
+    // A second of a stereo tone, with each channel at its own frequency.
+    fn stereo_tone() -> Vec<f32> {
+        (0..RATE as usize)
+            .flat_map(|i| {
+                let t = i as f32 / RATE as f32;
+
+                [(2.0 * PI * 440.0 * t).sin(), (2.0 * PI * 660.0 * t).sin()]
+            })
+            .collect()
+    }
+
+    fn stretch(samples: &[f32], tempo: f32) -> Vec<f32> {
+        let source = SamplesBuffer::new(2, RATE, samples.to_vec());
+
+        TimeStretch::new(source, new_tempo(tempo)).collect()
+    }
+
+    #[test]
+    fn passes_audio_through_at_normal_tempo() {
+        let input = stereo_tone();
+
+        assert_eq!(stretch(&input, 1.0), input);
+    }
+
+    #[test]
+    fn output_length_scales_with_tempo() {
+        let input = stereo_tone();
+        let input_frames = input.len() / 2;
+        let hop = (GRAIN.as_secs_f64() * RATE as f64 / 2.0).round();
+
+        for tempo in [0.5, 0.75, 1.5, 2.0] {
+            let output = stretch(&input, tempo);
+
+            assert_eq!(output.len() % 2, 0, "{}", tempo);
+
+            // The last grain is padded out to a whole hop with silence.
+            let expected = input_frames as f64 / tempo as f64;
+            let frames = (output.len() / 2) as f64;
+
+            assert!(
+                (frames - expected).abs() <= hop,
+                "{}: {} frames, expected {}",
+                tempo,
+                frames,
+                expected
+            );
+        }
+    }
+}
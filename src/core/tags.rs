@@ -0,0 +1,709 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::db;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use id3::TagLike;
+use log;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagError {
+    ReadError(String),
+    ParseError(String),
+    WriteError(String),
+    DatabaseError(db::DatabaseError),
+}
+
+// The tags read from an audio file's ID3v2, Vorbis comment or MP4 metadata.
+// The title isn't stored as a column of its own; it becomes the display name
+// of newly added tracks.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+}
+
+// The fields of the track-list edit modal. Empty fields remove the tag.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TagEdit {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub cover_art: Option<PathBuf>,
+}
+
+// The outcome of rescanning the library: how many tracks had their tags
+// refreshed and how many couldn't be read.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RescanSummary {
+    pub updated: usize,
+    pub failed: usize,
+}
+
+impl TrackTags {
+    // Fills in any tags that are still missing from a metadata revision.
+    // Files can carry several revisions (e.g. ID3v1 and ID3v2), so the first
+    // one to provide a value wins.
+    pub fn merge_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let Some(key) = tag.std_key else {
+                continue;
+            };
+
+            let value = tag.value.to_string();
+            let value = value.trim();
+
+            if value.is_empty() {
+                continue;
+            }
+
+            match key {
+                StandardTagKey::TrackTitle => set_once(&mut self.title, value.to_string()),
+                StandardTagKey::Artist => set_once(&mut self.artist, value.to_string()),
+                StandardTagKey::Album => set_once(&mut self.album, value.to_string()),
+                StandardTagKey::AlbumArtist => set_once(&mut self.album_artist, value.to_string()),
+                StandardTagKey::Genre => set_once(&mut self.genre, value.to_string()),
+                StandardTagKey::TrackNumber => {
+                    if let Some(number) = parse_number(value) {
+                        set_once(&mut self.track_number, number);
+                    }
+                }
+                StandardTagKey::DiscNumber => {
+                    if let Some(number) = parse_number(value) {
+                        set_once(&mut self.disc_number, number);
+                    }
+                }
+                StandardTagKey::Date | StandardTagKey::OriginalDate => {
+                    if let Some(year) = parse_year(value) {
+                        set_once(&mut self.year, year);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn set_once<T>(field: &mut Option<T>, value: T) {
+    if field.is_none() {
+        *field = Some(value);
+    }
+}
+
+// Track and disc numbers are often written as "3/12".
+fn parse_number(value: &str) -> Option<u32> {
+    value.split('/').next()?.trim().parse().ok()
+}
+
+// Dates range from a bare "2003" to a full "2003-05-14T00:00:00".
+fn parse_year(value: &str) -> Option<i32> {
+    value.get(..4)?.parse().ok()
+}
+
+// Reads the tags of the audio file at `path`. This blocks while the file is
+// probed, so async callers should run it with `spawn_blocking`.
+pub fn read(path: &Path) -> Result<TrackTags, TagError> {
+    let file = File::open(path).map_err(|e| TagError::ReadError(e.to_string()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension() {
+        hint.with_extension(&extension.to_string_lossy());
+    }
+
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| TagError::ParseError(e.to_string()))?;
+
+    let mut tags = TrackTags::default();
+
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.merge_revision(revision);
+        }
+    }
+
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.merge_revision(revision);
+    }
+
+    Ok(tags)
+}
+
+// Re-reads the tags of every track in `data/audio` and stores them in the
+// database. Used to fill the tag columns of tracks added before they existed.
+pub async fn rescan_library() -> Result<RescanSummary, db::DatabaseError> {
+    log::info!("Rescanning tags of all tracks.");
+
+    let mut summary = RescanSummary::default();
+
+    for track in db::get_all_music().await? {
+        let path = track.audio_path();
+
+        let tags = match tokio::task::spawn_blocking(move || read(&path)).await {
+            Ok(Ok(tags)) => tags,
+            Ok(Err(e)) => {
+                log::error!("Failed to read tags of {}: {:?}", track.video_id, e);
+
+                summary.failed += 1;
+                continue;
+            }
+            Err(e) => {
+                log::error!("Tag reader panicked for {}: {}", track.video_id, e);
+
+                summary.failed += 1;
+                continue;
+            }
+        };
+
+        db::update_tags(track.music_id, tags).await?;
+
+        summary.updated += 1;
+    }
+
+    log::info!(
+        "Rescanned tags: {} updated, {} failed.",
+        summary.updated,
+        summary.failed
+    );
+
+    Ok(summary)
+}
+
+// Whether tags can be written back into files with this extension. Other
+// formats only have their database row updated when edited.
+pub fn supports_writing(extension: &str) -> bool {
+    matches!(extension.to_lowercase().as_str(), "mp3" | "flac" | "ogg")
+}
+
+// Saves an edit from the track list to both the database and the audio file.
+// The new tags are written to a copy of the file first. Inside the database
+// transaction the original is moved aside and the copy put in its place, and
+// if the transaction then fails to commit the original is moved back. Either
+// way the file and the database row never disagree.
+pub async fn edit_track(track: db::Track, edit: TagEdit) -> Result<(), TagError> {
+    log::info!("Editing tags for {}.", track.video_id);
+
+    let cover_art = match &edit.cover_art {
+        Some(path) => Some((
+            tokio::fs::read(path)
+                .await
+                .map_err(|e| TagError::ReadError(e.to_string()))?,
+            image_mime_type(path),
+        )),
+        None => None,
+    };
+
+    let audio_path = track.audio_path();
+    let staged_path = audio_path.with_extension(format!("{}.tmp", track.extension));
+    let original_path = audio_path.with_extension(format!("{}.orig", track.extension));
+
+    let writes_file = supports_writing(&track.extension);
+
+    let replacement = if writes_file {
+        let edit = edit.clone();
+        let cover_art = cover_art.clone();
+        let (audio, staged) = (audio_path.clone(), staged_path.clone());
+
+        tokio::task::spawn_blocking(move || stage(&audio, &staged, &edit, cover_art))
+            .await
+            .map_err(|e| TagError::WriteError(e.to_string()))??;
+
+        db::FileReplacement {
+            replace: Box::new({
+                let (audio, staged, original) = (
+                    audio_path.clone(),
+                    staged_path.clone(),
+                    original_path.clone(),
+                );
+
+                move || {
+                    fs::rename(&audio, &original)?;
+
+                    fs::rename(&staged, &audio).inspect_err(|_| {
+                        let _ = fs::rename(&original, &audio);
+                    })
+                }
+            }),
+            restore: Box::new({
+                let (audio, original) = (audio_path.clone(), original_path.clone());
+
+                move || fs::rename(original, audio)
+            }),
+        }
+    } else {
+        log::info!(
+            "Tags can't be written to .{} files; updating the database only.",
+            track.extension
+        );
+
+        db::FileReplacement {
+            replace: Box::new(|| Ok(())),
+            restore: Box::new(|| Ok(())),
+        }
+    };
+
+    if let Err(e) = db::update_metadata(track.music_id, edit, replacement).await {
+        let _ = tokio::fs::remove_file(&staged_path).await;
+
+        return Err(TagError::DatabaseError(e));
+    }
+
+    if writes_file {
+        if let Err(e) = tokio::fs::remove_file(&original_path).await {
+            log::error!("Failed to remove {}: {}", original_path.display(), e);
+        }
+    }
+
+    if let Some((data, mime_type)) = cover_art {
+        write_thumbnail(&track.video_id, data, mime_type).await;
+    }
+
+    Ok(())
+}
+
+// Copies the audio file to `staged` and writes the edited tags into the copy.
+fn stage(
+    audio: &Path,
+    staged: &Path,
+    edit: &TagEdit,
+    cover_art: Option<(Vec<u8>, &'static str)>,
+) -> Result<(), TagError> {
+    fs::copy(audio, staged).map_err(|e| TagError::WriteError(e.to_string()))?;
+
+    let extension = audio
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    let result = match extension.as_deref() {
+        Some("mp3") => write_id3(staged, edit, cover_art),
+        Some("flac") => write_vorbis_comments(staged, edit, cover_art),
+        Some("ogg") => write_ogg_comments(staged, edit, cover_art),
+        _ => Err(TagError::WriteError("Unsupported format".to_string())),
+    };
+
+    if result.is_err() {
+        let _ = fs::remove_file(staged);
+    }
+
+    result
+}
+
+fn write_id3(
+    path: &Path,
+    edit: &TagEdit,
+    cover_art: Option<(Vec<u8>, &'static str)>,
+) -> Result<(), TagError> {
+    let mut tag = id3::no_tag_ok(id3::Tag::read_from_path(path))
+        .map_err(|e| TagError::ParseError(e.to_string()))?
+        .unwrap_or_default();
+
+    tag.set_title(edit.title.clone());
+
+    match &edit.artist {
+        Some(artist) => tag.set_artist(artist.clone()),
+        None => tag.remove_artist(),
+    }
+
+    match &edit.album {
+        Some(album) => tag.set_album(album.clone()),
+        None => tag.remove_album(),
+    }
+
+    match edit.year {
+        Some(year) => tag.set_year(year),
+        None => tag.remove_year(),
+    }
+
+    match &edit.genre {
+        Some(genre) => tag.set_genre(genre.clone()),
+        None => tag.remove_genre(),
+    }
+
+    if let Some((data, mime_type)) = cover_art {
+        tag.remove_picture_by_type(id3::frame::PictureType::CoverFront);
+        tag.add_frame(id3::frame::Picture {
+            mime_type: mime_type.to_string(),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data,
+        });
+    }
+
+    tag.write_to_path(path, id3::Version::Id3v24)
+        .map_err(|e| TagError::WriteError(e.to_string()))
+}
+
+fn write_vorbis_comments(
+    path: &Path,
+    edit: &TagEdit,
+    cover_art: Option<(Vec<u8>, &'static str)>,
+) -> Result<(), TagError> {
+    let mut tag =
+        metaflac::Tag::read_from_path(path).map_err(|e| TagError::ParseError(e.to_string()))?;
+
+    for (key, value) in vorbis_fields(edit) {
+        match value {
+            Some(value) => tag.set_vorbis(key, vec![value]),
+            None => tag.remove_vorbis(key),
+        }
+    }
+
+    if let Some((data, mime_type)) = cover_art {
+        tag.remove_picture_type(metaflac::block::PictureType::CoverFront);
+        tag.add_picture(mime_type, metaflac::block::PictureType::CoverFront, data);
+    }
+
+    tag.write_to_path(path)
+        .map_err(|e| TagError::WriteError(e.to_string()))
+}
+
+// The Vorbis comment fields an edit sets, with None for the ones it removes.
+fn vorbis_fields(edit: &TagEdit) -> [(&'static str, Option<String>); 5] {
+    [
+        ("TITLE", Some(edit.title.clone())),
+        ("ARTIST", edit.artist.clone()),
+        ("ALBUM", edit.album.clone()),
+        ("DATE", edit.year.map(|year| year.to_string())),
+        ("GENRE", edit.genre.clone()),
+    ]
+}
+
+// Ogg Vorbis keeps its comments in the second packet of the stream. The file
+// is rewritten packet by packet with that one replaced, keeping the page
+// boundaries and granule positions of everything else so seeking still works.
+fn write_ogg_comments(
+    path: &Path,
+    edit: &TagEdit,
+    cover_art: Option<(Vec<u8>, &'static str)>,
+) -> Result<(), TagError> {
+    let file = File::open(path).map_err(|e| TagError::ReadError(e.to_string()))?;
+    let mut reader = ogg::PacketReader::new(BufReader::new(file));
+    let mut packets = Vec::new();
+
+    while let Some(packet) = reader
+        .read_packet()
+        .map_err(|e| TagError::ParseError(e.to_string()))?
+    {
+        packets.push(packet);
+    }
+
+    let comments = packets
+        .get_mut(1)
+        .filter(|packet| packet.data.starts_with(VORBIS_COMMENT_HEADER))
+        .ok_or_else(|| TagError::ParseError("Not an Ogg Vorbis file".to_string()))?;
+
+    comments.data = edit_comment_packet(&comments.data, edit, cover_art)?;
+
+    let file = File::create(path).map_err(|e| TagError::WriteError(e.to_string()))?;
+    let mut writer = ogg::PacketWriter::new(BufWriter::new(file));
+
+    for packet in packets {
+        let end = if packet.last_in_stream() {
+            ogg::PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            ogg::PacketWriteEndInfo::EndPage
+        } else {
+            ogg::PacketWriteEndInfo::NormalPacket
+        };
+
+        let (serial, granule) = (packet.stream_serial(), packet.absgp_page());
+
+        writer
+            .write_packet(packet.data.into_boxed_slice(), serial, end, granule)
+            .map_err(|e| TagError::WriteError(e.to_string()))?;
+    }
+
+    writer
+        .into_inner()
+        .flush()
+        .map_err(|e| TagError::WriteError(e.to_string()))
+}
+
+const VORBIS_COMMENT_HEADER: &[u8] = b"\x03vorbis";
+
+// Rebuilds a Vorbis comment header packet with the edited fields, keeping the
+// vendor string and any other comments as they were.
+fn edit_comment_packet(
+    packet: &[u8],
+    edit: &TagEdit,
+    cover_art: Option<(Vec<u8>, &'static str)>,
+) -> Result<Vec<u8>, TagError> {
+    let mut reader = &packet[VORBIS_COMMENT_HEADER.len()..];
+
+    let vendor = read_field(&mut reader)?;
+    let count = read_u32(&mut reader)?;
+    let mut comments = Vec::new();
+
+    for _ in 0..count {
+        comments.push(read_field(&mut reader)?);
+    }
+
+    let fields = vorbis_fields(edit);
+
+    // Keys are case-insensitive, so `title=` is replaced along with `TITLE=`.
+    let key_of = |comment: &[u8]| {
+        let key = comment
+            .split(|&byte| byte == b'=')
+            .next()
+            .unwrap_or_default();
+
+        String::from_utf8_lossy(key).to_uppercase()
+    };
+
+    comments.retain(|comment| {
+        let key = key_of(comment);
+
+        let replaced = fields.iter().any(|(field, _)| *field == key)
+            || (cover_art.is_some() && key == PICTURE_KEY);
+
+        !replaced
+    });
+
+    for (key, value) in fields {
+        if let Some(value) = value {
+            comments.push(format!("{}={}", key, value).into_bytes());
+        }
+    }
+
+    if let Some((data, mime_type)) = cover_art {
+        let picture = BASE64_STANDARD.encode(picture_block(&data, mime_type));
+
+        comments.push(format!("{}={}", PICTURE_KEY, picture).into_bytes());
+    }
+
+    let mut packet = VORBIS_COMMENT_HEADER.to_vec();
+
+    write_field(&mut packet, &vendor);
+    packet.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+
+    for comment in &comments {
+        write_field(&mut packet, comment);
+    }
+
+    // The framing bit.
+    packet.push(1);
+
+    Ok(packet)
+}
+
+// Cover art in Vorbis comments is a FLAC picture block, base64 encoded.
+const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+fn picture_block(data: &[u8], mime_type: &str) -> Vec<u8> {
+    let mut block = Vec::new();
+
+    // Front cover.
+    block.extend_from_slice(&3u32.to_be_bytes());
+    block.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime_type.as_bytes());
+    // No description, and the dimensions and colour depth are left unknown.
+    block.extend_from_slice(&[0; 20]);
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(data);
+
+    block
+}
+
+fn read_u32(reader: &mut &[u8]) -> Result<u32, TagError> {
+    let mut bytes = [0; 4];
+
+    reader
+        .read_exact(&mut bytes)
+        .map_err(|_| TagError::ParseError("Truncated Vorbis comments".to_string()))?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_field(reader: &mut &[u8]) -> Result<Vec<u8>, TagError> {
+    let length = read_u32(reader)? as usize;
+
+    if reader.len() < length {
+        return Err(TagError::ParseError(
+            "Truncated Vorbis comments".to_string(),
+        ));
+    }
+
+    let (field, rest) = reader.split_at(length);
+    *reader = rest;
+
+    Ok(field.to_vec())
+}
+
+fn write_field(packet: &mut Vec<u8>, field: &[u8]) {
+    packet.extend_from_slice(&(field.len() as u32).to_le_bytes());
+    packet.extend_from_slice(field);
+}
+
+fn image_mime_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+// Replaces the track's thumbnail with new cover art, removing any thumbnail
+// of a different image format so only one file matches the track.
+async fn write_thumbnail(video_id: &str, data: Vec<u8>, mime_type: &str) {
+    for extension in ["jpg", "png"] {
+        let _ =
+            tokio::fs::remove_file(format!("./data/thumbnails/{}.{}", video_id, extension)).await;
+    }
+
+    let extension = if mime_type == "image/png" {
+        "png"
+    } else {
+        "jpg"
+    };
+
+    if let Err(e) = tokio::fs::write(
+        format!("./data/thumbnails/{}.{}", video_id, extension),
+        data,
+    )
+    .await
+    {
+        log::error!("Failed to write thumbnail: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ogg_fixture(directory: &Path) -> PathBuf {
+        let path = directory.join("track.ogg");
+
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/short.ogg"),
+            &path,
+        )
+        .unwrap();
+
+        path
+    }
+
+    fn edit() -> TagEdit {
+        TagEdit {
+            title: "Title".to_string(),
+            artist: Some("Artist".to_string()),
+            album: Some("Album".to_string()),
+            year: Some(2003),
+            genre: None,
+            cover_art: None,
+        }
+    }
+
+    #[test]
+    fn writes_ogg_comments() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = ogg_fixture(directory.path());
+
+        write_ogg_comments(&path, &edit(), None).unwrap();
+
+        let tags = read(&path).unwrap();
+
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.year, Some(2003));
+        assert_eq!(tags.genre, None);
+
+        // The audio is untouched.
+        let file = BufReader::new(File::open(&path).unwrap());
+        let decoder = rodio::Decoder::new(file).unwrap();
+
+        assert_eq!(decoder.count(), 12672);
+    }
+
+    #[test]
+    fn rewriting_ogg_comments_replaces_them() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = ogg_fixture(directory.path());
+
+        write_ogg_comments(&path, &edit(), None).unwrap();
+
+        let second = TagEdit {
+            title: "Renamed".to_string(),
+            artist: None,
+            ..edit()
+        };
+
+        write_ogg_comments(&path, &second, Some((vec![1, 2, 3], "image/png"))).unwrap();
+
+        let tags = read(&path).unwrap();
+
+        assert_eq!(tags.title.as_deref(), Some("Renamed"));
+        assert_eq!(tags.artist, None);
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+
+        let probed = crate::core::import::probe_file(&path, "ogg").unwrap();
+
+        assert_eq!(probed.cover_art, Some((vec![1, 2, 3], "png")));
+    }
+
+    #[test]
+    fn comment_keys_are_case_insensitive() {
+        let mut packet = VORBIS_COMMENT_HEADER.to_vec();
+        write_field(&mut packet, b"vendor");
+        packet.extend_from_slice(&2u32.to_le_bytes());
+        write_field(&mut packet, b"title=Old");
+        write_field(&mut packet, b"COMMENT=Kept");
+        packet.push(1);
+
+        let edited = edit_comment_packet(&packet, &edit(), None).unwrap();
+
+        let mut reader = &edited[VORBIS_COMMENT_HEADER.len()..];
+        assert_eq!(read_field(&mut reader).unwrap(), b"vendor");
+
+        let count = read_u32(&mut reader).unwrap();
+        let comments: Vec<_> = (0..count)
+            .map(|_| String::from_utf8(read_field(&mut reader).unwrap()).unwrap())
+            .collect();
+
+        assert_eq!(
+            comments,
+            [
+                "COMMENT=Kept",
+                "TITLE=Title",
+                "ARTIST=Artist",
+                "ALBUM=Album",
+                "DATE=2003"
+            ]
+        );
+        assert_eq!(reader, [1]);
+    }
+
+    #[test]
+    fn rejects_truncated_comments() {
+        let mut packet = VORBIS_COMMENT_HEADER.to_vec();
+        packet.extend_from_slice(&100u32.to_le_bytes());
+
+        assert!(matches!(
+            edit_comment_packet(&packet, &edit(), None),
+            Err(TagError::ParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::playback;
+use super::source::StatusError;
+
+use log;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+// The bitrates offered for lossy formats, in kbps.
+pub const BITRATES: &[u32] = &[96, 128, 160, 192, 256, 320];
+
+pub const DEFAULT_BITRATE: u32 = 192;
+
+// The format downloads are converted to once they have downloaded. Only
+// formats the player can decode are offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputFormat {
+    // Opus used to be offered, but can't be played, so settings that chose it
+    // go back to the default.
+    #[default]
+    #[serde(alias = "Opus")]
+    Mp3,
+    Aac,
+    Flac,
+    // Keeps the downloaded file as it is when the player can read it, and
+    // falls back to MP3 otherwise.
+    Original,
+}
+
+impl OutputFormat {
+    pub const ALL: &'static [Self] = &[Self::Mp3, Self::Aac, Self::Flac, Self::Original];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 | Self::Original => "mp3",
+            Self::Aac => "m4a",
+            Self::Flac => "flac",
+        }
+    }
+
+    // Whether the bitrate setting applies, which it doesn't for lossless
+    // output.
+    pub fn has_bitrate(self) -> bool {
+        !matches!(self, Self::Flac)
+    }
+
+    fn codec(self) -> &'static str {
+        match self {
+            Self::Mp3 | Self::Original => "libmp3lame",
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mp3 => "MP3",
+            Self::Aac => "AAC",
+            Self::Flac => "FLAC",
+            Self::Original => "Keep original",
+        }
+        .fmt(f)
+    }
+}
+
+// What a download in `extension` should be converted to, or None if it is
+// kept as it is. Files already in the chosen format aren't converted again.
+pub fn target(format: OutputFormat, extension: &str) -> Option<OutputFormat> {
+    let extension = extension.to_lowercase();
+
+    match format {
+        OutputFormat::Original if playback::is_supported(&extension) => None,
+        format if format.extension() == extension => None,
+        format => Some(format),
+    }
+}
+
+// The ffmpeg binary to run for the configured path. An empty path uses the
+// system's ffmpeg on Unix and the bundled one elsewhere, and a bare file name
+// is looked for in `data` first, which is where the installer puts it.
+pub fn ffmpeg_binary(configured: &str) -> PathBuf {
+    if configured.is_empty() {
+        return if cfg!(unix) {
+            PathBuf::from("ffmpeg")
+        } else {
+            PathBuf::from("./data/ffmpeg")
+        };
+    }
+
+    let path = PathBuf::from(configured);
+    let bundled = Path::new("./data").join(&path);
+
+    if path.parent() == Some(Path::new("")) && bundled.exists() {
+        bundled
+    } else {
+        path
+    }
+}
+
+// Checks that `ffmpeg` runs before anything is downloaded for it to convert.
+pub async fn preflight(ffmpeg: &Path) -> Result<(), StatusError> {
+    let output = Command::new(ffmpeg)
+        .arg("-version")
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            log::error!(
+                "{} -version failed: {}",
+                ffmpeg.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+
+            Err(StatusError::FFmpegNotFound(ffmpeg.display().to_string()))
+        }
+        Err(e) => {
+            log::error!("Failed to run {}: {}", ffmpeg.display(), e);
+
+            Err(StatusError::FFmpegNotFound(ffmpeg.display().to_string()))
+        }
+    }
+}
+
+// Converts `input` to `output` in `format`. Cancelling drops this future,
+// which stops ffmpeg too. On failure the error holds the last line ffmpeg
+// wrote to stderr, which is usually the reason, and the whole of it is logged.
+pub async fn convert(
+    ffmpeg: &Path,
+    input: &Path,
+    output: &Path,
+    format: OutputFormat,
+    bitrate: u32,
+) -> Result<(), StatusError> {
+    let mut command = Command::new(ffmpeg);
+
+    command
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(input)
+        .args(["-vn", "-ac", "2", "-ar", "44100", "-c:a", format.codec()]);
+
+    if format.has_bitrate() {
+        command.args(["-b:a", &format!("{}k", bitrate)]);
+    }
+
+    let result = command.arg(output).kill_on_drop(true).output().await;
+
+    let output = result.map_err(|e| {
+        log::error!("Failed to run {}: {}", ffmpeg.display(), e);
+
+        StatusError::FFmpegNotFound(ffmpeg.display().to_string())
+    })?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    log::error!("ffmpeg failed with {}: {}", output.status, stderr);
+
+    let reason = stderr
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| line.trim().to_string())
+        .unwrap_or_else(|| output.status.to_string());
+
+    Err(StatusError::FFmpegConversionError(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_only_playable_formats() {
+        for format in OutputFormat::ALL {
+            assert!(
+                playback::is_supported(format.extension()),
+                "{} converts to .{}, which can't be played",
+                format,
+                format.extension()
+            );
+        }
+    }
+
+    #[test]
+    fn reads_opus_setting_as_default() {
+        let format: OutputFormat = serde_json::from_str("\"Opus\"").unwrap();
+
+        assert_eq!(format, OutputFormat::default());
+    }
+}
@@ -0,0 +1,325 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::playback::AudioEvent;
+
+use iced::futures::SinkExt;
+use iced::{stream, Subscription};
+use realfft::RealFftPlanner;
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc as async_mpsc;
+
+// How many bars the spectrum is split into, spaced evenly in pitch between
+// the lowest and highest frequency shown.
+pub const BAR_COUNT: usize = 32;
+const LOWEST_FREQUENCY: f32 = 40.0;
+const HIGHEST_FREQUENCY: f32 = 16000.0;
+
+// How many points of the waveform are drawn.
+pub const WAVEFORM_POINTS: usize = 256;
+
+// The number of frames in each block sent from the audio thread, and in each
+// transform.
+const BLOCK_FRAMES: usize = 1024;
+const FFT_SIZE: usize = 2048;
+
+// How many blocks can wait to be analysed. The audio thread drops blocks
+// rather than wait for the analyser.
+const BLOCK_CAPACITY: usize = 8;
+
+// The quietest level a bar shows, in dB below full scale.
+const FLOOR: f32 = 70.0;
+
+// How far a bar can fall each frame, so it drops smoothly after a peak.
+const FALL: f32 = 0.04;
+
+// Frames are drawn at most this often.
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+// How the control bar shows what is playing: the track's thumbnail, or a
+// spectrum or waveform drawn from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VisualiserStyle {
+    #[default]
+    Off,
+    Bars,
+    Waveform,
+}
+
+impl VisualiserStyle {
+    // The style clicking the visualiser switches to next.
+    pub fn cycle(self) -> Self {
+        match self {
+            Self::Off => Self::Bars,
+            Self::Bars => Self::Waveform,
+            Self::Waveform => Self::Off,
+        }
+    }
+}
+
+// A block of audio as it is played, mixed down to mono.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+// Where the playing sources send their blocks. None while nothing is drawing
+// them.
+pub type BlockSender = Arc<Mutex<Option<SyncSender<Block>>>>;
+
+// What the visualiser draws: the level of each bar from 0 to 1, and the most
+// recent stretch of the waveform from -1 to 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    pub bars: Vec<f32>,
+    pub waveform: Vec<f32>,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self {
+            bars: vec![0.0; BAR_COUNT],
+            waveform: vec![0.0; WAVEFORM_POINTS],
+        }
+    }
+}
+
+// Passes a source through unchanged, sending a copy of it in blocks to the
+// visualiser. Nothing here waits: blocks are dropped if the visualiser falls
+// behind, or if the sender is busy being replaced.
+pub struct Tap<S> {
+    input: S,
+    sender: BlockSender,
+    channels: usize,
+    channel: usize,
+    // The sum of the channels of the frame being read.
+    frame: f32,
+    block: Vec<f32>,
+}
+
+impl<S> Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(input: S, sender: BlockSender) -> Self {
+        Self {
+            channels: input.channels().max(1) as usize,
+            input,
+            sender,
+            channel: 0,
+            frame: 0.0,
+            block: Vec::with_capacity(BLOCK_FRAMES),
+        }
+    }
+
+    fn send_block(&mut self) {
+        let samples = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_FRAMES));
+
+        let Ok(mut sender) = self.sender.try_lock() else {
+            return;
+        };
+
+        if let Some(active) = sender.as_ref() {
+            let block = Block {
+                samples,
+                sample_rate: self.input.sample_rate(),
+            };
+
+            // The visualiser was closed, so there is no one to send to.
+            if let Err(TrySendError::Disconnected(_)) = active.try_send(block) {
+                *sender = None;
+            }
+        }
+    }
+}
+
+impl<S> Iterator for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.input.next()?;
+
+        self.frame += sample;
+        self.channel += 1;
+
+        if self.channel == self.channels {
+            self.block.push(self.frame / self.channels as f32);
+            self.frame = 0.0;
+            self.channel = 0;
+
+            if self.block.len() == BLOCK_FRAMES {
+                self.send_block();
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tap<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+
+        self.block.clear();
+        self.frame = 0.0;
+        self.channel = 0;
+
+        Ok(())
+    }
+}
+
+// Streams spectrums of what is playing while subscribed. The analysis runs on
+// its own thread so that neither the audio nor the UI waits for it.
+pub fn subscription(sender: mpsc::Sender<AudioEvent>) -> Subscription<Spectrum> {
+    Subscription::run_with_id(
+        "visualiser",
+        stream::channel(1, move |mut output| async move {
+            let (block_sender, blocks) = mpsc::sync_channel(BLOCK_CAPACITY);
+            let (frame_sender, mut frames) = async_mpsc::channel(1);
+
+            if sender.send(AudioEvent::Visualise(block_sender)).is_err() {
+                log::error!("Failed to start the visualiser.");
+            }
+
+            thread::spawn(move || analyse(blocks, frame_sender));
+
+            while let Some(spectrum) = frames.recv().await {
+                if output.send(spectrum).await.is_err() {
+                    break;
+                }
+            }
+        }),
+    )
+}
+
+// Turns the blocks received into a spectrum once every frame, until the
+// subscription is dropped. Frames are skipped while the UI is still busy with
+// the last one, and while nothing is playing once the bars have fallen.
+fn analyse(blocks: Receiver<Block>, frames: async_mpsc::Sender<Spectrum>) {
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(FFT_SIZE);
+    let mut input = fft.make_input_vec();
+    let mut output = fft.make_output_vec();
+
+    // A periodic Hann window.
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|n| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / FFT_SIZE as f32).cos())
+        .collect();
+
+    let mut history = vec![0.0; FFT_SIZE];
+    let mut sample_rate = 44100;
+    let mut spectrum = Spectrum::default();
+    let mut next_frame = Instant::now();
+
+    loop {
+        next_frame += FRAME_INTERVAL;
+        thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+
+        let mut received = false;
+
+        loop {
+            match blocks.try_recv() {
+                Ok(block) => {
+                    history.extend(block.samples);
+
+                    let excess = history.len() - FFT_SIZE;
+                    history.drain(..excess);
+
+                    sample_rate = block.sample_rate;
+                    received = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return,
+            }
+        }
+
+        let levels = if received {
+            for ((input, sample), weight) in input.iter_mut().zip(&history).zip(&window) {
+                *input = sample * weight;
+            }
+
+            if fft.process(&mut input, &mut output).is_err() {
+                continue;
+            }
+
+            bars(&output, sample_rate)
+        } else if spectrum.bars.iter().any(|&bar| bar > 0.0) {
+            vec![0.0; BAR_COUNT]
+        } else {
+            continue;
+        };
+
+        for (bar, level) in spectrum.bars.iter_mut().zip(levels) {
+            *bar = level.max(*bar - FALL).max(0.0);
+        }
+
+        let step = FFT_SIZE / WAVEFORM_POINTS;
+
+        spectrum.waveform = if received {
+            history.iter().step_by(step).copied().collect()
+        } else {
+            vec![0.0; WAVEFORM_POINTS]
+        };
+
+        match frames.try_send(spectrum.clone()) {
+            Ok(()) | Err(async_mpsc::error::TrySendError::Full(_)) => (),
+            Err(async_mpsc::error::TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+// The level of each bar from the magnitudes of a transform, taking the
+// loudest bin in each.
+fn bars(bins: &[realfft::num_complex::Complex<f32>], sample_rate: u32) -> Vec<f32> {
+    let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+    let ratio = (HIGHEST_FREQUENCY / LOWEST_FREQUENCY).powf(1.0 / BAR_COUNT as f32);
+
+    (0..BAR_COUNT)
+        .map(|bar| {
+            let low = LOWEST_FREQUENCY * ratio.powi(bar as i32);
+            let high = low * ratio;
+
+            let first = ((low / bin_width) as usize).min(bins.len() - 1);
+            let last = ((high / bin_width) as usize).clamp(first + 1, bins.len());
+
+            // A full scale sine wave through a Hann window peaks at a quarter
+            // of the transform size.
+            let magnitude = bins[first..last]
+                .iter()
+                .map(|bin| bin.norm())
+                .fold(0.0, f32::max)
+                * 4.0
+                / FFT_SIZE as f32;
+
+            let level = 20.0 * magnitude.max(1e-9).log10();
+
+            ((level + FLOOR) / FLOOR).clamp(0.0, 1.0)
+        })
+        .collect()
+}
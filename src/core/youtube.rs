@@ -1,154 +1,187 @@
-use std::collections::HashMap;
-use std::path::Path;
+use std::time::Duration;
 
 use super::request;
-use crate::db;
+use super::source::{AudioStream, MusicSource, SearchResult, StatusError, TrackInfo};
 
-use tokio::fs;
-use tokio::process::Command;
+use iced::futures::future::BoxFuture;
+use iced::futures::{stream, FutureExt, StreamExt};
 
-use rusty_ytdl::search::{SearchResult, YouTube};
+use rusty_ytdl::search::{self, Playlist, PlaylistSearchOptions, YouTube};
 use rusty_ytdl::{Video, VideoOptions, VideoQuality, VideoSearchOptions};
 
+// A video listed in a YouTube playlist.
 #[derive(Debug, Clone, PartialEq)]
-pub enum StatusError {
-    NetworkError,
-    VideoNotFound,
-    UnknownError,
-    FFmpegConversionError,
-    VideoOptionError,
-    VideoInfoError,
-    DownloadError,
-    CodecError,
-    ThumbnailError,
-    WriteError,
+pub struct PlaylistVideo {
+    pub video_id: String,
+    pub title: String,
 }
 
-// Simply calls ffmpeg to convert audio files from `webm` format to `mp3` format.
-// YouTube does not store files in `mp3` format, so after downloading from YouTube,
-// we need to use FFmpeg to convert to `mp3` codec.
-// Alternatives would be nice to avoid using FFmpeg since it's a large dependancy.
-async fn ffmpeg_convert_codec(video_id: String) -> Result<bool, StatusError> {
-    let in_file = format!("./data/audio/{}.webm", video_id);
-    let out_file = format!("./data/audio/{}.mp3", video_id);
-
-    let cmd_dest = if cfg!(unix) {
-        String::from("ffmpeg")
-    } else {
-        String::from("./data/ffmpeg")
-    };
-
-    let output = Command::new(cmd_dest)
-        .args(&[
-            "-i", &in_file, "-vn", "-ar", "44100", "-ac", "2", "-b:a", "192k", &out_file,
-        ])
-        .output()
-        .await;
-
-    fs::remove_file(in_file).await.unwrap();
-
-    match output {
-        Ok(output) => Ok(output.status.success()),
-        Err(_) => Err(StatusError::FFmpegConversionError),
-    }
-}
-
-pub async fn get_search_results(
-    query: String,
-) -> Result<Vec<HashMap<String, String>>, StatusError> {
-    let youtube = YouTube::new().unwrap();
-
-    let res = match youtube.search(query, None).await {
-        Ok(res) => res,
-        Err(error) => match error {
-            rusty_ytdl::VideoError::Reqwest(_) => return Err(StatusError::NetworkError),
-            rusty_ytdl::VideoError::VideoNotFound => {
-                return Err(StatusError::VideoNotFound);
-            }
-            _ => {
-                return Err(StatusError::UnknownError);
-            }
-        },
-    };
-
-    let mut results = Vec::new();
-    let mut index = 0;
+// Searches and downloads the audio of YouTube videos.
+pub struct YouTubeSource;
 
-    for video in res {
-        if index > 6 {
-            break;
-        }
-
-        if let SearchResult::Video(video) = video {
-            let mut result = HashMap::new();
-            result.insert("title".to_string(), video.title);
-            result.insert("thumbnail".to_string(), video.thumbnails[0].url.clone());
-            result.insert("channel".to_string(), video.channel.name);
-            result.insert("video_id".to_string(), video.id);
-            results.push(result);
-
-            index += 1;
-        }
-    }
-
-    log::info!("Results: {:?}", results);
-
-    Ok(results)
+// The link to the video with the given id.
+pub fn video_url(video_id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", video_id)
 }
 
-pub async fn download_from_url(url: String) -> Result<(), StatusError> {
+fn video(url: String) -> Result<Video, StatusError> {
     let video_options = VideoOptions {
         quality: VideoQuality::HighestAudio,
         filter: VideoSearchOptions::Audio,
         ..Default::default()
     };
 
-    let video =
-        Video::new_with_options(url, video_options).map_err(|_| StatusError::VideoOptionError)?;
+    Video::new_with_options(url, video_options).map_err(|_| StatusError::VideoOptionError)
+}
 
-    let video_info = video
-        .get_info()
-        .await
-        .map_err(|_| StatusError::VideoInfoError)?;
+impl MusicSource for YouTubeSource {
+    fn name(&self) -> &'static str {
+        "YouTube"
+    }
 
-    let path_str = format!(
-        "./data/audio/{}.webm",
-        video_info.video_details.video_id.as_str()
-    );
-    let path = Path::new(&path_str);
+    fn resolve(&self, url: &str) -> Option<String> {
+        let re = regex::Regex::new(
+            r"^(https?://)?(www\.|m\.|music\.)?(youtube\.com|youtu\.be)/(watch\?(.*&)?v=|shorts/|embed/)?([A-Za-z0-9_-]{11})([?&#/]|$)",
+        )
+        .unwrap();
 
-    video
-        .download(path)
-        .await
-        .map_err(|_| StatusError::DownloadError)?;
+        re.captures(url)
+            .and_then(|captures| captures.get(6))
+            .map(|id| id.as_str().to_string())
+    }
 
-    let video_id = video_info.video_details.video_id;
+    fn search(&self, query: String) -> BoxFuture<'static, Result<Vec<SearchResult>, StatusError>> {
+        async move {
+            let youtube = YouTube::new().unwrap();
+
+            let res = youtube
+                .search(query, None)
+                .await
+                .map_err(|error| match error {
+                    rusty_ytdl::VideoError::Reqwest(_) => StatusError::NetworkError,
+                    rusty_ytdl::VideoError::VideoNotFound => StatusError::VideoNotFound,
+                    _ => StatusError::UnknownError,
+                })?;
+
+            let results: Vec<SearchResult> = res
+                .into_iter()
+                .filter_map(|result| match result {
+                    search::SearchResult::Video(video) => Some(SearchResult {
+                        url: video_url(&video.id),
+                        title: video.title,
+                        channel: video.channel.name,
+                        thumbnail: video.thumbnails[0].url.clone(),
+                    }),
+                    _ => None,
+                })
+                .take(7)
+                .collect();
+
+            log::info!("Results: {:?}", results);
+
+            Ok(results)
+        }
+        .boxed()
+    }
 
-    let mut to_store = HashMap::new();
-    to_store.insert("format_type".to_string(), "mp3".to_string());
-    to_store.insert("video_id".to_string(), video_id.clone());
-    to_store.insert("display_name".to_string(), video_info.video_details.title);
-    to_store.insert(
-        "duration".to_string(),
-        video_info.video_details.length_seconds.to_string(),
-    );
+    fn metadata(&self, url: String) -> BoxFuture<'static, Result<TrackInfo, StatusError>> {
+        async move {
+            let video_info = video(url.clone())?
+                .get_info()
+                .await
+                .map_err(|_| StatusError::VideoInfoError)?;
+
+            let details = video_info.video_details;
+
+            let duration = details
+                .length_seconds
+                .parse::<u64>()
+                .map_err(|_| StatusError::VideoInfoError)?;
+
+            Ok(TrackInfo {
+                id: details.video_id,
+                url,
+                title: details.title,
+                duration: Some(Duration::from_secs(duration)),
+                extension: "webm".to_string(),
+                thumbnail: details
+                    .thumbnails
+                    .first()
+                    .map(|thumbnail| thumbnail.url.clone()),
+            })
+        }
+        .boxed()
+    }
+
+    fn download(&self, info: TrackInfo) -> BoxFuture<'static, Result<AudioStream, StatusError>> {
+        async move {
+            let stream = video(info.url)?
+                .stream()
+                .await
+                .map_err(|_| StatusError::DownloadError)?;
+
+            let length = stream.content_length() as u64;
+
+            let chunks = stream::unfold(Some(stream), |stream| async move {
+                let stream = stream?;
+
+                match stream.chunk().await {
+                    Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), Some(stream))),
+                    Ok(None) => None,
+                    Err(_) => Some((Err(StatusError::DownloadError), None)),
+                }
+            });
+
+            Ok(AudioStream {
+                length: Some(length),
+                chunks: chunks.boxed(),
+            })
+        }
+        .boxed()
+    }
 
-    let _ = db::add_music(to_store);
+    fn thumbnail(
+        &self,
+        info: TrackInfo,
+    ) -> BoxFuture<'static, Result<Option<Vec<u8>>, StatusError>> {
+        async move {
+            let Some(url) = info.thumbnail else {
+                return Ok(None);
+            };
 
-    ffmpeg_convert_codec(video_id.clone())
-        .await
-        .map_err(|_| StatusError::CodecError)?;
+            let downloaded = request::request_thumbnail(url)
+                .await
+                .map_err(|_| StatusError::ThumbnailError)?;
 
-    let thumbnail = &video_info.video_details.thumbnails[0].url;
+            Ok(Some(downloaded.to_vec()))
+        }
+        .boxed()
+    }
+}
 
-    let downloaded = request::request_thumbnail(thumbnail.clone())
-        .await
-        .map_err(|_| StatusError::ThumbnailError)?;
+// The name of the playlist at `url` and every video in it, in the playlist's
+// order.
+pub async fn get_playlist(url: String) -> Result<(String, Vec<PlaylistVideo>), StatusError> {
+    let options = PlaylistSearchOptions {
+        fetch_all: true,
+        ..Default::default()
+    };
 
-    let thumbnail_path = format!("./data/thumbnails/{}.jpg", video_id);
-    fs::write(thumbnail_path, downloaded)
+    let playlist = Playlist::get(url, Some(&options))
         .await
-        .map_err(|_| StatusError::WriteError)?;
-
-    Ok(())
+        .map_err(|error| match error {
+            rusty_ytdl::VideoError::Reqwest(_) => StatusError::NetworkError,
+            _ => StatusError::VideoInfoError,
+        })?;
+
+    let videos = playlist
+        .videos
+        .into_iter()
+        .map(|video| PlaylistVideo {
+            video_id: video.id,
+            title: video.title,
+        })
+        .collect();
+
+    Ok((playlist.name, videos))
 }
@@ -38,6 +38,7 @@ pub fn main() -> iced::Result {
         .font(include_bytes!("../assets/icons.ttf").as_slice())
         .font(include_bytes!("../assets/font.ttf").as_slice())
         .theme(Wavey::theme)
+        .exit_on_close_request(false)
         .run_with(Wavey::new)
 }
 
@@ -45,6 +46,7 @@ pub fn main() -> iced::Result {
 enum Message {
     Pages(ui::UiEvent),
     SettingsLoaded(Option<state::AppSettings>),
+    CloseRequested(window::Id),
 }
 
 struct Wavey {
@@ -56,31 +58,38 @@ impl Wavey {
         log4rs::init_file("logging_config.yaml", Default::default()).unwrap();
 
         log::info!("Starting Wavey.");
-        // Creates the database if it doesn't exist.
-        if !db::check_database_exists() {
-            log::info!("Creating database because it does not exist.");
-            let _ = db::create_database_tables();
+        // Opens the database, creating or upgrading it to the latest schema and
+        // verifying the validity of its data.
+        if let Err(e) = db::start_receiver() {
+            log::error!("Failed to open database: {:?}", e);
         }
 
-        // Verifies validity of the data in the database.
-        let _ = db::verify_data_integrity();
-
         if !json::check_exists() {
             log::info!("Creating settings file because it does not exist.");
             let _ = json::create_file();
         }
 
+        let mut pages = ui::Pages::default();
+        let load_pages = pages.load().map(Message::Pages);
+
         (
-            Self {
-                pages: Default::default(),
-            },
-            Task::perform(json::load_settings(), Message::SettingsLoaded),
+            Self { pages },
+            Task::batch(vec![
+                Task::perform(json::load_settings(), Message::SettingsLoaded),
+                load_pages,
+            ]),
         )
     }
 
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::Pages(x) => self.pages.update(x).map(Message::Pages),
+            // The session is saved one last time before the window closes.
+            Message::CloseRequested(id) => self
+                .pages
+                .save_session()
+                .map(Message::Pages)
+                .chain(window::close(id)),
             Message::SettingsLoaded(settings) => {
                 if let Some(settings) = settings {
                     self.pages
@@ -98,7 +107,10 @@ impl Wavey {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        self.pages.subscription().map(Message::Pages)
+        Subscription::batch(vec![
+            self.pages.subscription().map(Message::Pages),
+            window::close_requests().map(Message::CloseRequested),
+        ])
     }
 
     fn theme(&self) -> iced::Theme {
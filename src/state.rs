@@ -1,13 +1,54 @@
-use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::core::equalizer::EqualizerSettings;
+use crate::core::loudness::NormalisationMode;
+use crate::core::queue::{Queue, RepeatMode};
+use crate::core::transcode::{self, OutputFormat};
+use crate::core::visualiser::VisualiserStyle;
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AppSettings {
     pub theme: String,
+    // The volume slider level, from 0 to 1.
     pub volume: f32,
+    #[serde(default)]
+    pub muted: bool,
     pub ffmpeg_path: String,
     pub rpc_enabled: bool,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default)]
+    pub repeat: RepeatMode,
+    #[serde(default = "default_gapless")]
+    pub gapless: bool,
+    #[serde(default)]
+    pub crossfade_seconds: u64,
+    #[serde(default)]
+    pub normalisation: NormalisationMode,
+    #[serde(default)]
+    pub equalizer: EqualizerSettings,
+    // The output device to play to, None for the system default.
+    #[serde(default)]
+    pub output_device: Option<String>,
+    #[serde(default)]
+    pub preserve_pitch: bool,
+    #[serde(default)]
+    pub visualiser: VisualiserStyle,
+    #[serde(default)]
+    pub download_format: OutputFormat,
+    // In kbps, for lossy formats.
+    #[serde(default = "default_download_bitrate")]
+    pub download_bitrate: u32,
+}
+
+fn default_gapless() -> bool {
+    true
+}
+
+fn default_download_bitrate() -> u32 {
+    transcode::DEFAULT_BITRATE
 }
 
 pub struct PlayerState {
@@ -17,7 +58,13 @@ pub struct PlayerState {
 
     pub is_paused: bool,
     pub seconds_passed: u64,
-    pub queued_tracks: Vec<HashMap<String, String>>,
+    pub queue: Queue,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    pub device_available: bool,
+    pub speed: f32,
+    // Time left on the sleep timer, if one is running.
+    pub sleep: Option<Duration>,
 }
 
 impl Default for AppSettings {
@@ -25,8 +72,20 @@ impl Default for AppSettings {
         Self {
             theme: "Dark".to_string(),
             volume: 0.5,
+            muted: false,
             ffmpeg_path: "".to_string(),
             rpc_enabled: false,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            gapless: default_gapless(),
+            crossfade_seconds: 0,
+            normalisation: NormalisationMode::Off,
+            equalizer: EqualizerSettings::default(),
+            output_device: None,
+            preserve_pitch: false,
+            visualiser: VisualiserStyle::Off,
+            download_format: OutputFormat::Mp3,
+            download_bitrate: default_download_bitrate(),
         }
     }
 }
@@ -38,7 +97,12 @@ impl Default for PlayerState {
             is_paused: true,
             seconds_passed: 0,
             total_duration: 0,
-            queued_tracks: Vec::new(),
+            queue: Queue::default(),
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            device_available: true,
+            speed: 1.0,
+            sleep: None,
             active_video_id: String::new(),
         }
     }
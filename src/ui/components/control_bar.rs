@@ -1,25 +1,67 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::core::db;
+use crate::core::envelope::{self, Envelope};
 use crate::core::format;
+use crate::core::json;
+use crate::core::playback::{PlaybackStatus, SleepTimer};
+use crate::core::queue::RepeatMode;
 use crate::core::request;
+use crate::core::visualiser::VisualiserStyle;
 use crate::state;
+use crate::ui::components::seek_bar;
+use crate::ui::components::visualiser;
 use crate::ui::helpers::helper;
 use crate::ui::helpers::icons;
 use crate::ui::helpers::style;
 
 use iced::widget::Space;
-use iced::widget::{column, container, image, row, slider, text};
-use iced::{time, Alignment, Element, Length, Task};
-
-use tokio::time::Duration;
+use iced::widget::{column, container, image, mouse_area, pick_list, row, slider, stack, text};
+use iced::{Alignment, Element, Length, Task};
+
+// A playback speed as offered in the speed menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed(pub f32);
+
+// The size of the seek bar, and the width of the loop markers drawn over it.
+const PROGRESS_WIDTH: f32 = 350.0;
+const PROGRESS_HEIGHT: f32 = 22.0;
+const MARKER_WIDTH: f32 = 2.0;
+
+const SPEEDS: &[Speed] = &[
+    Speed(0.5),
+    Speed(0.75),
+    Speed(1.0),
+    Speed(1.25),
+    Speed(1.5),
+    Speed(1.75),
+    Speed(2.0),
+    Speed(2.5),
+    Speed(3.0),
+];
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}×", self.0)
+    }
+}
 
 pub struct State {
     pub player_state: state::PlayerState,
 
     active_thumbnail_handle: Option<iced::advanced::image::Handle>,
+    visualiser: visualiser::State,
+    seek_bar: seek_bar::State,
     volume_slider: f32,
+    muted: bool,
+    sleep_timer: Option<SleepTimer>,
+    // The start of a loop whose end hasn't been chosen yet, and the loop the
+    // playback thread is repeating.
+    loop_start: Option<Duration>,
+    ab_loop: Option<(Duration, Duration)>,
     formatted_current_duration: String,
     formatted_total_duration: String,
-    slider_value: f32,
-    slider_is_active: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,12 +69,27 @@ pub enum Event {
     BackwardPressed,
     ForwardPressed,
     PauseToggleAction,
-    Tick,
+    ToggleShuffle,
+    CycleRepeat,
+    ToggleQueue,
+    SpeedSelected(Speed),
+    SpeedSaved(Result<(), db::DatabaseError>),
+    SleepSelected(SleepTimer),
+    CancelSleep,
+    Visualiser(visualiser::Event),
+    SeekBar(seek_bar::Event),
+    EnvelopeLoaded(String, Option<Envelope>),
+    SetLoopStart,
+    SetLoopEnd,
+    ClearLoop,
+    Continue,
+    Status(PlaybackStatus),
     Mute,
     Unmute,
-    SeekTo(f32),
     ProgressChanged(f32),
     VolumeChanged(f32),
+    VolumeReleased,
+    VolumeLoaded(f32, bool),
     InitiatePlay(String, Option<iced::advanced::image::Handle>),
     ThumbnailRetrieved(iced::advanced::image::Handle),
 }
@@ -40,83 +97,195 @@ pub enum Event {
 impl State {
     pub fn update(&mut self, message: Event) -> Task<Event> {
         match message {
-            Event::Mute => {
-                self.volume_slider = 0.0;
+            Event::VolumeLoaded(volume, muted) => {
+                self.volume_slider = volume;
+                self.muted = muted;
 
                 Task::none()
             }
 
-            Event::Unmute => {
-                self.volume_slider = 0.5;
+            // Muting keeps the slider level so unmuting can go back to it.
+            Event::Mute | Event::Unmute => {
+                let muted = message == Event::Mute;
 
-                Task::none()
+                self.muted = muted;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.muted = muted;
+                    }),
+                    |_| Event::Continue,
+                )
             }
 
             Event::VolumeChanged(value) => {
                 self.volume_slider = value;
+                self.muted = false;
 
                 Task::none()
             }
 
-            Event::Tick => {
-                if self.player_state.is_paused {
-                    return Task::none();
+            Event::VolumeReleased => {
+                let volume = self.volume_slider;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.volume = volume;
+                        settings.muted = false;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+
+            Event::Status(status) => match status {
+                PlaybackStatus::TrackStarted(track) => self.load_track(Some(track)),
+                PlaybackStatus::TrackEnded(_) => self.load_track(None),
+                PlaybackStatus::Position(position) => {
+                    self.player_state.seconds_passed = position.as_secs();
+
+                    self.formatted_current_duration =
+                        format::duration(self.player_state.seconds_passed);
+
+                    Task::none()
+                }
+                PlaybackStatus::QueueChanged(queue) => {
+                    self.player_state.queue = queue;
+
+                    Task::none()
                 }
+                PlaybackStatus::OutputChanged(device) => {
+                    self.player_state.device_available = device.is_some();
 
-                self.player_state.seconds_passed += 1;
-                self.slider_value += 1.0;
-
-                if self.player_state.seconds_passed >= self.player_state.total_duration {
-                    self.slider_is_active = false;
-                    self.slider_value = 0.0;
-                    self.player_state.total_duration = 0;
-                    self.player_state.seconds_passed = 0;
-
-                    self.formatted_current_duration = "0:00".to_string();
-                    self.formatted_total_duration = "0:00".to_string();
-                    self.player_state.display_name = "Nothing is playing.".to_string();
-
-                    let index = self.player_state.queued_tracks.iter().position(|x| {
-                        x.get("video_id").unwrap() == &self.player_state.active_video_id
-                    });
-
-                    if index.is_some() {
-                        let next_index = index.unwrap() + 1;
-
-                        if next_index < self.player_state.queued_tracks.len() {
-                            let next_track =
-                                self.player_state.queued_tracks.get(next_index).unwrap();
-                            let video_id = next_track.get("video_id").unwrap().to_string();
-                            let display_name = next_track.get("display_name").unwrap().to_string();
-                            let total_duration =
-                                next_track.get("duration").unwrap().parse::<u64>().unwrap();
-
-                            self.player_state.display_name = display_name.clone();
-                            self.slider_is_active = true;
-                            self.player_state.total_duration = total_duration;
-                            self.player_state.active_video_id = video_id.clone();
-
-                            return Task::perform(
-                                request::request_thumbnail_by_video_id(video_id),
-                                Event::ThumbnailRetrieved,
-                            );
-                        }
+                    Task::none()
+                }
+                PlaybackStatus::SpeedChanged(speed) => {
+                    self.player_state.speed = speed;
+
+                    Task::none()
+                }
+                PlaybackStatus::SleepChanged(remaining) => {
+                    self.player_state.sleep = remaining;
+
+                    if remaining.is_none() {
+                        self.sleep_timer = None;
                     }
 
+                    Task::none()
+                }
+                PlaybackStatus::LoopStartChanged(start) => {
+                    self.loop_start = start;
+
+                    Task::none()
+                }
+                PlaybackStatus::LoopChanged(ab_loop) => {
+                    self.ab_loop = ab_loop;
+
+                    Task::none()
+                }
+                PlaybackStatus::Playing => {
+                    self.player_state.is_paused = false;
+
+                    Task::none()
+                }
+                PlaybackStatus::Paused => {
+                    self.player_state.is_paused = true;
+
+                    Task::none()
+                }
+                PlaybackStatus::Error(_) => Task::none(),
+            },
+            Event::BackwardPressed => Task::none(),
+            Event::ForwardPressed => Task::none(),
+
+            Event::ToggleShuffle => {
+                let shuffle = !self.player_state.shuffle;
+
+                self.player_state.shuffle = shuffle;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.shuffle = shuffle;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::CycleRepeat => {
+                let repeat = self.player_state.repeat.cycle();
+
+                self.player_state.repeat = repeat;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.repeat = repeat;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::ToggleQueue => Task::none(),
+            Event::SpeedSelected(speed) => {
+                self.player_state.speed = speed.0;
+
+                if self.player_state.active_video_id.is_empty() {
                     return Task::none();
                 }
 
-                self.formatted_current_duration =
-                    format::duration(self.player_state.seconds_passed);
-                self.formatted_total_duration = format::duration(self.player_state.total_duration);
+                Task::perform(
+                    db::set_speed(self.player_state.active_video_id.clone(), speed.0),
+                    Event::SpeedSaved,
+                )
+            }
+            Event::SpeedSaved(Ok(())) => Task::none(),
+            Event::SpeedSaved(Err(e)) => {
+                log::error!("Failed to save playback speed: {:?}", e);
 
                 Task::none()
             }
-            Event::BackwardPressed => Task::none(),
-            Event::ForwardPressed => Task::none(),
+            Event::SleepSelected(timer) => {
+                self.sleep_timer = Some(timer);
+                self.player_state.sleep = None;
+
+                Task::none()
+            }
+            Event::CancelSleep => {
+                self.sleep_timer = None;
+                self.player_state.sleep = None;
+
+                Task::none()
+            }
+            Event::Visualiser(event) => {
+                let cycled = event == visualiser::Event::CycleStyle;
+
+                let _ = self.visualiser.update(event);
+
+                if !cycled {
+                    return Task::none();
+                }
+
+                let style = self.visualiser.style;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.visualiser = style;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::SeekBar(event) => self.seek_bar.update(event).map(Event::SeekBar),
+            // A track skipped past before its envelope loaded is ignored.
+            Event::EnvelopeLoaded(video_id, envelope) => {
+                if video_id != self.player_state.active_video_id {
+                    return Task::none();
+                }
+
+                self.seek_bar
+                    .update(seek_bar::Event::EnvelopeLoaded(envelope))
+                    .map(Event::SeekBar)
+            }
+            // The loop is shown once the playback thread has marked it.
+            Event::SetLoopStart | Event::SetLoopEnd | Event::ClearLoop => Task::none(),
+            Event::Continue => Task::none(),
 
             Event::ProgressChanged(value) => {
-                self.slider_value = value;
                 self.player_state.seconds_passed = value as u64;
 
                 self.formatted_current_duration =
@@ -127,8 +296,7 @@ impl State {
             }
 
             Event::InitiatePlay(video_id, handle) => {
-                self.slider_value = 0.0;
-                self.slider_is_active = true;
+                self.player_state.seconds_passed = 0;
                 self.formatted_current_duration = "0:00".to_string();
                 self.formatted_total_duration = "0:00".to_string();
 
@@ -150,23 +318,98 @@ impl State {
                 Task::none()
             }
 
-            Event::PauseToggleAction => {
-                if self.player_state.is_paused {
-                    self.player_state.is_paused = false;
-                } else {
-                    self.player_state.is_paused = true;
-                }
+            // Shown as paused or playing once the playback thread says so.
+            Event::PauseToggleAction => Task::none(),
+        }
+    }
 
-                Task::none()
-            }
+    pub fn visualiser_style(&self) -> VisualiserStyle {
+        self.visualiser.style
+    }
 
-            Event::SeekTo(value) => {
-                self.player_state.seconds_passed = value as u64;
-                self.slider_value = value;
+    pub fn sleep_timer(&self) -> Option<SleepTimer> {
+        self.sleep_timer
+    }
 
-                Task::none()
-            }
+    // Marks where the loop starts and ends along the progress slider.
+    fn loop_markers(&self) -> Option<Element<'_, Event>> {
+        let markers = match (self.ab_loop, self.loop_start) {
+            (Some((start, end)), _) => vec![start, end],
+            (None, Some(start)) => vec![start],
+            (None, None) => return None,
+        };
+
+        let total = self.player_state.total_duration as f32;
+
+        if total == 0.0 {
+            return None;
         }
+
+        let mut row = row![].height(Length::Fill);
+        let mut x = 0.0;
+
+        for marker in markers {
+            let position =
+                (marker.as_secs_f32() / total).min(1.0) * (PROGRESS_WIDTH - MARKER_WIDTH);
+
+            row = row
+                .push(Space::with_width((position - x).max(0.0)))
+                .push(container(Space::new(MARKER_WIDTH, Length::Fill)).style(style::loop_marker));
+
+            x = position.max(x) + MARKER_WIDTH;
+        }
+
+        Some(row.into())
+    }
+
+    // Shows `track` as the one now playing, or nothing if the queue has ended.
+    fn load_track(&mut self, track: Option<db::Track>) -> Task<Event> {
+        self.player_state.seconds_passed = 0;
+        self.formatted_current_duration = "0:00".to_string();
+        self.loop_start = None;
+
+        let Some(track) = track else {
+            self.player_state.total_duration = 0;
+            self.player_state.display_name = "Nothing is playing.".to_string();
+            self.formatted_total_duration = "0:00".to_string();
+
+            return Task::none();
+        };
+
+        let new_track = self.player_state.active_video_id != track.video_id;
+
+        // The envelope is kept when a track plays again, so its seek bar
+        // doesn't flash empty while it is reloaded.
+        let envelope = if new_track {
+            let _ = self.seek_bar.update(seek_bar::Event::EnvelopeLoaded(None));
+            let video_id = track.video_id.clone();
+
+            Task::perform(envelope::load(track.clone()), move |envelope| {
+                Event::EnvelopeLoaded(video_id.clone(), envelope)
+            })
+        } else {
+            Task::none()
+        };
+
+        // Playing a track from a page already provides its thumbnail.
+        let same_track = !new_track && self.active_thumbnail_handle.is_some();
+
+        self.player_state.display_name = track.display_name;
+        self.player_state.total_duration = track.duration.as_secs();
+        self.player_state.active_video_id = track.video_id.clone();
+        self.formatted_total_duration = format::duration(self.player_state.total_duration);
+
+        if same_track {
+            return envelope;
+        }
+
+        Task::batch(vec![
+            envelope,
+            Task::perform(
+                request::request_thumbnail_by_video_id(track.video_id),
+                Event::ThumbnailRetrieved,
+            ),
+        ])
     }
 
     pub fn view(&self) -> iced::Element<Event> {
@@ -182,30 +425,132 @@ impl State {
                 helper::action(icons::pause_icon(), "Pause", Some(Event::PauseToggleAction));
         }
 
-        if self.volume_slider == 0.0 {
-            volume_icon = helper::action(icons::volume_off(), "Unmute", Some(Event::Mute));
+        if self.muted {
+            volume_icon = helper::action(icons::volume_off(), "Unmute", Some(Event::Unmute));
         } else {
-            volume_icon = helper::action(icons::volume_on(), "Mute", Some(Event::Unmute));
+            volume_icon = helper::action(icons::volume_on(), "Mute", Some(Event::Mute));
         }
 
-        if self.active_thumbnail_handle.is_none() {
-            thumbnail = container(text("")).into();
+        let repeat = match self.player_state.repeat {
+            RepeatMode::Off => {
+                helper::toggle_action(icons::repeat_icon(), "Repeat", Event::CycleRepeat, false)
+            }
+            RepeatMode::All => {
+                helper::toggle_action(icons::repeat_icon(), "Repeat all", Event::CycleRepeat, true)
+            }
+            RepeatMode::One => helper::toggle_action(
+                row![icons::repeat_icon(), text("1").size(10)].into(),
+                "Repeat one",
+                Event::CycleRepeat,
+                true,
+            ),
+        };
+
+        // Clicking the thumbnail switches to the visualiser, and clicking the
+        // visualiser switches between its styles and back.
+        if self.visualiser.style != VisualiserStyle::Off {
+            thumbnail = self.visualiser.view(90.0, 60.0).map(Event::Visualiser);
+        } else if self.active_thumbnail_handle.is_none() {
+            thumbnail = mouse_area(container(text("")).width(90).height(60))
+                .on_press(Event::Visualiser(visualiser::Event::CycleStyle))
+                .into();
         } else {
-            thumbnail = container(
+            thumbnail = mouse_area(container(
                 image(self.active_thumbnail_handle.clone().unwrap())
                     .width(90)
                     .height(60),
-            )
+            ))
+            .on_press(Event::Visualiser(visualiser::Event::CycleStyle))
             .into();
         }
 
+        // While a sleep timer runs it shows what is left, and cancels it when
+        // pressed.
+        let sleep: Element<Event> = match (self.sleep_timer, self.player_state.sleep) {
+            (None, _) => pick_list(SleepTimer::ALL, None::<SleepTimer>, Event::SleepSelected)
+                .placeholder("Sleep")
+                .text_size(12)
+                .into(),
+            (Some(timer), remaining) => helper::toggle_action(
+                text(match remaining {
+                    Some(remaining) => format!("Sleep {}", format::duration(remaining.as_secs())),
+                    None => format!("Sleep: {}", timer),
+                })
+                .size(12)
+                .into(),
+                "Cancel sleep timer",
+                Event::CancelSleep,
+                true,
+            ),
+        };
+
+        // Pressed once to mark the start of a loop, again to mark its end and
+        // start looping, and a third time to stop.
+        let ab_loop = match (self.ab_loop, self.loop_start) {
+            (Some(_), _) => helper::toggle_action(
+                text("A-B").size(12).into(),
+                "Stop looping",
+                Event::ClearLoop,
+                true,
+            ),
+            (None, Some(_)) => helper::toggle_action(
+                text("A-").size(12).into(),
+                "Set loop end",
+                Event::SetLoopEnd,
+                true,
+            ),
+            (None, None) => helper::toggle_action(
+                text("A-B").size(12).into(),
+                "Set loop start",
+                Event::SetLoopStart,
+                false,
+            ),
+        };
+
+        let total = self.player_state.total_duration as f32;
+
+        let progress = self
+            .seek_bar
+            .view(
+                if total > 0.0 {
+                    self.player_state.seconds_passed as f32 / total
+                } else {
+                    0.0
+                },
+                PROGRESS_WIDTH,
+                PROGRESS_HEIGHT,
+            )
+            .map(move |event| match event {
+                seek_bar::Event::Seek(fraction) => {
+                    Event::ProgressChanged((fraction * total).floor())
+                }
+                event => Event::SeekBar(event),
+            });
+
+        let progress: Element<Event> = match self.loop_markers() {
+            Some(markers) => stack![progress, markers].into(),
+            None => progress.into(),
+        };
+
+        let display_name = if self.player_state.device_available {
+            self.player_state.display_name.clone()
+        } else {
+            format!("{} (no audio device)", self.player_state.display_name)
+        };
+
         container(
             row![
                 Space::with_width(10),
                 container(thumbnail).width(Length::FillPortion(3)),
                 column![
-                    text(&self.player_state.display_name).size(14),
+                    text(display_name).size(14),
                     row![
+                        helper::toggle_action(
+                            text("Shuffle").size(12).into(),
+                            "Shuffle",
+                            Event::ToggleShuffle,
+                            self.player_state.shuffle,
+                        ),
                         helper::action(
                             icons::backward_icon(),
                             "Back",
@@ -217,17 +562,14 @@ impl State {
                             "Forward",
                             Some(Event::ForwardPressed)
                         ),
+                        repeat,
+                        ab_loop,
                     ]
+                    .align_y(Alignment::Center)
                     .spacing(10),
                     row![
                         text(&self.formatted_current_duration).size(14),
-                        slider(
-                            0.0..=self.player_state.total_duration as f32,
-                            self.slider_value,
-                            Event::ProgressChanged
-                        )
-                        .width(350)
-                        .step(1.0),
+                        progress,
                         text(&self.formatted_total_duration).size(14),
                     ]
                     .spacing(10),
@@ -238,10 +580,23 @@ impl State {
                 .width(Length::FillPortion(7)),
                 container(
                     row![
+                        pick_list(
+                            SPEEDS,
+                            Some(Speed(self.player_state.speed)),
+                            Event::SpeedSelected
+                        )
+                        .text_size(12),
+                        sleep,
+                        helper::action(icons::list_icon(), "Queue", Some(Event::ToggleQueue)),
                         volume_icon,
-                        slider(0.0..=1.0, self.volume_slider, Event::VolumeChanged)
-                            .step(0.1)
-                            .width(120)
+                        slider(
+                            0.0..=1.0,
+                            if self.muted { 0.0 } else { self.volume_slider },
+                            Event::VolumeChanged
+                        )
+                        .on_release(Event::VolumeReleased)
+                        .step(0.05)
+                        .width(120)
                     ]
                     .align_y(Alignment::Center)
                     .spacing(10),
@@ -258,26 +613,22 @@ impl State {
         .padding(10)
         .into()
     }
-
-    pub fn subscription(&self) -> iced::Subscription<Event> {
-        if self.slider_is_active {
-            return time::every(Duration::from_secs(1)).map(|_| Event::Tick);
-        }
-
-        iced::Subscription::none()
-    }
 }
 
 impl Default for State {
     fn default() -> Self {
         Self {
             active_thumbnail_handle: None,
+            visualiser: visualiser::State::default(),
+            seek_bar: seek_bar::State::default(),
             player_state: state::PlayerState::default(),
             formatted_current_duration: String::from("0:00"),
             formatted_total_duration: String::from("0:00"),
-            slider_value: 0.0,
-            slider_is_active: false,
             volume_slider: 0.5,
+            muted: false,
+            sleep_timer: None,
+            loop_start: None,
+            ab_loop: None,
         }
     }
 }
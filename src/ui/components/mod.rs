@@ -1,4 +1,7 @@
 pub mod control_bar;
 pub mod nav;
+pub mod queue_panel;
+pub mod seek_bar;
 pub mod sidebar;
 pub mod toast;
+pub mod visualiser;
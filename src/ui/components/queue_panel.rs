@@ -0,0 +1,138 @@
+use crate::core::format;
+use crate::core::queue::Queue;
+use crate::ui::helpers::style;
+
+use iced::widget::{button, column, container, horizontal_space, row, scrollable, text, Column};
+use iced::{Alignment, Element, Length, Task};
+
+pub struct State {
+    pub visible: bool,
+    queue: Queue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Toggle,
+    QueueChanged(Queue),
+    JumpTo(usize),
+    Remove(usize),
+    Move(usize, usize),
+    Clear,
+}
+
+impl State {
+    pub fn update(&mut self, message: Event) -> Task<Event> {
+        match message {
+            Event::Toggle => {
+                self.visible = !self.visible;
+
+                Task::none()
+            }
+            Event::QueueChanged(queue) => {
+                self.queue = queue;
+
+                Task::none()
+            }
+            Event::JumpTo(_) => Task::none(),
+            Event::Remove(_) => Task::none(),
+            Event::Move(_, _) => Task::none(),
+            Event::Clear => Task::none(),
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let position = self.queue.position();
+        let last = self.queue.len().saturating_sub(1);
+
+        let mut history = column![].spacing(5);
+        let mut now_playing = column![].spacing(5);
+        let mut up_next = column![].spacing(5);
+
+        for (index, track) in self.queue.entries().enumerate() {
+            let mut entry = row![
+                button(text(&track.display_name).size(14))
+                    .style(style::sidebar_button)
+                    .on_press(Event::JumpTo(index)),
+                horizontal_space(),
+                text(format::duration(track.duration.as_secs())).size(12),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(5);
+
+            if index > position {
+                entry = entry
+                    .push(queue_button(
+                        "Up",
+                        (index > position + 1).then_some(Event::Move(index, index - 1)),
+                    ))
+                    .push(queue_button(
+                        "Down",
+                        (index < last).then_some(Event::Move(index, index + 1)),
+                    ));
+            }
+
+            entry = entry.push(queue_button("Remove", Some(Event::Remove(index))));
+
+            if index < position {
+                history = history.push(entry);
+            } else if index == position {
+                now_playing = now_playing.push(entry);
+            } else {
+                up_next = up_next.push(entry);
+            }
+        }
+
+        let content = column![
+            row![
+                text("Queue").size(22),
+                horizontal_space(),
+                button(text("Clear").size(14))
+                    .style(style::sidebar_button)
+                    .on_press(Event::Clear),
+                button(text("Close").size(14))
+                    .style(style::sidebar_button)
+                    .on_press(Event::Toggle),
+            ]
+            .align_y(Alignment::Center),
+            scrollable(
+                column![
+                    section("Now Playing", now_playing),
+                    section("Up Next", up_next),
+                    section("History", history),
+                ]
+                .spacing(15)
+            )
+            .height(Length::Fill),
+        ]
+        .spacing(10);
+
+        container(content)
+            .style(style::dynamic_colour)
+            .height(Length::Fill)
+            .width(320)
+            .padding(10)
+            .into()
+    }
+}
+
+fn section<'a>(title: &'a str, entries: Column<'a, Event>) -> Element<'a, Event> {
+    column![text(title).size(12).style(style::sidebar_text), entries]
+        .spacing(5)
+        .into()
+}
+
+fn queue_button(label: &str, on_press: Option<Event>) -> Element<'_, Event> {
+    button(text(label).size(12))
+        .style(style::sidebar_button)
+        .on_press_maybe(on_press)
+        .into()
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            queue: Queue::default(),
+        }
+    }
+}
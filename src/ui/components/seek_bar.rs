@@ -0,0 +1,213 @@
+use crate::core::envelope::Envelope;
+
+use iced::mouse;
+use iced::widget::canvas::{self, event, Cache, Frame, Geometry};
+use iced::{Color, Element, Point, Rectangle, Renderer, Size, Task, Theme};
+
+// The gap between the envelope's bars, as a fraction of the width each bar is
+// given.
+const BAR_GAP: f32 = 0.2;
+
+// The height of the line drawn while a track has no envelope yet.
+const LINE_HEIGHT: f32 = 4.0;
+
+pub struct State {
+    envelope: Option<Envelope>,
+    // The whole envelope in its unplayed colours. The played part is drawn
+    // over it each frame, so it only needs redrawing for a new envelope.
+    cache: Cache,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    EnvelopeLoaded(Option<Envelope>),
+    // Where along the track was clicked, from 0 to 1.
+    Seek(f32),
+}
+
+// What the canvas is drawn from: the seek bar and how far through the track
+// playback is, from 0 to 1.
+struct SeekBar<'a> {
+    state: &'a State,
+    progress: f32,
+}
+
+// Whether the mouse is held down on the seek bar, so dragging keeps seeking.
+#[derive(Default)]
+struct Dragging(bool);
+
+impl State {
+    pub fn update(&mut self, message: Event) -> Task<Event> {
+        match message {
+            Event::EnvelopeLoaded(envelope) => {
+                self.envelope = envelope;
+                self.cache.clear();
+
+                Task::none()
+            }
+            Event::Seek(_) => Task::none(),
+        }
+    }
+
+    pub fn view(&self, progress: f32, width: f32, height: f32) -> Element<'_, Event> {
+        canvas::Canvas::new(SeekBar {
+            state: self,
+            progress: progress.clamp(0.0, 1.0),
+        })
+        .width(width)
+        .height(height)
+        .into()
+    }
+
+    // Draws the bars of the envelope up to `progress` along it.
+    fn draw_envelope(
+        envelope: &Envelope,
+        frame: &mut Frame,
+        progress: f32,
+        peak_colour: Color,
+        rms_colour: Color,
+    ) {
+        let size = frame.size();
+        let slot = size.width / envelope.peaks.len().max(1) as f32;
+        let middle = size.height / 2.0;
+        let played = (progress * envelope.peaks.len() as f32).ceil() as usize;
+
+        for (index, (peak, rms)) in envelope
+            .peaks
+            .iter()
+            .zip(&envelope.rms)
+            .take(played)
+            .enumerate()
+        {
+            let x = index as f32 * slot;
+            let width = (slot * (1.0 - BAR_GAP)).max(1.0);
+            let peak = (size.height * peak).max(1.0);
+            let rms = (size.height * rms).max(1.0);
+
+            frame.fill_rectangle(
+                Point::new(x, middle - peak / 2.0),
+                Size::new(width, peak),
+                peak_colour,
+            );
+            frame.fill_rectangle(
+                Point::new(x, middle - rms / 2.0),
+                Size::new(width, rms),
+                rms_colour,
+            );
+        }
+    }
+
+    // A plain bar for tracks whose envelope hasn't been generated yet.
+    fn draw_line(frame: &mut Frame, progress: f32, colour: Color) {
+        let size = frame.size();
+
+        frame.fill_rectangle(
+            Point::new(0.0, (size.height - LINE_HEIGHT) / 2.0),
+            Size::new(size.width * progress, LINE_HEIGHT),
+            colour,
+        );
+    }
+}
+
+impl SeekBar<'_> {
+    // Dragging past either end of the seek bar stays at that end.
+    fn seek(&self, bounds: Rectangle, position: Point) -> Event {
+        Event::Seek(((position.x - bounds.x) / bounds.width).clamp(0.0, 1.0))
+    }
+}
+
+impl canvas::Program<Event> for SeekBar<'_> {
+    type State = Dragging;
+
+    fn update(
+        &self,
+        dragging: &mut Dragging,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<Event>) {
+        match event {
+            canvas::Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                let Some(position) = cursor.position_over(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+
+                dragging.0 = true;
+
+                (event::Status::Captured, Some(self.seek(bounds, position)))
+            }
+            canvas::Event::Mouse(mouse::Event::CursorMoved { position }) if dragging.0 => {
+                (event::Status::Captured, Some(self.seek(bounds, position)))
+            }
+            canvas::Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left))
+                if dragging.0 =>
+            {
+                dragging.0 = false;
+
+                (event::Status::Captured, None)
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _dragging: &Dragging,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let palette = theme.extended_palette();
+
+        let unplayed = self.state.cache.draw(renderer, bounds.size(), |frame| {
+            match &self.state.envelope {
+                Some(envelope) => State::draw_envelope(
+                    envelope,
+                    frame,
+                    1.0,
+                    palette.background.weak.color,
+                    palette.background.strong.color,
+                ),
+                None => State::draw_line(frame, 1.0, palette.background.strong.color),
+            }
+        });
+
+        let mut played = Frame::new(renderer, bounds.size());
+
+        match &self.state.envelope {
+            Some(envelope) => State::draw_envelope(
+                envelope,
+                &mut played,
+                self.progress,
+                palette.primary.weak.color,
+                palette.primary.strong.color,
+            ),
+            None => State::draw_line(&mut played, self.progress, palette.primary.strong.color),
+        }
+
+        vec![unplayed, played.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        dragging: &Dragging,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if dragging.0 || cursor.is_over(bounds) {
+            mouse::Interaction::Pointer
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            envelope: None,
+            cache: Cache::new(),
+        }
+    }
+}
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use crate::core::db;
 use crate::ui::helpers::helper;
 use crate::ui::helpers::icons;
@@ -9,7 +7,7 @@ use iced::widget::{button, column, container, scrollable, text, Space};
 use iced::{Alignment, Length, Task};
 
 pub struct State {
-    playlists: Vec<HashMap<String, String>>,
+    playlists: Vec<db::Playlist>,
     collapsed: bool,
 }
 
@@ -20,8 +18,11 @@ pub enum Event {
     OpenSettings,
     OpenPlaylists,
     OpenDownload,
+    OpenDownloads,
+    OpenEqualizer,
     CreatePlaylist,
     UpdatePlaylists,
+    PlaylistsLoaded(Result<Vec<db::Playlist>, db::DatabaseError>),
     OpenPlaylist(i32),
 }
 
@@ -36,7 +37,15 @@ impl State {
             Event::OpenPlaylist(_value) => Task::none(),
 
             Event::UpdatePlaylists => {
-                self.playlists = db::get_all_playlists();
+                Task::perform(db::get_all_playlists(), Event::PlaylistsLoaded)
+            }
+            Event::PlaylistsLoaded(Ok(playlists)) => {
+                self.playlists = playlists;
+
+                Task::none()
+            }
+            Event::PlaylistsLoaded(Err(e)) => {
+                log::error!("Failed to get playlists: {:?}", e);
 
                 Task::none()
             }
@@ -45,6 +54,8 @@ impl State {
             Event::OpenSettings => Task::none(),
             Event::OpenPlaylists => Task::none(),
             Event::OpenDownload => Task::none(),
+            Event::OpenDownloads => Task::none(),
+            Event::OpenEqualizer => Task::none(),
         }
     }
 
@@ -55,7 +66,7 @@ impl State {
 
         for playlist in &self.playlists {
             col = col.push(
-                button(text(playlist.get("name").unwrap()))
+                button(text(&playlist.name))
                     .style(style::sidebar_button)
                     .on_press(Event::OpenPlaylist(i)),
             );
@@ -73,6 +84,12 @@ impl State {
                         "Add Music",
                         Some(Event::OpenDownload)
                     ),
+                    helper::action(
+                        icons::download_icon(),
+                        "Downloads",
+                        Some(Event::OpenDownloads)
+                    ),
+                    helper::action(icons::volume_on(), "Equalizer", Some(Event::OpenEqualizer)),
                     helper::action(
                         icons::settings_icon(),
                         "Settings",
@@ -106,6 +123,16 @@ impl State {
                         "Add Music",
                         Some(Event::OpenDownload)
                     ),
+                    helper::action_with_text(
+                        icons::download_icon(),
+                        "Downloads",
+                        Some(Event::OpenDownloads)
+                    ),
+                    helper::action_with_text(
+                        icons::volume_on(),
+                        "Equalizer",
+                        Some(Event::OpenEqualizer)
+                    ),
                     helper::action_with_text(
                         icons::settings_icon(),
                         "Settings",
@@ -136,7 +163,7 @@ impl Default for State {
     fn default() -> Self {
         Self {
             collapsed: false,
-            playlists: db::get_all_playlists(),
+            playlists: Vec::new(),
         }
     }
 }
@@ -0,0 +1,144 @@
+use crate::core::visualiser::{Spectrum, VisualiserStyle};
+
+use iced::mouse;
+use iced::widget::canvas::{self, Cache, Frame, Geometry, Path, Stroke};
+use iced::widget::{canvas as canvas_widget, mouse_area};
+use iced::{Element, Point, Rectangle, Renderer, Size, Task, Theme};
+
+// The gap between bars, as a fraction of the width each bar is given.
+const BAR_GAP: f32 = 0.25;
+
+pub struct State {
+    pub style: VisualiserStyle,
+
+    spectrum: Spectrum,
+    cache: Cache,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    StyleLoaded(VisualiserStyle),
+    Spectrum(Spectrum),
+    CycleStyle,
+}
+
+impl State {
+    pub fn update(&mut self, message: Event) -> Task<Event> {
+        match message {
+            Event::StyleLoaded(style) => {
+                self.style = style;
+
+                Task::none()
+            }
+            Event::Spectrum(spectrum) => {
+                self.spectrum = spectrum;
+                self.cache.clear();
+
+                Task::none()
+            }
+            Event::CycleStyle => {
+                self.style = self.style.cycle();
+                self.spectrum = Spectrum::default();
+                self.cache.clear();
+
+                Task::none()
+            }
+        }
+    }
+
+    pub fn view(&self, width: f32, height: f32) -> Element<'_, Event> {
+        mouse_area(canvas_widget(self).width(width).height(height))
+            .on_press(Event::CycleStyle)
+            .into()
+    }
+
+    fn draw_bars(&self, frame: &mut Frame, theme: &Theme) {
+        let size = frame.size();
+        let slot = size.width / self.spectrum.bars.len() as f32;
+        let colour = theme.extended_palette().primary.strong.color;
+
+        for (index, level) in self.spectrum.bars.iter().enumerate() {
+            let height = (size.height * level).max(1.0);
+
+            frame.fill_rectangle(
+                Point::new(index as f32 * slot, size.height - height),
+                Size::new(slot * (1.0 - BAR_GAP), height),
+                colour,
+            );
+        }
+    }
+
+    fn draw_waveform(&self, frame: &mut Frame, theme: &Theme) {
+        let size = frame.size();
+        let step = size.width / (self.spectrum.waveform.len() - 1).max(1) as f32;
+        let middle = size.height / 2.0;
+
+        let path = Path::new(|builder| {
+            for (index, sample) in self.spectrum.waveform.iter().enumerate() {
+                let point = Point::new(
+                    index as f32 * step,
+                    middle - sample.clamp(-1.0, 1.0) * middle,
+                );
+
+                if index == 0 {
+                    builder.move_to(point);
+                } else {
+                    builder.line_to(point);
+                }
+            }
+        });
+
+        frame.stroke(
+            &path,
+            Stroke::default()
+                .with_width(1.5)
+                .with_color(theme.extended_palette().primary.strong.color),
+        );
+    }
+}
+
+impl canvas::Program<Event> for State {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let geometry = self
+            .cache
+            .draw(renderer, bounds.size(), |frame| match self.style {
+                VisualiserStyle::Bars => self.draw_bars(frame, theme),
+                VisualiserStyle::Waveform => self.draw_waveform(frame, theme),
+                VisualiserStyle::Off => (),
+            });
+
+        vec![geometry]
+    }
+
+    fn mouse_interaction(
+        &self,
+        _state: &(),
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if cursor.is_over(bounds) {
+            mouse::Interaction::Pointer
+        } else {
+            mouse::Interaction::default()
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            style: VisualiserStyle::Off,
+            spectrum: Spectrum::default(),
+            cache: Cache::new(),
+        }
+    }
+}
@@ -89,13 +89,33 @@ pub fn action<'a, Message: Clone + 'a>(
     }
 }
 
+// An action that is highlighted while the setting it toggles is on.
+pub fn toggle_action<'a, Message: Clone + 'a>(
+    content: Element<'a, Message>,
+    label: &'a str,
+    on_press: Message,
+    active: bool,
+) -> Element<'a, Message> {
+    let action = button(content).on_press(on_press).style(if active {
+        style::active_button
+    } else {
+        style::button_theme
+    });
+
+    tooltip(action, label, tooltip::Position::FollowCursor)
+        .style(container::rounded_box)
+        .into()
+}
+
 pub fn track_list_item<'a, Message: Clone + 'a>(
     thumbnail_handle: iced::advanced::image::Handle,
     label: &'a str,
-    duration: &'a str,
+    duration: u64,
     play_event: Message,
     edit_event: Message,
     add_playlist_event: Message,
+    play_next_event: Message,
+    enqueue_event: Message,
     hovered: bool,
 ) -> Element<'a, Message> {
     let mut content = row![]
@@ -110,7 +130,7 @@ pub fn track_list_item<'a, Message: Clone + 'a>(
         .push(Space::with_width(10))
         .push(text(label))
         .push(horizontal_space())
-        .push(text(format::duration(duration.parse().unwrap())))
+        .push(text(format::duration(duration)))
         .push(Space::with_width(15));
 
     if hovered {
@@ -125,6 +145,18 @@ pub fn track_list_item<'a, Message: Clone + 'a>(
                 .on_press(add_playlist_event)
                 .style(style::button_theme),
         );
+
+        content = content.push(action(
+            icons::forward_icon(),
+            "Play next",
+            Some(play_next_event),
+        ));
+
+        content = content.push(action(
+            icons::list_icon(),
+            "Add to queue",
+            Some(enqueue_event),
+        ));
     }
 
     container(content).style(style::track_list_item).into()
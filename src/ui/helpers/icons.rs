@@ -67,9 +67,9 @@ pub fn search_icon<'a, Message>() -> Element<'a, Message> {
     icon('\u{0e80e}')
 }
 
-// pub fn repeat_icon<'a, Message>() -> Element<'a, Message> {
-//     icon('\u{0e801}')
-// }
+pub fn repeat_icon<'a, Message>() -> Element<'a, Message> {
+    icon('\u{0e801}')
+}
 
 // pub fn upload_icon<'a, Message>() -> Element<'a, Message> {
 //     icon('\u{0e80f}')
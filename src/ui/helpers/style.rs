@@ -43,6 +43,16 @@ pub fn button_theme(theme: &Theme, _status: Status) -> button::Style {
     }
 }
 
+pub fn active_button(theme: &Theme, _status: Status) -> button::Style {
+    let palette = theme.extended_palette();
+
+    button::Style {
+        background: Some(Background::Color(Color::TRANSPARENT)),
+        text_color: palette.primary.base.color,
+        ..button::Style::default()
+    }
+}
+
 pub fn sidebar_button(theme: &Theme, _status: Status) -> button::Style {
     let palette = theme.extended_palette();
 
@@ -92,6 +102,16 @@ pub fn track_list_item(theme: &Theme) -> container::Style {
     }
 }
 
+// The markers drawn over the progress slider at the ends of a loop.
+pub fn loop_marker(theme: &Theme) -> container::Style {
+    let palette = theme.extended_palette();
+
+    container::Style {
+        background: Some(Background::Color(palette.primary.strong.color)),
+        ..Default::default()
+    }
+}
+
 fn offset_colour(colour: Color, hovered: bool, is_dark: bool) -> Color {
     let r = colour.r;
     let g = colour.g;
@@ -3,14 +3,26 @@ mod helpers;
 mod pages;
 
 use std::sync::mpsc;
+use std::time::Duration;
 
+use crate::core::db;
+use crate::core::download;
+use crate::core::envelope;
+use crate::core::format;
+use crate::core::loudness;
 use crate::core::playback;
+use crate::core::queue::Queue;
 use crate::core::rpc;
+use crate::core::session;
+use crate::core::visualiser::{self, VisualiserStyle};
 use crate::state;
 use components::control_bar;
+use components::queue_panel;
 use components::sidebar;
 use components::toast;
 use pages::add_music;
+use pages::downloads;
+use pages::equalizer;
 use pages::ffmpeg;
 use pages::playlist;
 use pages::settings;
@@ -23,6 +35,10 @@ use iced::keyboard::key;
 use iced::widget;
 use iced::widget::{column, row};
 use iced::{Subscription, Task, Theme};
+use tokio::sync::mpsc as async_mpsc;
+
+// How often the playback session is saved while wavey is open.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(15);
 
 pub struct Pages {
     pub current_page: Page,
@@ -31,20 +47,25 @@ pub struct Pages {
     nav: components::nav::State,
     sidebar: components::sidebar::State,
     controls: components::control_bar::State,
+    queue_panel: queue_panel::State,
 
     track_list: track_list::State,
     settings: settings::State,
     add_music: add_music::State,
+    downloads: downloads::State,
     ffmpeg: ffmpeg::State,
     playlist: playlist::State,
+    equalizer: equalizer::State,
 
     playback_sender: mpsc::Sender<playback::AudioEvent>,
+    download_sender: async_mpsc::UnboundedSender<download::DownloadEvent>,
     rpc_sender: Option<mpsc::Sender<rpc::RpcEvent>>,
 
     toasts: Vec<toast::Toast>,
     theme: Theme,
-    track_list_loaded: bool,
     rpc_enabled: bool,
+    // The session as last saved, so it is only written again once it changes.
+    last_session: Option<session::Session>,
 }
 
 #[derive(Default)]
@@ -53,8 +74,10 @@ pub enum Page {
     TrackList,
     Settings,
     AddMusic,
+    Downloads,
     FFmpeg,
     Playlist,
+    Equalizer,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -62,15 +85,25 @@ pub enum UiEvent {
     NavAction(components::nav::Event),
     SidebarAction(components::sidebar::Event),
     ControlsAction(components::control_bar::Event),
+    QueuePanelAction(queue_panel::Event),
 
     TrackListAction(track_list::Event),
     SettingsAction(settings::Event),
     AddMusicAction(add_music::Event),
+    DownloadsAction(downloads::Event),
     FFmpegAction(ffmpeg::Event),
     PlaylistAction(playlist::Event),
+    EqualizerAction(equalizer::Event),
 
     CloseToast(usize),
     KeyboardEvent(IcedEvent),
+    PlaybackStatus(playback::PlaybackStatus),
+    DownloadStatus(download::DownloadStatus),
+    LoudnessAnalysed(Result<loudness::AnalysisSummary, db::DatabaseError>),
+    EnvelopesGenerated(Result<envelope::GenerationSummary, db::DatabaseError>),
+    SessionRestored(Option<session::RestoredSession>),
+    SaveSession,
+    SessionSaved(Result<(), session::SessionError>),
 
     SettingsLoaded(state::AppSettings),
 }
@@ -81,6 +114,10 @@ impl Pages {
 
         playback::start_receiver(playback_reciever);
 
+        let (download_sender, download_receiver) = async_mpsc::unbounded_channel();
+
+        download::start_manager(download_receiver);
+
         Self {
             current_page: Page::TrackList,
             app_settings: None,
@@ -88,31 +125,161 @@ impl Pages {
             nav: Default::default(),
             sidebar: Default::default(),
             controls: Default::default(),
+            queue_panel: Default::default(),
 
             track_list: Default::default(),
             add_music: Default::default(),
+            downloads: Default::default(),
             settings: Default::default(),
             ffmpeg: Default::default(),
             playlist: Default::default(),
+            equalizer: Default::default(),
 
             playback_sender,
+            download_sender,
             rpc_sender: None,
 
             toasts: vec![],
             theme: Theme::Dark,
-            track_list_loaded: false,
             rpc_enabled: false,
+            last_session: None,
+        }
+    }
+
+    // Requests the data every page needs from the database on startup.
+    pub fn load(&mut self) -> Task<UiEvent> {
+        Task::batch(vec![
+            self.sidebar
+                .update(sidebar::Event::UpdatePlaylists)
+                .map(UiEvent::SidebarAction),
+            self.playlist
+                .update(playlist::Event::LoadPlaylists)
+                .map(UiEvent::PlaylistAction),
+            self.track_list
+                .update(track_list::Event::GetThumbnailHandles)
+                .map(UiEvent::TrackListAction),
+            Task::perform(loudness::analyse_library(), UiEvent::LoudnessAnalysed),
+            Task::perform(envelope::generate_library(), UiEvent::EnvelopesGenerated),
+            Task::perform(session::restore(), UiEvent::SessionRestored),
+        ])
+    }
+
+    // Saves what is playing so it can be picked up again on the next start.
+    pub fn save_session(&mut self) -> Task<UiEvent> {
+        let player_state = &self.controls.player_state;
+
+        let session = session::Session::new(
+            &player_state.queue,
+            player_state.seconds_passed,
+            player_state.shuffle,
+            player_state.repeat,
+        );
+
+        if self.last_session.as_ref() == Some(&session) {
+            return Task::none();
         }
+
+        self.last_session = Some(session.clone());
+
+        Task::perform(session::save(session), UiEvent::SessionSaved)
     }
 
     pub fn update(&mut self, message: UiEvent) -> Task<UiEvent> {
         match message {
+            UiEvent::SessionRestored(None) => Task::none(),
+            // The last session comes back paused, at the point it was left.
+            UiEvent::SessionRestored(Some(restored)) => {
+                self.controls.player_state.shuffle = restored.shuffle;
+                self.controls.player_state.repeat = restored.repeat;
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetRepeat(restored.repeat))
+                    .expect("Failed to send repeat command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::Restore(
+                        restored.queue,
+                        restored.position,
+                    ))
+                    .expect("Failed to send restore command");
+
+                Task::none()
+            }
+            UiEvent::SaveSession => self.save_session(),
+            UiEvent::SessionSaved(Ok(())) => Task::none(),
+            UiEvent::SessionSaved(Err(e)) => {
+                log::error!("Failed to save session: {:?}", e);
+
+                Task::none()
+            }
             UiEvent::SettingsLoaded(settings) => {
                 self.app_settings = Some(settings.clone());
 
                 self.theme = helpers::theme::get_theme_from_settings(&settings.theme);
                 self.rpc_enabled = settings.rpc_enabled;
 
+                self.controls.player_state.shuffle = settings.shuffle;
+                self.controls.player_state.repeat = settings.repeat;
+
+                let _ = self.controls.update(control_bar::Event::Visualiser(
+                    components::visualiser::Event::StyleLoaded(settings.visualiser),
+                ));
+
+                let _ = self.controls.update(control_bar::Event::VolumeLoaded(
+                    settings.volume,
+                    settings.muted,
+                ));
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetVolume(settings.volume))
+                    .expect("Failed to send volume command");
+
+                if settings.muted {
+                    self.playback_sender
+                        .send(playback::AudioEvent::Mute)
+                        .expect("Failed to send mute command");
+                }
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetRepeat(settings.repeat))
+                    .expect("Failed to send repeat command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetGapless(settings.gapless))
+                    .expect("Failed to send gapless command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetCrossfade(Duration::from_secs(
+                        settings.crossfade_seconds,
+                    )))
+                    .expect("Failed to send crossfade command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetNormalisation(
+                        settings.normalisation,
+                    ))
+                    .expect("Failed to send normalisation command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetOutputDevice(
+                        settings.output_device.clone(),
+                    ))
+                    .expect("Failed to send output device command");
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetPreservePitch(
+                        settings.preserve_pitch,
+                    ))
+                    .expect("Failed to send preserve pitch command");
+
+                let _ = self
+                    .equalizer
+                    .update(equalizer::Event::SettingsLoaded(settings.equalizer.clone()));
+
+                self.playback_sender
+                    .send(playback::AudioEvent::SetEqualizer(self.equalizer.gains()))
+                    .expect("Failed to send equalizer command");
+
                 if settings.ffmpeg_path.is_empty() {
                     self.current_page = Page::FFmpeg;
                 } else {
@@ -154,6 +321,18 @@ impl Pages {
                         .update(components::control_bar::Event::PauseToggleAction)
                         .map(UiEvent::ControlsAction)
                 }
+                IcedEvent::Keyboard(keyboard::Event::KeyPressed {
+                    key: keyboard::Key::Named(key::Named::Escape),
+                    ..
+                }) if self.controls.sleep_timer().is_some() => {
+                    self.playback_sender
+                        .send(playback::AudioEvent::SetSleepTimer(None))
+                        .expect("Failed to send sleep timer command");
+
+                    self.controls
+                        .update(components::control_bar::Event::CancelSleep)
+                        .map(UiEvent::ControlsAction)
+                }
                 _ => Task::none(),
             },
             UiEvent::NavAction(event) => {
@@ -179,32 +358,32 @@ impl Pages {
                     .map(UiEvent::PlaylistAction);
 
                 match event {
-                    playlist::Event::CreatePlaylist => Task::batch(vec![
+                    playlist::Event::PlaylistCreated(Ok(_)) => Task::batch(vec![
                         playlist_command,
                         self.sidebar
                             .update(sidebar::Event::UpdatePlaylists)
                             .map(UiEvent::SidebarAction),
                     ]),
-                    playlist::Event::PlayTrack(
-                        video_id,
-                        display_name,
-                        duration,
-                        handle,
-                        tracks,
-                    ) => {
+                    playlist::Event::PlayTrack(track, handle, tracks) => {
+                        let shuffle = self.controls.player_state.shuffle;
+
                         self.controls.player_state = state::PlayerState {
-                            active_video_id: video_id.clone(),
-                            display_name: display_name.clone(),
-                            total_duration: duration,
-                            is_paused: false,
+                            active_video_id: track.video_id.clone(),
+                            display_name: track.display_name.clone(),
+                            total_duration: track.duration.as_secs(),
+                            is_paused: self.controls.player_state.is_paused,
                             seconds_passed: 0,
-                            queued_tracks: tracks.clone().unwrap_or_default(),
+                            queue: Queue::from_track(track.clone(), tracks.clone(), shuffle),
+                            shuffle,
+                            repeat: self.controls.player_state.repeat,
+                            device_available: self.controls.player_state.device_available,
+                            speed: self.controls.player_state.speed,
+                            sleep: self.controls.player_state.sleep,
                         };
 
                         self.playback_sender
                             .send(playback::AudioEvent::Queue(
-                                video_id.clone().to_string(),
-                                tracks.clone(),
+                                self.controls.player_state.queue.clone(),
                             ))
                             .expect("Failed to send play command");
 
@@ -213,8 +392,8 @@ impl Pages {
                                 .as_ref()
                                 .unwrap()
                                 .send(rpc::RpcEvent::Set(
-                                    display_name.clone(),
-                                    duration.to_string(),
+                                    track.display_name.clone(),
+                                    track.duration.as_secs(),
                                 ))
                                 .expect("Failed to send rpc command");
                         }
@@ -229,6 +408,20 @@ impl Pages {
                             playlist_command,
                         ])
                     }
+                    playlist::Event::PlayNext(track) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::PlayNext(track.clone()))
+                            .expect("Failed to send queue command");
+
+                        playlist_command
+                    }
+                    playlist::Event::Enqueue(track) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::Enqueue(track.clone()))
+                            .expect("Failed to send queue command");
+
+                        playlist_command
+                    }
                     _ => playlist_command,
                 }
             }
@@ -271,65 +464,68 @@ impl Pages {
 
                         download_command
                     }
-                    add_music::Event::DownloadPressed(video_id) => {
+                    add_music::Event::DownloadPressed(url) => {
+                        self.download_sender
+                            .send(download::DownloadEvent::Add(url.clone()))
+                            .expect("Failed to send download command");
+
                         self.toasts.push(toast::Toast {
-                            title: "Download Started".into(),
-                            body: format!("Downloading video: {}", video_id),
+                            title: "Download Queued".into(),
+                            body: format!("Added to downloads: {}", url),
                             status: toast::Status::Primary,
                         });
 
                         download_command
                     }
-                    add_music::Event::DownloadComplete(status) => {
-                        match status {
-                            Ok(_) => {
-                                self.toasts.push(toast::Toast {
-                                    title: "Download Complete".into(),
-                                    body: "Downloaded video successfully".into(),
-                                    status: toast::Status::Success,
-                                });
-
-                                return Task::batch(vec![
-                                    self.track_list
-                                        .update(track_list::Event::GetThumbnailHandles)
-                                        .map(UiEvent::TrackListAction),
-                                    download_command,
-                                ]);
-                            }
-                            Err(error) => {
-                                log::error!("Failed to download video: {:?}", error);
+                    add_music::Event::PlaylistRequested(url) => {
+                        self.download_sender
+                            .send(download::DownloadEvent::AddPlaylist(url.clone()))
+                            .expect("Failed to send download command");
 
-                                self.toasts.push(toast::Toast {
-                                    title: "Download Failed".into(),
-                                    body: format!("Failed to download video: {:?}", error),
-                                    status: toast::Status::Danger,
-                                });
-                            }
-                        };
+                        self.toasts.push(toast::Toast {
+                            title: "Playlist Queued".into(),
+                            body: format!("Fetching the tracks of playlist: {}", url),
+                            status: toast::Status::Primary,
+                        });
 
                         download_command
                     }
-                    add_music::Event::UrlResult(status) => {
-                        match status {
-                            Ok(_) => {
-                                self.toasts.push(toast::Toast {
-                                    title: "Download Complete".into(),
-                                    body: "Downloaded video successfully".into(),
-                                    status: toast::Status::Success,
-                                });
-                            }
-                            Err(error) => {
-                                log::error!("Failed to download video: {:?}", error);
+                    add_music::Event::ImportComplete(summary) => {
+                        let mut body = format!("Imported {} tracks.", summary.imported.len());
+
+                        for (path, error) in &summary.failed {
+                            body.push_str(&format!(
+                                "\n{}: {:?}",
+                                path.file_name().unwrap_or_default().to_string_lossy(),
+                                error
+                            ));
+                        }
 
-                                self.toasts.push(toast::Toast {
-                                    title: "Download Failed".into(),
-                                    body: format!("Failed to download video: {:?}", error),
-                                    status: toast::Status::Danger,
-                                });
-                            }
-                        };
+                        self.toasts.push(toast::Toast {
+                            title: if summary.failed.is_empty() {
+                                "Import Complete".into()
+                            } else {
+                                format!("{} files failed to import", summary.failed.len())
+                            },
+                            body,
+                            status: if summary.failed.is_empty() {
+                                toast::Status::Success
+                            } else {
+                                toast::Status::Danger
+                            },
+                        });
 
-                        download_command
+                        Task::batch(vec![
+                            self.track_list
+                                .update(track_list::Event::GetThumbnailHandles)
+                                .map(UiEvent::TrackListAction),
+                            Task::perform(loudness::analyse_library(), UiEvent::LoudnessAnalysed),
+                            Task::perform(
+                                envelope::generate_library(),
+                                UiEvent::EnvelopesGenerated,
+                            ),
+                            download_command,
+                        ])
                     }
                     _ => download_command,
                 }
@@ -349,6 +545,75 @@ impl Pages {
                     settings::Event::ThemeSelected(theme) => {
                         self.theme = helpers::theme::match_theme(Some(theme));
                     }
+                    settings::Event::RescanComplete(ref result) => {
+                        match result {
+                            Ok(summary) => self.toasts.push(toast::Toast {
+                                title: "Rescan Complete".into(),
+                                body: format!(
+                                    "Updated tags of {} tracks, {} could not be read.",
+                                    summary.updated, summary.failed
+                                ),
+                                status: toast::Status::Success,
+                            }),
+                            Err(error) => self.toasts.push(toast::Toast {
+                                title: "Rescan Failed".into(),
+                                body: format!("Failed to rescan tags: {:?}", error),
+                                status: toast::Status::Danger,
+                            }),
+                        }
+
+                        return Task::batch(vec![
+                            self.track_list
+                                .update(track_list::Event::GetThumbnailHandles)
+                                .map(UiEvent::TrackListAction),
+                            self.settings.update(event).map(UiEvent::SettingsAction),
+                        ]);
+                    }
+                    settings::Event::ToggleGapless => {
+                        let gapless = self
+                            .settings
+                            .values
+                            .as_ref()
+                            .is_some_and(|values| !values.gapless);
+
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetGapless(gapless))
+                            .expect("Failed to send gapless command");
+                    }
+                    settings::Event::CrossfadeReleased => {
+                        let seconds = self
+                            .settings
+                            .values
+                            .as_ref()
+                            .map_or(0, |values| values.crossfade_seconds);
+
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetCrossfade(Duration::from_secs(
+                                seconds,
+                            )))
+                            .expect("Failed to send crossfade command");
+                    }
+                    settings::Event::DeviceSelected(ref device) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetOutputDevice(device.name()))
+                            .expect("Failed to send output device command");
+                    }
+                    settings::Event::TogglePreservePitch => {
+                        let preserve_pitch = self
+                            .settings
+                            .values
+                            .as_ref()
+                            .is_some_and(|values| !values.preserve_pitch);
+
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetPreservePitch(preserve_pitch))
+                            .expect("Failed to send preserve pitch command");
+                    }
+                    settings::Event::NormalisationSelected(normalisation) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetNormalisation(normalisation))
+                            .expect("Failed to send normalisation command");
+                    }
                     settings::Event::ToggleRpcEnabled => {
                         if self.rpc_enabled {
                             self.rpc_sender
@@ -365,45 +630,32 @@ impl Pages {
                 self.settings.update(event).map(UiEvent::SettingsAction)
             }
             UiEvent::TrackListAction(ref event) => {
-                let track_list_command: Task<UiEvent>;
-
-                if !self.track_list_loaded {
-                    track_list_command = Task::batch(vec![
-                        self.track_list
-                            .update(track_list::Event::GetThumbnailHandles)
-                            .map(UiEvent::TrackListAction),
-                        self.track_list
-                            .update(event.clone())
-                            .map(UiEvent::TrackListAction),
-                    ]);
-                    self.track_list_loaded = true;
-                } else {
-                    track_list_command = self
-                        .track_list
-                        .update(event.clone())
-                        .map(UiEvent::TrackListAction);
-                }
+                let track_list_command = self
+                    .track_list
+                    .update(event.clone())
+                    .map(UiEvent::TrackListAction);
+
                 match event {
-                    track_list::Event::PlayTrack(
-                        video_id,
-                        display_name,
-                        duration,
-                        handle,
-                        tracks,
-                    ) => {
+                    track_list::Event::PlayTrack(track, handle, tracks) => {
+                        let shuffle = self.controls.player_state.shuffle;
+
                         self.controls.player_state = state::PlayerState {
-                            active_video_id: video_id.clone(),
-                            display_name: display_name.clone(),
-                            total_duration: *duration,
-                            is_paused: false,
+                            active_video_id: track.video_id.clone(),
+                            display_name: track.display_name.clone(),
+                            total_duration: track.duration.as_secs(),
+                            is_paused: self.controls.player_state.is_paused,
                             seconds_passed: 0,
-                            queued_tracks: tracks.clone().unwrap(),
+                            queue: Queue::from_track(track.clone(), tracks.clone(), shuffle),
+                            shuffle,
+                            repeat: self.controls.player_state.repeat,
+                            device_available: self.controls.player_state.device_available,
+                            speed: self.controls.player_state.speed,
+                            sleep: self.controls.player_state.sleep,
                         };
 
                         self.playback_sender
                             .send(playback::AudioEvent::Queue(
-                                video_id.clone().to_string(),
-                                tracks.clone(),
+                                self.controls.player_state.queue.clone(),
                             ))
                             .expect("Failed to send play command");
 
@@ -412,8 +664,8 @@ impl Pages {
                                 .as_ref()
                                 .unwrap()
                                 .send(rpc::RpcEvent::Set(
-                                    display_name.clone(),
-                                    duration.to_string(),
+                                    track.display_name.clone(),
+                                    track.duration.as_secs(),
                                 ))
                                 .expect("Failed to send rpc command");
                         }
@@ -428,23 +680,144 @@ impl Pages {
                             track_list_command,
                         ])
                     }
-                    track_list::Event::Submit => {
-                        return Task::batch(vec![
+                    // New bookmarks for the playing track default to where it
+                    // is up to.
+                    track_list::Event::ShowBookmarks(track)
+                        if track.video_id == self.controls.player_state.active_video_id =>
+                    {
+                        let position = format::duration(self.controls.player_state.seconds_passed);
+
+                        Task::batch(vec![
+                            track_list_command,
                             self.track_list
-                                .update(track_list::Event::GetThumbnailHandles)
+                                .update(track_list::Event::NewBookmarkTime(position))
                                 .map(UiEvent::TrackListAction),
-                            track_list_command,
                         ])
                     }
-                    track_list::Event::DeleteTrack => {
-                        return Task::batch(vec![
+                    // Jumping to a bookmark starts its track first, unless it is
+                    // already the one playing.
+                    track_list::Event::JumpToBookmark(track, position) => {
+                        let play = if track.video_id != self.controls.player_state.active_video_id {
+                            let tracks = self.track_list.tracks().to_vec();
+
+                            self.update(UiEvent::TrackListAction(track_list::Event::PlayTrack(
+                                track.clone(),
+                                None,
+                                Some(tracks),
+                            )))
+                        } else {
+                            Task::none()
+                        };
+
+                        self.playback_sender
+                            .send(playback::AudioEvent::SeekTo(position.as_secs()))
+                            .expect("Failed to send seek command");
+
+                        Task::batch(vec![track_list_command, play])
+                    }
+                    track_list::Event::PlayNext(track) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::PlayNext(track.clone()))
+                            .expect("Failed to send queue command");
+
+                        track_list_command
+                    }
+                    track_list::Event::Enqueue(track) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::Enqueue(track.clone()))
+                            .expect("Failed to send queue command");
+
+                        track_list_command
+                    }
+                    track_list::Event::TagsWritten(Err(error)) => {
+                        self.toasts.push(toast::Toast {
+                            title: "Edit Failed".into(),
+                            body: format!("Failed to save track metadata: {:?}", error),
+                            status: toast::Status::Danger,
+                        });
+
+                        track_list_command
+                    }
+                    _ => track_list_command,
+                }
+            }
+
+            UiEvent::DownloadsAction(event) => {
+                let command = match &event {
+                    downloads::Event::Cancel(id) => Some(download::DownloadEvent::Cancel(*id)),
+                    downloads::Event::Retry(id) => Some(download::DownloadEvent::Retry(*id)),
+                    downloads::Event::ClearFinished => Some(download::DownloadEvent::ClearFinished),
+                    downloads::Event::DownloadsChanged(_) => None,
+                };
+
+                if let Some(command) = command {
+                    self.download_sender
+                        .send(command)
+                        .expect("Failed to send download command");
+                }
+
+                self.downloads.update(event).map(UiEvent::DownloadsAction)
+            }
+            UiEvent::DownloadStatus(download::DownloadStatus::Changed(list)) => self
+                .downloads
+                .update(downloads::Event::DownloadsChanged(list))
+                .map(UiEvent::DownloadsAction),
+            UiEvent::DownloadStatus(download::DownloadStatus::PlaylistFilled(name, added)) => {
+                self.toasts.push(toast::Toast {
+                    title: "Playlist Downloaded".into(),
+                    body: format!("Added {} tracks to {}", added, name),
+                    status: toast::Status::Success,
+                });
+
+                Task::batch(vec![
+                    self.sidebar
+                        .update(sidebar::Event::UpdatePlaylists)
+                        .map(UiEvent::SidebarAction),
+                    self.playlist
+                        .update(playlist::Event::LoadPlaylists)
+                        .map(UiEvent::PlaylistAction),
+                ])
+            }
+            UiEvent::DownloadStatus(download::DownloadStatus::PlaylistFailed(name, error)) => {
+                self.toasts.push(toast::Toast {
+                    title: "Playlist Failed".into(),
+                    body: format!("Failed to download playlist {}: {:?}", name, error),
+                    status: toast::Status::Danger,
+                });
+
+                Task::none()
+            }
+            UiEvent::DownloadStatus(download::DownloadStatus::Finished(finished)) => {
+                let title = finished.title.unwrap_or(finished.url);
+
+                match finished.state {
+                    download::DownloadState::Done => {
+                        self.toasts.push(toast::Toast {
+                            title: "Download Complete".into(),
+                            body: format!("Downloaded {}", title),
+                            status: toast::Status::Success,
+                        });
+
+                        Task::batch(vec![
                             self.track_list
                                 .update(track_list::Event::GetThumbnailHandles)
                                 .map(UiEvent::TrackListAction),
-                            track_list_command,
-                        ]);
+                            Task::perform(loudness::analyse_library(), UiEvent::LoudnessAnalysed),
+                            Task::perform(
+                                envelope::generate_library(),
+                                UiEvent::EnvelopesGenerated,
+                            ),
+                        ])
+                    }
+                    state => {
+                        self.toasts.push(toast::Toast {
+                            title: "Download Failed".into(),
+                            body: format!("{}: {}", title, state),
+                            status: toast::Status::Danger,
+                        });
+
+                        Task::none()
                     }
-                    _ => track_list_command,
                 }
             }
 
@@ -456,6 +829,12 @@ impl Pages {
 
                 match event {
                     components::sidebar::Event::OpenDownload => self.current_page = Page::AddMusic,
+                    components::sidebar::Event::OpenDownloads => {
+                        self.current_page = Page::Downloads
+                    }
+                    components::sidebar::Event::OpenEqualizer => {
+                        self.current_page = Page::Equalizer
+                    }
                     components::sidebar::Event::OpenPlaylists => {
                         return {
                             self.current_page = Page::Playlist;
@@ -497,6 +876,107 @@ impl Pages {
                 sidebar_command
             }
 
+            UiEvent::PlaybackStatus(status) => {
+                match &status {
+                    playback::PlaybackStatus::Position(position)
+                        if self.rpc_enabled && !self.controls.player_state.is_paused =>
+                    {
+                        self.rpc_sender
+                            .as_ref()
+                            .unwrap()
+                            .send(rpc::RpcEvent::SetProgress(
+                                self.controls.player_state.display_name.clone(),
+                                position.as_secs(),
+                                self.controls.player_state.total_duration,
+                            ))
+                            .expect("Failed to send progress command");
+                    }
+                    playback::PlaybackStatus::OutputChanged(None) => {
+                        self.toasts.push(toast::Toast {
+                            title: "No Audio Device".into(),
+                            body: "Playback will resume once an output device is available.".into(),
+                            status: toast::Status::Danger,
+                        });
+                    }
+                    playback::PlaybackStatus::Error(error) => {
+                        self.toasts.push(toast::Toast {
+                            title: "Playback Error".into(),
+                            body: error.clone(),
+                            status: toast::Status::Danger,
+                        });
+                    }
+                    playback::PlaybackStatus::QueueChanged(queue) => {
+                        let _ = self
+                            .queue_panel
+                            .update(queue_panel::Event::QueueChanged(queue.clone()));
+                    }
+                    _ => (),
+                }
+
+                self.controls
+                    .update(control_bar::Event::Status(status))
+                    .map(UiEvent::ControlsAction)
+            }
+            // Analysis runs in the background without a toast; the track list
+            // is reloaded so tracks played from it carry their new gain.
+            UiEvent::LoudnessAnalysed(result) => match result {
+                Ok(summary) if summary.analysed > 0 => self
+                    .track_list
+                    .update(track_list::Event::GetThumbnailHandles)
+                    .map(UiEvent::TrackListAction),
+                Ok(_) => Task::none(),
+                Err(error) => {
+                    log::error!("Failed to analyse loudness: {:?}", error);
+
+                    Task::none()
+                }
+            },
+            UiEvent::EnvelopesGenerated(Ok(_)) => Task::none(),
+            UiEvent::EnvelopesGenerated(Err(error)) => {
+                log::error!("Failed to generate envelopes: {:?}", error);
+
+                Task::none()
+            }
+            UiEvent::EqualizerAction(event) => {
+                let command = self
+                    .equalizer
+                    .update(event.clone())
+                    .map(UiEvent::EqualizerAction);
+
+                // The running filter follows the sliders as they move, while
+                // the settings are only saved once they are released.
+                if matches!(
+                    event,
+                    equalizer::Event::GainChanged(..) | equalizer::Event::PresetSelected(_)
+                ) {
+                    self.playback_sender
+                        .send(playback::AudioEvent::SetEqualizer(self.equalizer.gains()))
+                        .expect("Failed to send equalizer command");
+                }
+
+                command
+            }
+            UiEvent::QueuePanelAction(event) => {
+                let command = match event {
+                    queue_panel::Event::JumpTo(index) => Some(playback::AudioEvent::JumpTo(index)),
+                    queue_panel::Event::Remove(index) => Some(playback::AudioEvent::Remove(index)),
+                    queue_panel::Event::Move(from, to) => {
+                        Some(playback::AudioEvent::Move(from, to))
+                    }
+                    queue_panel::Event::Clear => Some(playback::AudioEvent::Clear),
+                    _ => None,
+                };
+
+                if let Some(command) = command {
+                    self.playback_sender
+                        .send(command)
+                        .expect("Failed to send queue command");
+                }
+
+                self.queue_panel
+                    .update(event)
+                    .map(UiEvent::QueuePanelAction)
+            }
             UiEvent::ControlsAction(event) => {
                 let controls_command = self
                     .controls
@@ -544,67 +1024,79 @@ impl Pages {
                             .send(playback::AudioEvent::Backward)
                             .expect("Failed to send backward command");
 
-                        if self.controls.player_state.queued_tracks.is_empty() {
-                            return controls_command;
-                        }
-
-                        self.controls
-                            .update(control_bar::Event::SeekTo(0.0))
-                            .map(UiEvent::ControlsAction)
+                        controls_command
                     }
                     components::control_bar::Event::ForwardPressed => {
                         self.playback_sender
                             .send(playback::AudioEvent::Forward)
                             .expect("Failed to send forward command");
 
-                        if self.controls.player_state.queued_tracks.is_empty() {
-                            return controls_command;
-                        }
+                        controls_command
+                    }
+                    components::control_bar::Event::ToggleShuffle => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetShuffle(
+                                self.controls.player_state.shuffle,
+                            ))
+                            .expect("Failed to send shuffle command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::SpeedSelected(speed) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetSpeed(speed.0))
+                            .expect("Failed to send speed command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::SetLoopStart => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetLoopStart)
+                            .expect("Failed to send loop command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::SetLoopEnd => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetLoopEnd)
+                            .expect("Failed to send loop command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::ClearLoop => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::ClearLoop)
+                            .expect("Failed to send loop command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::SleepSelected(timer) => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetSleepTimer(Some(timer)))
+                            .expect("Failed to send sleep timer command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::CancelSleep => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetSleepTimer(None))
+                            .expect("Failed to send sleep timer command");
+
+                        controls_command
+                    }
+                    components::control_bar::Event::ToggleQueue => Task::batch(vec![
+                        controls_command,
+                        self.queue_panel
+                            .update(queue_panel::Event::Toggle)
+                            .map(UiEvent::QueuePanelAction),
+                    ]),
+                    components::control_bar::Event::CycleRepeat => {
+                        self.playback_sender
+                            .send(playback::AudioEvent::SetRepeat(
+                                self.controls.player_state.repeat,
+                            ))
+                            .expect("Failed to send repeat command");
 
-                        let index = self
-                            .controls
-                            .player_state
-                            .queued_tracks
-                            .iter()
-                            .position(|x| {
-                                x.get("video_id").unwrap()
-                                    == &self.controls.player_state.active_video_id
-                            })
-                            .unwrap();
-                        let next_index = index + 1;
-                        let next_track = &self.controls.player_state.queued_tracks[next_index];
-
-                        let video_id = next_track.get("video_id").unwrap();
-                        let display_name = next_track.get("display_name").unwrap();
-                        let duration = next_track.get("duration").unwrap().parse::<u64>().unwrap();
-
-                        self.controls.player_state.display_name = display_name.clone();
-                        self.controls.player_state.total_duration = duration;
-                        self.controls.player_state.active_video_id = video_id.clone();
-                        self.controls.player_state.seconds_passed = 0;
-
-                        self.controls
-                            .update(control_bar::Event::InitiatePlay(video_id.to_string(), None))
-                            .map(UiEvent::ControlsAction)
-                    }
-                    components::control_bar::Event::Tick => {
-                        if self.rpc_enabled {
-                            if !self.controls.player_state.is_paused {
-                                self.rpc_sender
-                                    .as_ref()
-                                    .unwrap()
-                                    .send(rpc::RpcEvent::SetProgress(
-                                        self.controls.player_state.display_name.clone(),
-                                        self.controls.player_state.seconds_passed.to_string(),
-                                        self.controls
-                                            .player_state
-                                            .total_duration
-                                            .to_string()
-                                            .clone(),
-                                    ))
-                                    .expect("Failed to send tick command");
-                            }
-                        }
                         controls_command
                     }
                     _ => controls_command,
@@ -621,7 +1113,8 @@ impl Pages {
                     row![
                         self.sidebar.view().map(UiEvent::SidebarAction),
                         self.playlist.view().map(UiEvent::PlaylistAction),
-                    ],
+                    ]
+                    .push_maybe(self.queue_panel()),
                     self.controls.view().map(UiEvent::ControlsAction),
                 ];
 
@@ -640,7 +1133,8 @@ impl Pages {
                     row![
                         self.sidebar.view().map(UiEvent::SidebarAction),
                         self.track_list.view().map(UiEvent::TrackListAction),
-                    ],
+                    ]
+                    .push_maybe(self.queue_panel()),
                     self.controls.view().map(UiEvent::ControlsAction),
                 ];
 
@@ -653,7 +1147,36 @@ impl Pages {
                     row![
                         self.sidebar.view().map(UiEvent::SidebarAction),
                         self.add_music.view().map(UiEvent::AddMusicAction),
-                    ],
+                    ]
+                    .push_maybe(self.queue_panel()),
+                    self.controls.view().map(UiEvent::ControlsAction),
+                ];
+
+                toast::Manager::new(content, &self.toasts, UiEvent::CloseToast).into()
+            }
+
+            Page::Downloads => {
+                let content = column![
+                    self.nav.view().map(UiEvent::NavAction),
+                    row![
+                        self.sidebar.view().map(UiEvent::SidebarAction),
+                        self.downloads.view().map(UiEvent::DownloadsAction),
+                    ]
+                    .push_maybe(self.queue_panel()),
+                    self.controls.view().map(UiEvent::ControlsAction),
+                ];
+
+                toast::Manager::new(content, &self.toasts, UiEvent::CloseToast).into()
+            }
+
+            Page::Equalizer => {
+                let content = column![
+                    self.nav.view().map(UiEvent::NavAction),
+                    row![
+                        self.sidebar.view().map(UiEvent::SidebarAction),
+                        self.equalizer.view().map(UiEvent::EqualizerAction),
+                    ]
+                    .push_maybe(self.queue_panel()),
                     self.controls.view().map(UiEvent::ControlsAction),
                 ];
 
@@ -666,7 +1189,8 @@ impl Pages {
                     row![
                         self.sidebar.view().map(UiEvent::SidebarAction),
                         self.settings.view().map(UiEvent::SettingsAction),
-                    ],
+                    ]
+                    .push_maybe(self.queue_panel()),
                     self.controls.view().map(UiEvent::ControlsAction),
                 ];
 
@@ -679,11 +1203,30 @@ impl Pages {
         Subscription::batch(vec![
             event::listen().map(UiEvent::KeyboardEvent),
             self.track_list.subscription().map(UiEvent::TrackListAction),
-            self.controls.subscription().map(UiEvent::ControlsAction),
+            playback::status_subscription(self.playback_sender.clone())
+                .map(UiEvent::PlaybackStatus),
+            download::status_subscription(self.download_sender.clone())
+                .map(UiEvent::DownloadStatus),
             self.ffmpeg.subscription().map(UiEvent::FFmpegAction),
+            iced::time::every(SESSION_SAVE_INTERVAL).map(|_| UiEvent::SaveSession),
+            if self.controls.visualiser_style() == VisualiserStyle::Off {
+                Subscription::none()
+            } else {
+                visualiser::subscription(self.playback_sender.clone()).map(|spectrum| {
+                    UiEvent::ControlsAction(control_bar::Event::Visualiser(
+                        components::visualiser::Event::Spectrum(spectrum),
+                    ))
+                })
+            },
         ])
     }
 
+    fn queue_panel(&self) -> Option<iced::Element<'_, UiEvent>> {
+        self.queue_panel
+            .visible
+            .then(|| self.queue_panel.view().map(UiEvent::QueuePanelAction))
+    }
+
     pub fn theme(&self) -> iced::Theme {
         self.theme.clone()
     }
@@ -1,20 +1,24 @@
-use std::collections::HashMap;
+use std::path::PathBuf;
 
 use super::super::helpers::helper;
 use super::super::helpers::icons;
+use crate::core::file;
+use crate::core::import;
 use crate::core::request;
-use crate::core::youtube;
+use crate::core::source::{self, SearchResult, StatusError};
 
-use iced::widget::{column, container, image as image_widget, row, scrollable, text, text_input};
+use iced::widget::{
+    button, column, container, image as image_widget, row, scrollable, text, text_input,
+};
 use iced::Alignment;
 use iced::Task;
 
 pub struct State {
     query: String,
-    results: Option<Vec<HashMap<String, String>>>,
+    results: Option<Vec<SearchResult>>,
     thumbnails: Vec<iced::advanced::image::Handle>,
     loading: bool,
-    url_requested: bool,
+    importing: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,11 +26,16 @@ pub enum Event {
     SearchQuery(String),
     Submit,
     DownloadPressed(String),
+    PlaylistRequested(String),
 
     ThumbnailReceived(Result<Vec<Vec<u8>>, request::RequestError>),
-    DownloadComplete(Result<(), youtube::StatusError>),
-    SearchQueryReceived(Result<Vec<HashMap<String, String>>, youtube::StatusError>),
-    UrlResult(Result<(), youtube::StatusError>),
+    SearchQueryReceived(Result<Vec<SearchResult>, StatusError>),
+
+    ImportFiles,
+    ImportFolder,
+    FilesPicked(Result<Vec<PathBuf>, file::FileError>),
+    FolderPicked(Result<PathBuf, file::FileError>),
+    ImportComplete(import::ImportSummary),
 }
 
 impl State {
@@ -51,10 +60,12 @@ impl State {
             }
 
             Event::SearchQueryReceived(Ok(data)) => {
-                self.results = Some(data.clone());
+                let thumbnails = data.iter().map(|result| result.thumbnail.clone()).collect();
+
+                self.results = Some(data);
 
                 Task::perform(
-                    request::request_all_thumbnails(data),
+                    request::request_all_thumbnails(thumbnails),
                     Event::ThumbnailReceived,
                 )
             }
@@ -63,58 +74,48 @@ impl State {
 
                 Task::none()
             }
-            Event::UrlResult(Ok(_)) => {
-                self.url_requested = false;
-                self.loading = false;
-                self.results = None;
-                self.thumbnails = Vec::new();
-
-                Task::none()
-            }
-            Event::UrlResult(Err(e)) => {
-                log::error!("Failed to download from URL: {:?}", e);
-
-                Task::none()
-            }
-
             Event::Submit => {
-                self.loading = true;
-
-                self.url_requested = false;
                 self.results = None;
                 self.thumbnails = Vec::new();
 
-                let re = regex::Regex::new(r"(https?://)?(www\.)?(youtube\.com|youtu\.?be)/.+$")
-                    .unwrap();
-
-                if re.is_match(&self.query) {
-                    self.url_requested = true;
-
-                    let yt_url = self.query.clone();
+                let playlist_re = regex::Regex::new(
+                    r"(https?://)?(www\.|music\.)?youtube\.com/playlist\?list=.+$",
+                )
+                .unwrap();
 
-                    Task::perform(youtube::download_from_url(yt_url), Event::UrlResult)
+                // Links are handed straight to the download manager.
+                if playlist_re.is_match(&self.query) {
+                    Task::done(Event::PlaylistRequested(self.query.clone()))
+                } else if source::find(&self.query).is_some() {
+                    Task::done(Event::DownloadPressed(self.query.clone()))
                 } else {
+                    self.loading = true;
+
                     Task::perform(
-                        youtube::get_search_results(self.query.clone()),
+                        source::search_source().search(self.query.clone()),
                         Event::SearchQueryReceived,
                     )
                 }
             }
 
-            Event::DownloadPressed(video_id) => Task::perform(
-                youtube::download_from_url(video_id),
-                Event::DownloadComplete,
-            ),
+            // Passed on to the download manager by the caller.
+            Event::DownloadPressed(_) | Event::PlaylistRequested(_) => Task::none(),
 
-            Event::DownloadComplete(status) => {
-                match status {
-                    Ok(_) => {
-                        log::info!("Downloaded successfully");
-                    }
-                    Err(e) => {
-                        log::error!("Failed to download: {:?}", e);
-                    }
-                }
+            Event::ImportFiles => Task::perform(file::pick_audio_files(), Event::FilesPicked),
+            Event::ImportFolder => Task::perform(file::pick_folder(), Event::FolderPicked),
+            Event::FilesPicked(Ok(paths)) => {
+                self.importing = true;
+
+                Task::perform(import::import_files(paths), Event::ImportComplete)
+            }
+            Event::FolderPicked(Ok(folder)) => {
+                self.importing = true;
+
+                Task::perform(import::import_folder(folder), Event::ImportComplete)
+            }
+            Event::FilesPicked(Err(_)) | Event::FolderPicked(Err(_)) => Task::none(),
+            Event::ImportComplete(_) => {
+                self.importing = false;
 
                 Task::none()
             }
@@ -128,11 +129,28 @@ impl State {
 
     pub fn view(&self) -> iced::Element<Event> {
         let mut col = column![].spacing(10).padding(10).push(row![
-            text_input("Enter YouTube URL or search query", &self.query)
-                .on_input(Event::SearchQuery),
+            text_input(
+                "Enter a YouTube or audio file URL, or a search query",
+                &self.query
+            )
+            .on_input(Event::SearchQuery),
             helper::action(icons::search_icon(), "Search", Some(Event::Submit)),
         ]);
 
+        col = col.push(
+            row![
+                button("Import files")
+                    .on_press_maybe((!self.importing).then_some(Event::ImportFiles)),
+                button("Import folder")
+                    .on_press_maybe((!self.importing).then_some(Event::ImportFolder)),
+            ]
+            .spacing(10),
+        );
+
+        if self.importing {
+            col = col.push(text("Importing..."));
+        }
+
         if self.loading {
             col = col.push(text("Loading..."));
 
@@ -142,17 +160,13 @@ impl State {
         if self.results.is_some() && !self.loading {
             if let Some(results) = &self.results {
                 for (index, result) in results.iter().enumerate() {
-                    let title = result.get("title").unwrap();
-                    let channel = result.get("channel").unwrap();
-                    let heading = format!("{} - {}", title, channel);
+                    let heading = format!("{} - {}", result.title, result.channel);
 
                     let row = row![
                         helper::action(
                             icons::download_icon(),
                             "Download",
-                            Some(Event::DownloadPressed(
-                                result.get("video_id").unwrap().to_string()
-                            ))
+                            Some(Event::DownloadPressed(result.url.clone()))
                         ),
                         image_widget(self.thumbnails[index].clone())
                             .width(130)
@@ -184,7 +198,7 @@ impl Default for State {
             query: String::new(),
             results: None,
             loading: false,
-            url_requested: false,
+            importing: false,
             thumbnails: Vec::new(),
         }
     }
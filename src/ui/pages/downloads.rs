@@ -0,0 +1,100 @@
+use crate::core::download::{Download, DownloadId, DownloadState};
+
+use iced::widget::{button, column, container, progress_bar, row, scrollable, text};
+use iced::{Alignment, Element, Length, Task};
+
+pub struct State {
+    downloads: Vec<Download>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    DownloadsChanged(Vec<Download>),
+    Cancel(DownloadId),
+    Retry(DownloadId),
+    ClearFinished,
+}
+
+impl State {
+    pub fn update(&mut self, message: Event) -> Task<Event> {
+        match message {
+            Event::DownloadsChanged(downloads) => {
+                self.downloads = downloads;
+
+                Task::none()
+            }
+            // These are passed on to the download manager by the caller.
+            Event::Cancel(_) | Event::Retry(_) | Event::ClearFinished => Task::none(),
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let mut list = column![].spacing(15);
+
+        if self.downloads.is_empty() {
+            list = list.push(text("Downloads started from Add Music will appear here."));
+        }
+
+        for download in &self.downloads {
+            let title = download.title.as_deref().unwrap_or(&download.url);
+
+            let mut actions = row![].spacing(10);
+
+            if download.state.is_cancellable() {
+                actions = actions.push(button("Cancel").on_press(Event::Cancel(download.id)));
+            }
+
+            if download.state.is_retryable() {
+                actions = actions.push(button("Retry").on_press(Event::Retry(download.id)));
+            }
+
+            let progress = match download.state {
+                DownloadState::Downloading(fraction) => fraction,
+                DownloadState::Converting | DownloadState::Thumbnail | DownloadState::Done => 1.0,
+                _ => 0.0,
+            };
+
+            list = list.push(
+                row![
+                    column![
+                        text(title).size(16),
+                        text(download.state.to_string()).size(12),
+                        progress_bar(0.0..=1.0, progress).height(6),
+                    ]
+                    .spacing(5)
+                    .width(Length::Fill),
+                    actions,
+                ]
+                .align_y(Alignment::Center)
+                .spacing(20),
+            );
+        }
+
+        let finished = self
+            .downloads
+            .iter()
+            .any(|download| download.state.is_finished());
+
+        container(
+            column![
+                row![
+                    text("Downloads").size(18).width(Length::Fill),
+                    button("Clear finished")
+                        .on_press_maybe(finished.then_some(Event::ClearFinished)),
+                ]
+                .align_y(Alignment::Center),
+                scrollable(list).height(Length::Fill),
+            ]
+            .spacing(20)
+            .width(Length::Fill),
+        )
+        .padding(10)
+        .into()
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self { downloads: vec![] }
+    }
+}
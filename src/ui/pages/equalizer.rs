@@ -0,0 +1,124 @@
+use crate::core::equalizer::{EqPreset, EqualizerSettings, BANDS, BAND_COUNT, MAX_GAIN};
+use crate::core::json;
+
+use iced::widget::{column, container, pick_list, row, text, vertical_slider};
+use iced::{Alignment, Element, Length, Task};
+
+pub struct State {
+    settings: EqualizerSettings,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    SettingsLoaded(EqualizerSettings),
+    PresetSelected(EqPreset),
+    GainChanged(usize, f32),
+    GainReleased,
+    Continue,
+}
+
+impl State {
+    pub fn update(&mut self, message: Event) -> Task<Event> {
+        match message {
+            Event::SettingsLoaded(settings) => {
+                self.settings = settings;
+
+                Task::none()
+            }
+            Event::PresetSelected(preset) => {
+                self.settings.preset = preset;
+
+                self.save()
+            }
+            // Moving a slider turns whatever is showing into the custom preset.
+            Event::GainChanged(band, gain) => {
+                if self.settings.preset != EqPreset::Custom {
+                    self.settings.custom = self.settings.gains();
+                    self.settings.preset = EqPreset::Custom;
+                }
+
+                self.settings.custom[band] = gain;
+
+                Task::none()
+            }
+            Event::GainReleased => self.save(),
+            Event::Continue => Task::none(),
+        }
+    }
+
+    // The gain of each band in dB, as the playback thread should apply them.
+    pub fn gains(&self) -> [f32; BAND_COUNT] {
+        self.settings.gains()
+    }
+
+    fn save(&self) -> Task<Event> {
+        let equalizer = self.settings.clone();
+
+        Task::perform(
+            json::save_settings(move |settings| {
+                settings.equalizer = equalizer;
+            }),
+            |_| Event::Continue,
+        )
+    }
+
+    pub fn view(&self) -> Element<'_, Event> {
+        let gains = self.settings.gains();
+
+        let mut bands = row![].spacing(20).align_y(Alignment::Center);
+
+        for (band, (frequency, gain)) in BANDS.iter().zip(gains).enumerate() {
+            bands = bands.push(
+                column![
+                    text(format!("{:+.1} dB", gain)).size(12),
+                    vertical_slider(-MAX_GAIN..=MAX_GAIN, gain, move |gain| {
+                        Event::GainChanged(band, gain)
+                    })
+                    .on_release(Event::GainReleased)
+                    .step(0.5)
+                    .height(200),
+                    text(frequency_label(*frequency)).size(12),
+                ]
+                .spacing(10)
+                .align_x(Alignment::Center),
+            );
+        }
+
+        container(
+            column![
+                text("Equalizer").size(18),
+                row![
+                    text("Preset:"),
+                    pick_list(
+                        EqPreset::ALL,
+                        Some(self.settings.preset),
+                        Event::PresetSelected
+                    ),
+                ]
+                .align_y(Alignment::Center)
+                .spacing(10),
+                bands,
+            ]
+            .spacing(40)
+            .width(Length::Fill),
+        )
+        .padding(10)
+        .into()
+    }
+}
+
+fn frequency_label(frequency: f32) -> String {
+    if frequency >= 1000.0 {
+        format!("{}k", frequency / 1000.0)
+    } else {
+        format!("{}", frequency)
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            settings: EqualizerSettings::default(),
+        }
+    }
+}
@@ -1,4 +1,6 @@
 pub mod add_music;
+pub mod downloads;
+pub mod equalizer;
 pub mod ffmpeg;
 pub mod playlist;
 pub mod settings;
@@ -16,8 +16,8 @@ pub struct State {
     create_playlist_mode: bool,
     playlist_view: bool,
     playlist_name_input: String,
-    playlists: Vec<HashMap<String, String>>,
-    tracks: Vec<HashMap<String, String>>,
+    playlists: Vec<db::Playlist>,
+    tracks: Vec<db::Track>,
     thumbnails_recieved: bool,
     thumbnails: Vec<HashMap<String, iced::advanced::image::Handle>>,
 }
@@ -28,45 +28,47 @@ pub enum Event {
     OpenInCreateMode,
     CreatePlaylist,
     PlayTrack(
-        String,
-        String,
-        u64,
+        db::Track,
         Option<iced::advanced::image::Handle>,
-        Option<Vec<HashMap<String, String>>>,
+        Option<Vec<db::Track>>,
     ),
+    PlayNext(db::Track),
+    Enqueue(db::Track),
     ThumbnailHandlesReceived(Vec<HashMap<String, iced::advanced::image::Handle>>),
     OpenPlaylist(i32),
     PlaylistNameInput(String),
+    LoadPlaylists,
+    PlaylistsLoaded(Result<Vec<db::Playlist>, db::DatabaseError>),
+    PlaylistCreated(Result<db::PlaylistId, db::DatabaseError>),
+    TracksLoaded(Vec<db::Track>),
 }
 
 impl State {
     pub fn update(&mut self, message: Event) -> Task<Event> {
         match message {
-            Event::PlayTrack(_video_id, _display_name, _duration, _handle, _tracks) => Task::none(),
+            Event::PlayTrack(_track, _handle, _tracks) => Task::none(),
+            Event::PlayNext(_track) => Task::none(),
+            Event::Enqueue(_track) => Task::none(),
             Event::OpenPlaylist(index) => {
                 self.playlist_view = true;
                 self.thumbnails = Vec::new();
                 self.thumbnails_recieved = false;
                 self.tracks = Vec::new();
 
-                let playlist_tracks = db::get_playlist_tracks(
-                    self.playlists[index as usize]
-                        .get("id")
-                        .unwrap()
-                        .parse::<i32>()
-                        .unwrap(),
-                );
+                let Some(playlist) = self.playlists.get(index as usize) else {
+                    log::error!("No playlist at index {}", index);
 
-                for track in playlist_tracks {
-                    match db::get_music_from_id(
-                        track.get("music_id").unwrap().parse::<i32>().unwrap(),
-                    ) {
-                        Ok(music) => self.tracks.push(music),
-                        Err(e) => {
-                            log::error!("Failed to get music from id: {:?}", e);
-                        }
-                    }
-                }
+                    return Task::none();
+                };
+
+                Task::perform(
+                    load_playlist_tracks(playlist.playlist_id),
+                    Event::TracksLoaded,
+                )
+            }
+
+            Event::TracksLoaded(tracks) => {
+                self.tracks = tracks;
 
                 Task::perform(
                     request::request_thumbnail_from_playlist(self.tracks.clone()),
@@ -92,9 +94,27 @@ impl State {
                 self.playlist_view = false;
                 self.create_playlist_mode = false;
 
-                let _ = db::add_playlist(self.playlist_name_input.clone());
+                Task::perform(
+                    db::add_playlist(self.playlist_name_input.clone()),
+                    Event::PlaylistCreated,
+                )
+            }
+            Event::PlaylistCreated(Ok(_)) => {
+                Task::perform(db::get_all_playlists(), Event::PlaylistsLoaded)
+            }
+            Event::PlaylistCreated(Err(e)) => {
+                log::error!("Failed to create playlist: {:?}", e);
+
+                Task::none()
+            }
+            Event::LoadPlaylists => Task::perform(db::get_all_playlists(), Event::PlaylistsLoaded),
+            Event::PlaylistsLoaded(Ok(playlists)) => {
+                self.playlists = playlists;
 
-                self.playlists = db::get_all_playlists();
+                Task::none()
+            }
+            Event::PlaylistsLoaded(Err(e)) => {
+                log::error!("Failed to get playlists: {:?}", e);
 
                 Task::none()
             }
@@ -115,89 +135,41 @@ impl State {
     pub fn view(&self) -> iced::Element<Event> {
         if self.playlist_view {
             let mut col = column![];
-            if self.thumbnails_recieved {
-                for track in self.tracks.iter() {
-                    let handle = self
-                        .thumbnails
-                        .iter()
-                        .find(|x| x.contains_key(track.get("video_id").unwrap()))
-                        .unwrap()
-                        .get(track.get("video_id").unwrap())
-                        .unwrap();
-
-                    col = col.push(
-                        row![
-                            helper::action(
-                                icons::play_icon(),
-                                "Play",
-                                Some(Event::PlayTrack(
-                                    track.get("video_id").unwrap().to_string(),
-                                    track.get("display_name").unwrap().to_string(),
-                                    track
-                                        .get("duration")
-                                        .unwrap()
-                                        .parse::<i32>()
-                                        .unwrap()
-                                        .try_into()
-                                        .unwrap(),
-                                    Some(handle.clone()),
-                                    Some(self.tracks.clone()),
-                                ))
-                            ),
-                            helper::thumbnail(handle.clone()),
-                            text(track.get("display_name").unwrap()),
-                            horizontal_space(),
-                            text(format::duration(
-                                track
-                                    .get("duration")
-                                    .unwrap()
-                                    .parse::<i32>()
-                                    .unwrap()
-                                    .try_into()
-                                    .unwrap()
-                            )),
-                        ]
-                        .spacing(10)
-                        .align_y(Alignment::Center),
-                    );
-                }
-            } else {
-                for track in self.tracks.iter() {
-                    col = col.push(
-                        row![
-                            helper::action(
-                                icons::play_icon(),
-                                "Play",
-                                Some(Event::PlayTrack(
-                                    track.get("video_id").unwrap().to_string(),
-                                    track.get("display_name").unwrap().to_string(),
-                                    track
-                                        .get("duration")
-                                        .unwrap()
-                                        .parse::<i32>()
-                                        .unwrap()
-                                        .try_into()
-                                        .unwrap(),
-                                    None,
-                                    Some(self.tracks.clone())
-                                ))
-                            ),
-                            text(track.get("display_name").unwrap()),
-                            horizontal_space(),
-                            text(format::duration(
-                                track
-                                    .get("duration")
-                                    .unwrap()
-                                    .parse::<i32>()
-                                    .unwrap()
-                                    .try_into()
-                                    .unwrap()
-                            )),
-                        ]
-                        .spacing(10)
-                        .align_y(Alignment::Center),
-                    );
+            for track in self.tracks.iter() {
+                let duration = track.duration.as_secs();
+                let handle = self.thumbnails.iter().find_map(|x| x.get(&track.video_id));
+
+                let mut row = row![helper::action(
+                    icons::play_icon(),
+                    "Play",
+                    Some(Event::PlayTrack(
+                        track.clone(),
+                        handle.cloned(),
+                        Some(self.tracks.clone()),
+                    ))
+                )]
+                .spacing(10)
+                .align_y(Alignment::Center);
+
+                if let (true, Some(handle)) = (self.thumbnails_recieved, handle) {
+                    row = row.push(helper::thumbnail(handle.clone()));
                 }
+
+                col = col.push(
+                    row.push(text(&track.display_name))
+                        .push(horizontal_space())
+                        .push(text(format::duration(duration)))
+                        .push(helper::action(
+                            icons::forward_icon(),
+                            "Play next",
+                            Some(Event::PlayNext(track.clone())),
+                        ))
+                        .push(helper::action(
+                            icons::list_icon(),
+                            "Add to queue",
+                            Some(Event::Enqueue(track.clone())),
+                        )),
+                );
             }
 
             let content = container(scrollable(
@@ -237,7 +209,7 @@ impl State {
         // amend for loop to get an index
         for (index, playlist) in self.playlists.iter().enumerate() {
             col = col.push(
-                button(text(playlist.get("name").unwrap()))
+                button(text(&playlist.name))
                     .on_press(Event::OpenPlaylist(index as i32))
                     .style(style::sidebar_button),
             );
@@ -258,13 +230,39 @@ impl State {
     }
 }
 
+// Resolves every entry of a playlist to its track. Entries whose track can't
+// be read are logged and skipped so one bad row doesn't hide the whole playlist.
+async fn load_playlist_tracks(playlist_id: db::PlaylistId) -> Vec<db::Track> {
+    let entries = match db::get_playlist_tracks(playlist_id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Failed to get playlist tracks: {:?}", e);
+
+            return Vec::new();
+        }
+    };
+
+    let mut tracks = Vec::new();
+
+    for entry in entries {
+        match db::get_music_from_id(entry.music_id).await {
+            Ok(music) => tracks.push(music),
+            Err(e) => {
+                log::error!("Failed to get music from id: {:?}", e);
+            }
+        }
+    }
+
+    tracks
+}
+
 impl Default for State {
     fn default() -> Self {
         Self {
             create_playlist_mode: false,
             playlist_view: false,
             playlist_name_input: String::new(),
-            playlists: db::get_all_playlists(),
+            playlists: Vec::new(),
             tracks: Vec::new(),
             thumbnails_recieved: false,
             thumbnails: Vec::new(),
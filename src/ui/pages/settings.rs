@@ -1,8 +1,13 @@
 use super::super::helpers::theme;
+use crate::core::db;
 use crate::core::json;
+use crate::core::loudness::NormalisationMode;
+use crate::core::playback::{self, OutputDevice};
+use crate::core::tags;
+use crate::core::transcode::{self, OutputFormat};
 use crate::state::AppSettings;
 
-use iced::widget::{button, column, container, pick_list, row, scrollable, text};
+use iced::widget::{button, column, container, pick_list, row, scrollable, slider, text};
 use iced::{Alignment, Length, Task};
 
 pub struct State {
@@ -10,14 +15,27 @@ pub struct State {
     pub values: Option<AppSettings>,
 
     theme: theme::Themes,
+    rescanning: bool,
+    devices: Vec<OutputDevice>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     ThemeSelected(theme::Themes),
     ToggleRpcEnabled,
+    ToggleGapless,
+    TogglePreservePitch,
+    CrossfadeChanged(u64),
+    CrossfadeReleased,
+    NormalisationSelected(NormalisationMode),
+    DownloadFormatSelected(OutputFormat),
+    DownloadBitrateSelected(u32),
+    DevicesLoaded(Vec<String>),
+    DeviceSelected(OutputDevice),
     Continue,
     LoadSettings,
+    RescanTags,
+    RescanComplete(Result<tags::RescanSummary, db::DatabaseError>),
     SettingsLoaded(Option<AppSettings>),
 }
 
@@ -31,8 +49,40 @@ impl State {
 
                 Task::none()
             }
-            Event::LoadSettings => Task::perform(json::load_settings(), Event::SettingsLoaded),
+            Event::LoadSettings => Task::batch(vec![
+                Task::perform(json::load_settings(), Event::SettingsLoaded),
+                Task::perform(playback::output_devices(), Event::DevicesLoaded),
+            ]),
+            Event::DevicesLoaded(names) => {
+                self.devices = std::iter::once(OutputDevice::Default)
+                    .chain(names.into_iter().map(OutputDevice::Named))
+                    .collect();
+
+                Task::none()
+            }
+            Event::DeviceSelected(device) => {
+                let name = device.name();
+
+                self.values.as_mut().unwrap().output_device = name.clone();
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.output_device = name;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
             Event::Continue => Task::none(),
+            Event::RescanTags => {
+                self.rescanning = true;
+
+                Task::perform(tags::rescan_library(), Event::RescanComplete)
+            }
+            Event::RescanComplete(_) => {
+                self.rescanning = false;
+
+                Task::none()
+            }
             Event::ThemeSelected(theme) => {
                 self.theme = theme;
 
@@ -44,6 +94,78 @@ impl State {
                 )
             }
 
+            Event::ToggleGapless => {
+                let gapless = !self.values.as_ref().unwrap().gapless;
+
+                self.values.as_mut().unwrap().gapless = gapless;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.gapless = gapless;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::TogglePreservePitch => {
+                let preserve_pitch = !self.values.as_ref().unwrap().preserve_pitch;
+
+                self.values.as_mut().unwrap().preserve_pitch = preserve_pitch;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.preserve_pitch = preserve_pitch;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::CrossfadeChanged(seconds) => {
+                self.values.as_mut().unwrap().crossfade_seconds = seconds;
+
+                Task::none()
+            }
+            Event::CrossfadeReleased => {
+                let seconds = self.values.as_ref().unwrap().crossfade_seconds;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.crossfade_seconds = seconds;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+
+            Event::NormalisationSelected(normalisation) => {
+                self.values.as_mut().unwrap().normalisation = normalisation;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.normalisation = normalisation;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+
+            Event::DownloadFormatSelected(format) => {
+                self.values.as_mut().unwrap().download_format = format;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.download_format = format;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+            Event::DownloadBitrateSelected(bitrate) => {
+                self.values.as_mut().unwrap().download_bitrate = bitrate;
+
+                Task::perform(
+                    json::save_settings(move |settings| {
+                        settings.download_bitrate = bitrate;
+                    }),
+                    |_| Event::Continue,
+                )
+            }
+
             Event::ToggleRpcEnabled => {
                 let rpc_enabled = !self.values.as_ref().unwrap().rpc_enabled;
 
@@ -90,6 +212,88 @@ impl State {
                         ]
                         .align_y(Alignment::Center)
                         .spacing(10),
+                        row![
+                            text("Output device:"),
+                            pick_list(
+                                self.devices.as_slice(),
+                                Some(OutputDevice::from(
+                                    self.values.as_ref().unwrap().output_device.clone()
+                                )),
+                                Event::DeviceSelected
+                            ),
+                            text("Falls back to the system default if unavailable.").size(14),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
+                        row![
+                            text("Gapless playback:"),
+                            button(if self.values.as_ref().unwrap().gapless {
+                                "Enabled"
+                            } else {
+                                "Disabled"
+                            })
+                            .on_press(Event::ToggleGapless),
+                            text("Loads the next track early so there is no pause between them.")
+                                .size(14),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
+                        row![
+                            text("Crossfade:"),
+                            slider(
+                                0.0..=12.0,
+                                self.values.as_ref().unwrap().crossfade_seconds as f32,
+                                |value| Event::CrossfadeChanged(value as u64)
+                            )
+                            .on_release(Event::CrossfadeReleased)
+                            .step(1.0)
+                            .width(200),
+                            text(match self.values.as_ref().unwrap().crossfade_seconds {
+                                0 => "Off".to_string(),
+                                seconds => format!("{} seconds", seconds),
+                            }),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
+                        row![
+                            text("Keep pitch when changing speed:"),
+                            button(if self.values.as_ref().unwrap().preserve_pitch {
+                                "Enabled"
+                            } else {
+                                "Disabled"
+                            })
+                            .on_press(Event::TogglePreservePitch),
+                            text("Stretches the audio instead of playing it faster or slower.")
+                                .size(14),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
+                        row![
+                            text("Volume normalisation:"),
+                            pick_list(
+                                NormalisationMode::ALL,
+                                Some(self.values.as_ref().unwrap().normalisation),
+                                Event::NormalisationSelected
+                            ),
+                            text("Plays tracks at a similar loudness using their measured gain.")
+                                .size(14),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
+                        self.download_format_row(),
+                        row![
+                            text("Track tags:"),
+                            button(if self.rescanning {
+                                "Rescanning..."
+                            } else {
+                                "Rescan library"
+                            })
+                            .on_press_maybe((!self.rescanning).then_some(Event::RescanTags)),
+                            text("Re-reads artist, album and other tags from your audio files.")
+                                .size(14),
+                        ]
+                        .align_y(Alignment::Center)
+                        .spacing(10),
                     ]
                     .spacing(40)
                     .align_x(Alignment::Start)
@@ -103,12 +307,49 @@ impl State {
     }
 }
 
+impl State {
+    fn download_format_row(&self) -> iced::Element<'_, Event> {
+        let values = self.values.as_ref().unwrap();
+
+        let mut row = row![
+            text("Download format:"),
+            pick_list(
+                OutputFormat::ALL,
+                Some(values.download_format),
+                Event::DownloadFormatSelected
+            ),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(10);
+
+        if values.download_format.has_bitrate() {
+            row = row.push(pick_list(
+                transcode::BITRATES,
+                Some(values.download_bitrate),
+                Event::DownloadBitrateSelected,
+            ));
+            row = row.push(text("kbps"));
+        }
+
+        let hint = match values.download_format {
+            OutputFormat::Original => {
+                "Keeps downloads as they are when they can be played, and uses MP3 otherwise."
+            }
+            _ => "Downloads are converted to this with ffmpeg.",
+        };
+
+        row.push(text(hint).size(14)).into()
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         Self {
             is_loaded: false,
             values: None,
             theme: theme::Themes::default(),
+            rescanning: false,
+            devices: vec![OutputDevice::Default],
         }
     }
 }
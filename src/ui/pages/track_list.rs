@@ -1,11 +1,15 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use super::super::helpers::helper;
 use super::super::helpers::icons;
 use super::super::helpers::style;
 use crate::core::db;
+use crate::core::file;
 use crate::core::format;
 use crate::core::request;
+use crate::core::tags;
 
 use iced::event;
 use iced::event::Event as IcedEvent;
@@ -21,12 +25,22 @@ use iced::{Alignment, Element, Length, Task};
 use log;
 
 pub struct State {
-    track_list: Vec<HashMap<String, String>>,
+    track_list: Vec<db::Track>,
+    playlists: Vec<db::Playlist>,
     show_edit_modal: bool,
     show_add_modal: bool,
+    show_bookmarks_modal: bool,
     new_display_name: String,
+    new_artist: String,
+    new_album: String,
+    new_year: String,
+    new_genre: String,
+    new_cover_art: Option<PathBuf>,
+    bookmarks: Vec<db::Bookmark>,
+    new_bookmark_name: String,
+    new_bookmark_time: String,
     active_video_id: Option<String>,
-    active_display_name: Option<String>,
+    active_track: Option<db::Track>,
     thumbnails_received: bool,
     thumbnails: Vec<HashMap<String, iced::advanced::image::Handle>>,
 }
@@ -38,17 +52,36 @@ pub enum Event {
     Submit,
     DeleteTrack,
     GetThumbnailHandles,
-    AddToPlaylist(String, i32),
+    AddToPlaylist(String, db::PlaylistId),
+    AddedToPlaylist(Result<(), db::DatabaseError>),
+    TracksLoaded(Result<Vec<db::Track>, db::DatabaseError>),
+    PlaylistsLoaded(Result<Vec<db::Playlist>, db::DatabaseError>),
+    TrackUpdated(Result<(), db::DatabaseError>),
+    TagsWritten(Result<(), tags::TagError>),
     ThumbnailsReceived(Vec<HashMap<String, iced::advanced::image::Handle>>),
     NewDisplayName(String),
-    ShowEditModal(String, String),
+    NewArtist(String),
+    NewAlbum(String),
+    NewYear(String),
+    NewGenre(String),
+    PickCoverArt,
+    CoverArtPicked(Result<PathBuf, file::FileError>),
+    ShowEditModal(db::Track),
     ShowAddModal(String),
+    ShowBookmarks(db::Track),
+    BookmarksLoaded(Result<Vec<db::Bookmark>, db::DatabaseError>),
+    NewBookmarkName(String),
+    NewBookmarkTime(String),
+    AddBookmark,
+    DeleteBookmark(db::BookmarkId),
+    BookmarksUpdated(Result<(), db::DatabaseError>),
+    JumpToBookmark(db::Track, Duration),
+    PlayNext(db::Track),
+    Enqueue(db::Track),
     PlayTrack(
-        String,
-        String,
-        u64,
+        db::Track,
         Option<iced::advanced::image::Handle>,
-        Option<Vec<HashMap<String, String>>>,
+        Option<Vec<db::Track>>,
     ),
     KeyboardEvent(IcedEvent),
 }
@@ -56,12 +89,22 @@ pub enum Event {
 impl State {
     fn new() -> Self {
         Self {
-            track_list: db::get_all_music(),
+            track_list: Vec::new(),
+            playlists: Vec::new(),
             show_edit_modal: false,
             show_add_modal: false,
+            show_bookmarks_modal: false,
             new_display_name: String::new(),
+            new_artist: String::new(),
+            new_album: String::new(),
+            new_year: String::new(),
+            new_genre: String::new(),
+            new_cover_art: None,
+            bookmarks: Vec::new(),
+            new_bookmark_name: String::new(),
+            new_bookmark_time: String::new(),
             active_video_id: None,
-            active_display_name: None,
+            active_track: None,
             thumbnails_received: false,
             thumbnails: Vec::new(),
         }
@@ -73,6 +116,16 @@ impl State {
                 self.show_add_modal = true;
                 self.active_video_id = Some(video_id);
 
+                Task::perform(db::get_all_playlists(), Event::PlaylistsLoaded)
+            }
+            Event::PlaylistsLoaded(Ok(playlists)) => {
+                self.playlists = playlists;
+
+                Task::none()
+            }
+            Event::PlaylistsLoaded(Err(e)) => {
+                log::error!("Failed to get playlists: {:?}", e);
+
                 Task::none()
             }
             Event::HidePlaylistModal => {
@@ -82,23 +135,39 @@ impl State {
                 Task::none()
             }
             Event::AddToPlaylist(video_id, playlist_id) => {
-                let _ = db::add_music_playlist(video_id, playlist_id);
-
                 self.show_add_modal = false;
 
+                Task::perform(
+                    db::add_music_playlist(video_id, playlist_id),
+                    Event::AddedToPlaylist,
+                )
+            }
+            Event::AddedToPlaylist(result) => {
+                if let Err(e) = result {
+                    log::error!("Failed to add track to playlist: {:?}", e);
+                }
+
                 Task::none()
             }
 
             Event::GetThumbnailHandles => {
                 self.thumbnails_received = false;
 
-                self.track_list = db::get_all_music();
+                Task::perform(db::get_all_music(), Event::TracksLoaded)
+            }
+            Event::TracksLoaded(Err(e)) => {
+                log::error!("Failed to get music: {:?}", e);
+
+                Task::none()
+            }
+            Event::TracksLoaded(Ok(tracks)) => {
+                self.track_list = tracks;
                 self.track_list.reverse();
 
                 let video_ids: Vec<String> = self
                     .track_list
                     .iter()
-                    .map(|track| track.get("video_id").unwrap().clone())
+                    .map(|track| track.video_id.clone())
                     .collect();
 
                 Task::perform(
@@ -114,17 +183,97 @@ impl State {
                 Task::none()
             }
 
-            Event::PlayTrack(_video_id, _display_name, _duration, _handle, _tracks) => Task::none(),
+            Event::PlayTrack(_track, _handle, _tracks) => Task::none(),
+            Event::PlayNext(_track) => Task::none(),
+            Event::Enqueue(_track) => Task::none(),
 
-            Event::ShowEditModal(video_id, display_name) => {
-                log::info!("Showing modal for track with video_id: {}", video_id);
+            Event::ShowEditModal(track) => {
+                log::info!("Showing modal for track with video_id: {}", track.video_id);
 
                 self.show_edit_modal = true;
-                self.active_video_id = Some(video_id);
-                self.active_display_name = Some(display_name.clone());
-                self.new_display_name = display_name;
+                self.active_video_id = Some(track.video_id.clone());
+                self.new_display_name = track.display_name.clone();
+                self.new_artist = track.artist.clone().unwrap_or_default();
+                self.new_album = track.album.clone().unwrap_or_default();
+                self.new_year = track.year.map(|year| year.to_string()).unwrap_or_default();
+                self.new_genre = track.genre.clone().unwrap_or_default();
+                self.new_cover_art = None;
+                self.active_track = Some(track);
                 widget::focus_next()
             }
+            // Bookmarks are opened from a track's edit menu, in place of it.
+            Event::ShowBookmarks(track) => {
+                self.show_edit_modal = false;
+                self.show_bookmarks_modal = true;
+                self.bookmarks.clear();
+                self.new_bookmark_name.clear();
+                self.new_bookmark_time.clear();
+
+                let music_id = track.music_id;
+                self.active_track = Some(track);
+
+                Task::perform(db::get_bookmarks(music_id), Event::BookmarksLoaded)
+            }
+            Event::BookmarksLoaded(Ok(bookmarks)) => {
+                self.bookmarks = bookmarks;
+
+                Task::none()
+            }
+            Event::BookmarksLoaded(Err(e)) => {
+                log::error!("Failed to get bookmarks: {:?}", e);
+
+                Task::none()
+            }
+            Event::NewBookmarkName(value) => {
+                self.new_bookmark_name = value;
+
+                Task::none()
+            }
+            Event::NewBookmarkTime(value) => {
+                if value.chars().all(|c| c.is_ascii_digit() || c == ':') {
+                    self.new_bookmark_time = value;
+                }
+
+                Task::none()
+            }
+            Event::AddBookmark => {
+                let (Some(track), Some(position)) = (
+                    self.active_track.as_ref(),
+                    parse_time(&self.new_bookmark_time),
+                ) else {
+                    return Task::none();
+                };
+
+                let Some(name) = non_empty(&self.new_bookmark_name) else {
+                    return Task::none();
+                };
+
+                self.new_bookmark_name.clear();
+
+                Task::perform(
+                    db::add_bookmark(track.music_id, name, position.min(track.duration)),
+                    Event::BookmarksUpdated,
+                )
+            }
+            Event::DeleteBookmark(bookmark_id) => {
+                Task::perform(db::delete_bookmark(bookmark_id), Event::BookmarksUpdated)
+            }
+            Event::BookmarksUpdated(Ok(_)) => match &self.active_track {
+                Some(track) => {
+                    Task::perform(db::get_bookmarks(track.music_id), Event::BookmarksLoaded)
+                }
+                None => Task::none(),
+            },
+            Event::BookmarksUpdated(Err(e)) => {
+                log::error!("Failed to update bookmarks: {:?}", e);
+
+                Task::none()
+            }
+            Event::JumpToBookmark(..) => {
+                self.hide_modals();
+
+                Task::none()
+            }
             Event::HideEditModal => {
                 log::info!("Hiding modal.");
                 self.hide_modals();
@@ -136,23 +285,72 @@ impl State {
 
                 Task::none()
             }
+            Event::NewArtist(value) => {
+                self.new_artist = value;
+
+                Task::none()
+            }
+            Event::NewAlbum(value) => {
+                self.new_album = value;
+
+                Task::none()
+            }
+            Event::NewYear(value) => {
+                if value.is_empty() || value.chars().all(|c| c.is_ascii_digit()) {
+                    self.new_year = value;
+                }
+
+                Task::none()
+            }
+            Event::NewGenre(value) => {
+                self.new_genre = value;
+
+                Task::none()
+            }
+            Event::PickCoverArt => Task::perform(file::pick_image(), Event::CoverArtPicked),
+            Event::CoverArtPicked(Ok(path)) => {
+                self.new_cover_art = Some(path);
+
+                Task::none()
+            }
+            Event::CoverArtPicked(Err(_)) => Task::none(),
             Event::Submit => {
-                let active = self.active_video_id.clone().unwrap();
-                let new_display_name = self.new_display_name.clone();
+                let Some(track) = self.active_track.clone() else {
+                    return Task::none();
+                };
+
+                let edit = tags::TagEdit {
+                    title: self.new_display_name.trim().to_string(),
+                    artist: non_empty(&self.new_artist),
+                    album: non_empty(&self.new_album),
+                    year: self.new_year.parse().ok(),
+                    genre: non_empty(&self.new_genre),
+                    cover_art: self.new_cover_art.clone(),
+                };
 
                 self.hide_modals();
 
-                let _ = db::edit_display_name(active, new_display_name);
+                Task::perform(tags::edit_track(track, edit), Event::TagsWritten)
+            }
+            Event::TagsWritten(Ok(_)) => self.update(Event::GetThumbnailHandles),
+            Event::TagsWritten(Err(e)) => {
+                log::error!("Failed to write tags: {:?}", e);
 
                 Task::none()
             }
             Event::DeleteTrack => {
                 let active = self.active_video_id.clone().unwrap();
-                db::delete_music(active).unwrap();
 
                 self.hide_modals();
 
                 self.active_video_id = None;
+
+                Task::perform(db::delete_music(active), Event::TrackUpdated)
+            }
+            Event::TrackUpdated(Ok(_)) => self.update(Event::GetThumbnailHandles),
+            Event::TrackUpdated(Err(e)) => {
+                log::error!("Failed to update track: {:?}", e);
+
                 Task::none()
             }
             Event::KeyboardEvent(event) => match event {
@@ -200,38 +398,35 @@ impl State {
             .into();
         }
 
-        for audio_file in &self.track_list {
-            let video_id = audio_file.get("video_id").unwrap();
-            let display_name = audio_file.get("display_name").unwrap();
-            let duration = audio_file.get("duration").unwrap();
-            let formatted_duration = format::duration(duration.parse::<u64>().unwrap());
+        for track in &self.track_list {
+            let video_id = &track.video_id;
+            let display_name = &track.display_name;
+            let duration = track.duration.as_secs();
+            let formatted_duration = format::duration(duration);
 
             let row: Element<Event>;
 
-            if self.thumbnails_received {
-                let thumbnail_handle = self
-                    .thumbnails
-                    .iter()
-                    .find(|thumbnail| thumbnail.contains_key(video_id))
-                    .unwrap()
-                    .get(video_id)
-                    .unwrap();
+            let thumbnail_handle = self
+                .thumbnails
+                .iter()
+                .find_map(|thumbnail| thumbnail.get(video_id));
 
+            if let (true, Some(thumbnail_handle)) = (self.thumbnails_received, thumbnail_handle) {
                 row = hover(
                     row![
                         helper::track_list_item(
                             thumbnail_handle.clone(),
-                            &display_name,
-                            &duration,
+                            display_name,
+                            duration,
                             Event::PlayTrack(
-                                video_id.clone(),
-                                display_name.clone(),
-                                duration.parse::<u64>().unwrap(),
+                                track.clone(),
                                 Some(thumbnail_handle.clone()),
                                 Some(self.track_list.clone())
                             ),
-                            Event::ShowEditModal(video_id.clone(), display_name.clone()),
+                            Event::ShowEditModal(track.clone()),
                             Event::ShowAddModal(video_id.clone()),
+                            Event::PlayNext(track.clone()),
+                            Event::Enqueue(track.clone()),
                             false,
                         ),
                         Space::with_width(30),
@@ -241,17 +436,17 @@ impl State {
                     row![
                         helper::track_list_item(
                             thumbnail_handle.clone(),
-                            &display_name,
-                            &duration,
+                            display_name,
+                            duration,
                             Event::PlayTrack(
-                                video_id.clone(),
-                                display_name.clone(),
-                                duration.parse::<u64>().unwrap(),
+                                track.clone(),
                                 Some(thumbnail_handle.clone()),
                                 Some(self.track_list.clone())
                             ),
-                            Event::ShowEditModal(video_id.clone(), display_name.clone()),
+                            Event::ShowEditModal(track.clone()),
                             Event::ShowAddModal(video_id.clone()),
+                            Event::PlayNext(track.clone()),
+                            Event::Enqueue(track.clone()),
                             true,
                         ),
                         Space::with_width(30),
@@ -265,9 +460,7 @@ impl State {
                         icons::play_icon(),
                         display_name,
                         Some(Event::PlayTrack(
-                            video_id.clone(),
-                            display_name.clone(),
-                            duration.parse::<u64>().unwrap(),
+                            track.clone(),
                             None,
                             Some(self.track_list.clone()),
                         )),
@@ -281,7 +474,17 @@ impl State {
                     helper::action(
                         icons::edit_icon(),
                         "Edit",
-                        Some(Event::ShowEditModal(video_id.clone(), display_name.clone()))
+                        Some(Event::ShowEditModal(track.clone()))
+                    ),
+                    helper::action(
+                        icons::forward_icon(),
+                        "Play next",
+                        Some(Event::PlayNext(track.clone()))
+                    ),
+                    helper::action(
+                        icons::list_icon(),
+                        "Add to queue",
+                        Some(Event::Enqueue(track.clone()))
                     ),
                     Space::with_width(30),
                 ]
@@ -305,16 +508,46 @@ impl State {
                 column![
                     text("Edit Track").size(24),
                     column![
-                        text("New Track Name:"),
+                        text("Title:"),
                         text_input("Enter here...", &self.new_display_name)
                             .on_input(Event::NewDisplayName),
+                        text("Artist:"),
+                        text_input("Unknown", &self.new_artist).on_input(Event::NewArtist),
+                        text("Album:"),
+                        text_input("Unknown", &self.new_album).on_input(Event::NewAlbum),
+                        row![
+                            column![
+                                text("Year:"),
+                                text_input("", &self.new_year).on_input(Event::NewYear),
+                            ]
+                            .spacing(10),
+                            column![
+                                text("Genre:"),
+                                text_input("", &self.new_genre).on_input(Event::NewGenre),
+                            ]
+                            .spacing(10),
+                        ]
+                        .spacing(10),
+                        row![
+                            button("Cover Art").on_press(Event::PickCoverArt),
+                            text(
+                                self.new_cover_art
+                                    .as_ref()
+                                    .and_then(|path| path.file_name())
+                                    .map(|name| name.to_string_lossy().to_string())
+                                    .unwrap_or_else(|| "Unchanged".to_string())
+                            ),
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center),
                     ]
-                    .align_x(Alignment::Center)
                     .spacing(10),
                     row![
                         button("Delete Track")
                             .style(button::danger)
                             .on_press(Event::DeleteTrack),
+                        button("Bookmarks")
+                            .on_press_maybe(self.active_track.clone().map(Event::ShowBookmarks)),
                         button("Submit")
                             .style(button::success)
                             .on_press(Event::Submit),
@@ -330,17 +563,17 @@ impl State {
 
             helper::modal(content, edit, Event::HideEditModal)
         } else if self.show_add_modal {
-            let playlists = db::get_all_playlists();
-
             let mut col = column![].spacing(10).align_x(Alignment::Center);
 
-            for playlist in playlists {
-                let id = playlist.get("id").unwrap().parse::<i32>().unwrap().clone();
-                let name = playlist.get("name").unwrap().clone();
-
-                col = col.push(button(text(name)).style(style::sidebar_button).on_press(
-                    Event::AddToPlaylist(self.active_video_id.clone().unwrap(), id),
-                ));
+            for playlist in &self.playlists {
+                col = col.push(
+                    button(text(&playlist.name))
+                        .style(style::sidebar_button)
+                        .on_press(Event::AddToPlaylist(
+                            self.active_video_id.clone().unwrap(),
+                            playlist.playlist_id,
+                        )),
+                );
             }
 
             let add = container(
@@ -358,6 +591,55 @@ impl State {
             .width(300);
 
             helper::modal(content, add, Event::HidePlaylistModal)
+        } else if let (true, Some(track)) = (self.show_bookmarks_modal, &self.active_track) {
+            let mut list = column![].spacing(10);
+
+            if self.bookmarks.is_empty() {
+                list = list.push(text("This track has no bookmarks yet."));
+            }
+
+            for bookmark in &self.bookmarks {
+                list = list.push(
+                    row![
+                        button(text(&bookmark.name))
+                            .style(style::sidebar_button)
+                            .on_press(Event::JumpToBookmark(track.clone(), bookmark.position)),
+                        horizontal_space(),
+                        text(format::duration(bookmark.position.as_secs())),
+                        button("Delete")
+                            .style(button::danger)
+                            .on_press(Event::DeleteBookmark(bookmark.bookmark_id)),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+                );
+            }
+
+            let bookmarks = container(
+                column![
+                    text("Bookmarks").size(24),
+                    text(&track.display_name),
+                    scrollable(list).height(Length::Shrink),
+                    row![
+                        text_input("Name", &self.new_bookmark_name)
+                            .on_input(Event::NewBookmarkName)
+                            .on_submit(Event::AddBookmark),
+                        text_input("0:00", &self.new_bookmark_time)
+                            .on_input(Event::NewBookmarkTime)
+                            .on_submit(Event::AddBookmark)
+                            .width(70),
+                        button("Add").on_press(Event::AddBookmark),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+                ]
+                .align_x(Alignment::Center)
+                .spacing(20),
+            )
+            .style(container::rounded_box)
+            .width(400);
+
+            helper::modal(content, bookmarks, Event::HideEditModal)
         } else {
             content.into()
         }
@@ -367,14 +649,46 @@ impl State {
         event::listen().map(Event::KeyboardEvent)
     }
 
+    // The library as listed, for playing the rest of it after a track.
+    pub fn tracks(&self) -> &[db::Track] {
+        &self.track_list
+    }
+
     fn hide_modals(&mut self) {
         self.show_edit_modal = false;
         self.show_add_modal = false;
+        self.show_bookmarks_modal = false;
 
         self.new_display_name.clear();
+        self.new_artist.clear();
+        self.new_album.clear();
+        self.new_year.clear();
+        self.new_genre.clear();
+        self.new_cover_art = None;
+        self.new_bookmark_name.clear();
+        self.new_bookmark_time.clear();
+        self.bookmarks.clear();
+        self.active_track = None;
     }
 }
 
+// Reads a time typed as seconds, `m:ss` or `h:mm:ss`.
+fn parse_time(value: &str) -> Option<Duration> {
+    let mut seconds = 0;
+
+    for part in value.trim().split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+
+    Some(Duration::from_secs(seconds))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim();
+
+    (!value.is_empty()).then(|| value.to_string())
+}
+
 impl Default for State {
     fn default() -> Self {
         Self::new()